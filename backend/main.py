@@ -95,6 +95,12 @@ def remove_vowels(text: str) -> str:
     return re.sub(r'[aeiouAEIOU]', '', text).upper()
 
 
+def serial_to_int(serial: Optional[str]) -> int:
+    """Last run of digits in a registry serial number, or 0 if there is none"""
+    digits = re.findall(r'\d+', serial or "")
+    return int(digits[-1]) if digits else 0
+
+
 def generate_project_identifier(category_id: int, connection) -> str:
     """Generate unique project identifier based on category"""
     cursor = connection.cursor(dictionary=True)
@@ -1151,9 +1157,11 @@ async def approve_tokenization_request(request: Request, approve_data: ApproveRe
                 tr.*,
                 p.project_identifier,
                 p.name as project_name,
+                r.name as registry_name,
                 u.wallet_address as issuer_wallet
             FROM tokenization_requests tr
             LEFT JOIN projects p ON tr.project_id = p.id
+            LEFT JOIN registries r ON p.registry_id = r.id
             LEFT JOIN users u ON tr.issuer_id = u.user_id
             WHERE tr.id = %s AND tr.status = 'PENDING'
         """, (approve_data.request_id,))
@@ -1179,6 +1187,12 @@ async def approve_tokenization_request(request: Request, approve_data: ApproveRe
             project_id = tokenization_request["project_id"]
             issuer_wallet = tokenization_request["issuer_wallet"]
             quantity = tokenization_request["quantity"]  # Get quantity before using it
+            # Provenance is fixed on-chain at registration: the standard as a
+            # Symbol (e.g. "Gold Standard" -> "GOLDSTANDARD") and the numeric
+            # part of the registry serials
+            standard = re.sub(r"[^A-Z0-9]", "", (tokenization_request["registry_name"] or "").upper())
+            serial_start = serial_to_int(tokenization_request.get("serial_number_start"))
+            serial_end = serial_to_int(tokenization_request.get("serial_number_end")) or serial_start
             
             print(f"Deploying contract for project {project_identifier}, vintage {vintage_year}")
             
@@ -1189,6 +1203,9 @@ async def approve_tokenization_request(request: Request, approve_data: ApproveRe
                 admin_address=admin_user["wallet_address"],  # Admin is the contract admin
                 issuer_address=issuer_wallet,  # Issuer receives the minted tokens
                 quantity=float(quantity),  # Amount to mint
+                standard=standard,
+                serial_start=serial_start,
+                serial_end=serial_end,
                 decimal=7
             )
             
@@ -5,6 +5,7 @@ Uses subprocess to call stellar CLI for reliability
 import os
 import subprocess
 import re
+import json
 from pathlib import Path
 
 
@@ -136,7 +137,11 @@ class SorobanService:
         project_id: int, 
         vintage_year: int, 
         token_address: str,
-        admin_address: str
+        admin_address: str,
+        standard: str,
+        serial_start: int = 0,
+        serial_end: int = 0,
+        retire_not_before: int = 0
     ):
         """Register an asset in the carbon controller contract using stellar CLI"""
         if not self.carbon_controller_address:
@@ -157,7 +162,13 @@ class SorobanService:
                 "--project_id", str(project_id),
                 "--vintage_year", str(vintage_year),
                 "--token", token_address,
-                "--admin", admin_address
+                "--admin", admin_address,
+                "--provenance", json.dumps({
+                    "standard": standard,
+                    "serial_start": serial_start,
+                    "serial_end": serial_end,
+                    "retire_not_before": retire_not_before,
+                })
             ]
             
             # Set environment variables
@@ -283,6 +294,9 @@ class SorobanService:
         admin_address: str,
         issuer_address: str,
         quantity: float,
+        standard: str,
+        serial_start: int = 0,
+        serial_end: int = 0,
         decimal: int = 7
     ):
         """
@@ -310,7 +324,10 @@ class SorobanService:
                     project_id=project_id,
                     vintage_year=vintage_year,
                     token_address=contract_address,
-                    admin_address=admin_address
+                    admin_address=admin_address,
+                    standard=standard,
+                    serial_start=serial_start,
+                    serial_end=serial_end
                 )
                 
                 # Step 3: Mint tokens to issuer
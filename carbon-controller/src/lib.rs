@@ -1,13 +1,49 @@
 #![no_std]
+// Contract entry points take their parameters flat, and `contractimpl`
+// generates client / args items with the same signatures.
+#![allow(clippy::too_many_arguments)]
 
 use soroban_sdk::{
-    contract, contractimpl, contracttype, contractevent, Address, Env, String, Symbol,
+    contract, contractclient, contractimpl, contracttype, contractevent, symbol_short, Address,
+    Bytes, BytesN, Env, IntoVal, Map, String, Symbol, TryFromVal, Val, Vec,
     token::{TokenClient, StellarAssetClient},
+    xdr::{ScErrorType, ToXdr},
 };
 
 #[contract]
 pub struct CarbonController;
 
+/// Supply view that some SEP-41 tokens expose on top of the standard
+/// interface (SACs don't). Only ever called through `try_` so a missing
+/// function degrades to `None`.
+#[contractclient(name = "TokenSupplyClient")]
+pub trait TokenSupply {
+    fn total_supply(env: Env) -> i128;
+}
+
+/// Interface a proof-of-retirement NFT contract must implement to be set
+/// with `set_retirement_nft`. The controller calls `mint_proof` as its own
+/// contract address, so the NFT contract should only accept mints from it.
+#[contractclient(name = "RetirementNftClient")]
+pub trait RetirementNft {
+    fn mint_proof(
+        env: Env,
+        holder: Address,
+        cert_id: u64,
+        amount: i128,
+        project_id: i64,
+        vintage_year: i32,
+    );
+}
+
+/// Interface an external verification oracle must implement to be set with
+/// `set_retirement_oracle`: whether `amount` of `asset_code` is still valid
+/// upstream (not cancelled or already claimed at the registry).
+#[contractclient(name = "RetirementOracleClient")]
+pub trait RetirementOracle {
+    fn is_valid(env: Env, asset_code: Symbol, amount: i128) -> bool;
+}
+
 #[contracttype]
 #[derive(Clone)]
 pub struct CarbonAssetMeta {
@@ -15,224 +51,7219 @@ pub struct CarbonAssetMeta {
     pub vintage_year: i32,
     pub token: Address,   // token contract id (SAC or SEP-41 token)
     pub admin: Address,   // who is allowed to mint / freeze
+    pub metadata_uri: String, // off-chain verification documents (empty if unset)
+    pub standard: Symbol,     // verification standard, e.g. "VERRA", "GOLDSTD"
+    pub serial_start: i64,    // first registry serial number covered by this asset
+    pub serial_end: i64,      // last registry serial number (inclusive)
+    pub retire_not_before: u64, // ledger timestamp before which retirement is locked (0 = none)
+    pub burnable: bool,   // token supports `burn`; otherwise retire by transfer to `retire_sink`
+    pub retire_sink: Option<Address>, // dead address for non-burnable tokens
+    pub region: Symbol,   // marketplace region used as an event topic (empty if unset)
+    pub closed: bool,     // terminal: no more listings, trades or mints
+    pub asset_class: Symbol, // routes settlement via `SettlementFor` (empty = global XML token)
+    pub conservation_bps: u32, // share of each listing sale routed to `conservation_fund`
+    pub conservation_fund: Option<Address>, // set together with a nonzero `conservation_bps`
+    pub max_holder_balance: i128, // concentration cap on any one holder's balance (0 = none)
+    pub retire_fee: i128, // flat XML charged per retirement, paid to the fee collector (0 = none)
+}
+
+/// Where an asset's credits come from and when they're verified, set when
+/// the asset is registered. Only `retire_not_before` may change later, and
+/// only to a later date.
+#[contracttype]
+#[derive(Clone)]
+pub struct Provenance {
+    pub standard: Symbol,       // verification standard, e.g. "VERRA", "GOLDSTD"
+    pub serial_start: i64,      // first registry serial number covered by the asset
+    pub serial_end: i64,        // last registry serial number (inclusive)
+    pub retire_not_before: u64, // verification date; no retirement before it (0 = none)
 }
 
 #[contracttype]
 pub enum DataKey {
     Asset(Symbol),                // asset_code, e.g. "ZORLU23"
     XmlToken,                     // global XML token contract
-    Listing(Symbol, Address),     // (asset_code, seller)
+    Listing(Symbol, Address),     // (asset_code, seller), persistent storage
+    Admin,                        // contract-wide admin (compliance, config)
+    Volume(Symbol),               // lifetime carbon units traded per asset
+    XmlVolume(Symbol),            // lifetime XML paid per asset
+    SellerStats(Address),         // listing outcomes per seller, persistent storage
+    MinPrice(Symbol),             // per-asset price floor for listings
+    MaxSingleTradeXml,            // trades above this need an admin co-sign
+    DutchAuction(Symbol, Address), // (asset_code, seller), persistent storage
+    NextAuctionId,                // id counter for English auctions
+    EnglishAuction(u64),          // auction_id, persistent storage
+    TradingDisabled(Symbol),      // secondary trading paused for this asset
+    Minted(Symbol),               // lifetime units minted through the controller
+    TotalRetired(Symbol),         // lifetime units retired (burned)
+    AssetAdmins(Symbol),          // (signer set, threshold) for privileged asset actions
+    ListingsOf(Address),          // asset codes the seller has an active listing for, persistent
+    ListingSellers(Symbol),       // sellers with an active listing for the asset, persistent
+    XmlTokenHistory,              // bounded log of (xml_token, timestamp) changes
+    Migration(Symbol),            // pending token migration for an asset
+    NextListingId(Address),       // per-seller listing id counter, persistent storage
+    MinRetireAmount(Symbol),      // per-asset floor for a single retirement
+    NextCertificateId,            // id counter for retirement certificates
+    Certificate(u64),             // certificate_id, persistent storage
+    ListedTotal(Symbol, Address), // units committed to listings + Dutch auctions, persistent
+    GlobalStats,                  // contract-wide headline counters
+    ListingCaps,                  // (max active listings, max per seller); 0 = unlimited
+    Fee,                          // global marketplace fee: (fee_bps, collector)
+    AssetFee(Symbol),             // per-asset fee_bps overriding the global one
+    CreditFallback,               // book undeliverable seller proceeds as credits
+    Credit(Address, Address),     // (who, token) -> proceeds held for withdrawal, persistent
+    PriceScale,                   // decimal places of listing prices beyond XML base units
+    SettlementFor(Symbol),        // asset_class -> settlement token for listings / Dutch auctions
+    UsedReference(String),        // registry reference already retired, persistent storage
+    Issuer(Symbol),               // primary issuer of an asset (first mint recipient by default)
+    PrimaryFeeBps,                // fee_bps for primary sales, overriding the per-asset / global fee
+    Paused,                       // global trading halt: resume_at timestamp (0 = until unpause)
+    Custodied(Symbol),            // units the controller holds in custody for an asset, all sellers
+    CustodyBalance(Symbol, Address), // units held for one seller (listed or not), persistent
+    RetireQuorum,                 // (threshold, compliance_signer) for large retirements
+    RetirePurposes,               // accepted retirement purpose codes
+    AdminHistory(Symbol),         // bounded log of (asset admin, timestamp) changes
+    MaxListingDuration,           // seconds a listing stays live after its last write (0 = forever)
+    Alias(Symbol),                // deprecated asset code -> canonical code
+    PriceHistory(Symbol, Address), // bounded log of (price, timestamp) per listing, persistent
+    EscrowedXml,                  // XML held for open English-auction bids
+    EscrowedCarbon(Symbol),       // carbon held as lots of open English auctions
+}
+
+/// Storage keys added after `DataKey` reached the 50-variant limit of a
+/// `#[contracttype]` enum. Same conventions: instance storage unless noted.
+#[contracttype]
+pub enum ExtKey {
+    PendingAdmin,            // proposed contract admin awaiting `accept_admin`
+    EventConfig,             // bitflags of enabled optional events (`EVENT_*`); unset = all on
+    Basket(Symbol),          // basket_code -> components as (asset_code, weight_bps)
+    CertTransferLock,        // seconds after issue before a certificate may be transferred
+    CertSlot(u64),           // certificate id -> slot in its owner's index, persistent
+    RequireMatchingDecimals, // registration rejects tokens whose decimals differ from XML's
+    Frozen(Symbol),          // compliance freeze: blocks minting, trading and retirement
+    NextMintId,              // id counter for attested mints
+    MintAttestation(u64),    // mint_id -> `MintAttestation`, persistent
+    UsedAttestation(String), // attestation_ref already minted against -> mint_id, persistent
+    MaxBpsSum,               // cap on an asset's worst-case fee + conservation bps
+    Bid(Symbol, Address),    // (asset_code, buyer) -> resting `LimitBid`, persistent
+    BidBuyers(Symbol),       // buyers with a resting limit bid for the asset, oldest first
+    BidsOf(Address),         // asset codes the buyer has a resting limit bid for, persistent
+    RetirementNftContract,   // proof-of-retirement NFT minted on every retirement, if set
+    MinUpdateInterval,       // seconds a seller must wait between price changes (0 = off)
+    DailyStats(Symbol, u64), // (asset_code, UTC day) -> `DailyStats`, persistent
+    DailySummaryEmitted(Symbol, u64), // `DailySummaryEvent` already published, persistent
+    UnitLabel(Symbol),       // what one whole token stands for, e.g. "TCO2E" (the default)
+    WaiveBuybackFees,        // `buyback_and_retire` skips the marketplace fee
+    AutoRetire(Address),     // opted-in account -> retirement note for credits it buys, persistent
+    CustodyLots(Symbol, Address), // (asset, seller) -> (amount, deposit_ledger) lots, persistent
+    NextTradeId,             // id counter for listing purchases (`PurchaseEvent::trade_id`)
+    RetirementOracle,        // oracle every retirement must pass (`RetirementOracle`), if set
+    FrozenHolder(Address),   // compliance freeze on one account: no listing, buying or retiring
+    TotalRetiredBy(Address), // lifetime units retired by a holder, all assets, persistent
+    FeeSplits,               // marketplace fee shares as (collector, share_bps), summing to 10000
+    TotalListed(Symbol),     // units offered across all open listings of an asset
+    Role(Symbol, Address),   // (role, holder) -> expiry timestamp, 0 = never, persistent
+    TradeLog,                // last `MAX_TRADE_LOG_LEN` purchases, oldest first, persistent
+    MintLockup(Symbol),      // seconds after a mint before its recipient may list the asset
+    MintedAt(Symbol, Address), // (asset, holder) -> timestamp of the latest mint, persistent
+    IndexLen(Index),         // entries in a paged index, persistent
+    IndexPage(Index, u32),   // (index, page) -> up to `INDEX_PAGE_LEN` entries, persistent
+}
+
+/// Open-ended lists kept as fixed-size persistent pages (see `index_push`),
+/// so no single ledger entry grows with them.
+#[contracttype]
+#[derive(Clone)]
+pub enum Index {
+    CertsByVintage(i64, i32), // certificate ids per (project_id, vintage_year)
+    CertsByDay(u64),          // certificate ids per UTC day (timestamp / 86400)
+    OwnedCerts(Address),      // certificate ids currently owned
+    ProjectAssets(i64),       // asset codes (vintages) registered under a project
+    AssetCodes,               // every registered asset code, in registration order
+}
+
+/// Largest `decimals()` a registered token may report. Anything above this
+/// can't be priced without overflowing the i128 scaling math.
+const MAX_TOKEN_DECIMALS: u32 = 18;
+
+/// Longest accepted `metadata_uri`.
+const MAX_URI_LEN: u32 = 256;
+
+/// Most distinct bidders an English auction accepts, so settlement refunds
+/// stay within the transaction budget.
+const MAX_BIDDERS: u32 = 20;
+
+/// Most resting limit bids an asset's book holds, so a new listing can scan
+/// every bid within the transaction budget.
+const MAX_LIMIT_BIDS: u32 = 50;
+
+/// Most bid / listing pairs one `match_orders` run settles, so the
+/// triggering call stays within the transaction budget.
+const MAX_MATCHES_PER_CALL: u32 = 10;
+
+/// Largest per-asset admin signer set.
+const MAX_ASSET_ADMINS: u32 = 10;
+
+/// Entries kept in bounded audit logs; the oldest entry is dropped first.
+const MAX_HISTORY_LEN: u32 = 20;
+
+/// Open custody lots tracked per (asset, seller); see `adjust_custody_lots`.
+const MAX_CUSTODY_LOTS: u32 = 20;
+
+/// Purchases kept in the global `TradeLog` for indexers to backfill from.
+const MAX_TRADE_LOG_LEN: u32 = 100;
+
+/// Upper bound on the number of keys a single batch read may resolve.
+const MAX_BATCH_SIZE: u32 = 50;
+
+/// Entries per page of a paged `Index`.
+const INDEX_PAGE_LEN: u32 = 100;
+
+/// Largest accepted `price_scale`; keeps `10^price_scale` well inside i128.
+const MAX_PRICE_SCALE: u32 = 18;
+
+/// Highest marketplace fee the admin may configure (10%).
+const MAX_FEE_BPS: u32 = 1_000;
+
+/// Highest conservation share an asset may configure (10%), so fee plus
+/// conservation never take more than 20% of a sale.
+const MAX_CONSERVATION_BPS: u32 = 1_000;
+
+/// Default cap on everything taken out of one sale in basis points (fee
+/// plus conservation), until the admin sets another with `set_max_bps_sum`.
+const DEFAULT_MAX_BPS_SUM: u32 = 3_000;
+
+/// Optional events the admin can switch off with `set_event_config` to save
+/// ledger writes. Retirement, purchase, mint and governance events are always
+/// emitted.
+const EVENT_LISTING_CLOSED: u32 = 1 << 0;
+const EVENT_LISTING_CONSUMED: u32 = 1 << 1;
+const EVENT_BATCH_LISTING: u32 = 1 << 2;
+const EVENT_LISTING_FLAGGED: u32 = 1 << 3;
+const EVENT_BID_PLACED: u32 = 1 << 4;
+const ALL_OPTIONAL_EVENTS: u32 = EVENT_LISTING_CLOSED
+    | EVENT_LISTING_CONSUMED
+    | EVENT_BATCH_LISTING
+    | EVENT_LISTING_FLAGGED
+    | EVENT_BID_PLACED;
+
+/// Most assets a single basket may combine; each one is routed separately.
+const MAX_BASKET_COMPONENTS: u32 = 10;
+
+/// Most extra settlement tokens one listing may accept.
+const MAX_ACCEPTED_TOKENS: u32 = 5;
+
+/// Most collectors the marketplace fee can be split between.
+const MAX_FEE_COLLECTORS: u32 = 10;
+
+/// Roles the contract admin can hand out with `grant_role`. The admin
+/// itself implicitly holds all of them. `ADMIN` holders may grant and
+/// revoke the others but can't replace the contract admin.
+const ROLE_ADMIN: &str = "ADMIN";
+const ROLE_COMPLIANCE: &str = "COMPLIANCE";
+const ROLE_FEE_MANAGER: &str = "FEE_MANAGER";
+const ROLE_PAUSER: &str = "PAUSER";
+const KNOWN_ROLES: [&str; 4] = [ROLE_ADMIN, ROLE_COMPLIANCE, ROLE_FEE_MANAGER, ROLE_PAUSER];
+
+/// Width of an `Index::CertsByDay` bucket.
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Basis points per whole.
+const BPS_DENOMINATOR: i128 = 10_000;
+
+/// Longest accepted free-text string (retirement notes, close reasons)
+/// unless a field has its own limit below or `MAX_URI_LEN`.
+const MAX_STRING_LEN: u32 = 256;
+
+/// Longest accepted certificate beneficiary name.
+const MAX_BENEFICIARY_NAME_LEN: u32 = 128;
+
+/// Longest accepted external registry reference on a retirement.
+const MAX_REFERENCE_LEN: u32 = 64;
+
+/// Accepted vintage years for `register_vintages`: from the Kyoto baseline
+/// year to far enough ahead for forward-issued (ex-ante) credits.
+const MIN_VINTAGE_YEAR: i32 = 1990;
+const MAX_VINTAGE_YEAR: i32 = 2100;
+
+/// Interface revision reported by `schema_version`. Bump it whenever a
+/// public function, event or returned struct changes shape.
+const SCHEMA_VERSION: u32 = 1;
+
+/// Every user-supplied `String` goes through here before it is stored or
+/// published, so none can bloat storage rent or event size.
+fn require_string_len(s: &String, max: u32, field: &str) {
+    if s.len() > max {
+        panic!("{} too long: {} bytes, max {}", field, s.len(), max);
+    }
 }
 
+/// Reads follow one level of `alias_asset` redirect when the code itself
+/// has no entry.
 fn read_asset(e: &Env, code: Symbol) -> CarbonAssetMeta {
-    let key = DataKey::Asset(code);
+    let key = DataKey::Asset(code.clone());
+    if let Some(meta) = e.storage().instance().get::<DataKey, CarbonAssetMeta>(&key) {
+        return meta;
+    }
+    read_alias(e, &code)
+        .and_then(|canonical| e.storage().instance().get(&DataKey::Asset(canonical)))
+        .unwrap_or_else(|| panic!("asset not registered"))
+}
+
+fn read_alias(e: &Env, code: &Symbol) -> Option<Symbol> {
+    e.storage().instance().get(&DataKey::Alias(code.clone()))
+}
+
+fn write_asset(e: &Env, code: Symbol, meta: &CarbonAssetMeta) {
+    e.storage().instance().set(&DataKey::Asset(code), meta);
+}
+
+fn read_admin(e: &Env) -> Address {
     e.storage()
         .instance()
-        .get::<DataKey, CarbonAssetMeta>(&key)
-        .unwrap_or_else(|| panic!("asset not registered"))
+        .get::<DataKey, Address>(&DataKey::Admin)
+        .unwrap_or_else(|| panic!("admin not set"))
 }
 
-/// Carbon credit retirement event, indexed off-chain.
-#[contractevent]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct CarbonRetireEvent {
-    #[topic]
-    pub asset_code: Symbol,
-    #[topic]
-    pub holder: Address,
-    pub amount: i128,
-    pub project_id: i64,
-    pub vintage_year: i32,
-    pub note: String,
+/// Expiry stored for a role grant, or `None` if `who` was never granted
+/// `role` (or it was revoked). `Some(0)` is a grant without expiry.
+fn read_role_expiry(e: &Env, role: &Symbol, who: &Address) -> Option<u64> {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::Role(role.clone(), who.clone()))
+}
+
+fn holds_role(e: &Env, role: &Symbol, who: &Address) -> bool {
+    if *who == read_admin(e) {
+        return true;
+    }
+    match read_role_expiry(e, role, who) {
+        Some(0) => true,
+        Some(expires_at) => e.ledger().timestamp() <= expires_at,
+        None => false,
+    }
+}
+
+/// `caller` must sign and be the contract admin or a holder of `role`.
+fn require_role(e: &Env, caller: &Address, role: &str) {
+    caller.require_auth();
+    if !holds_role(e, &Symbol::new(e, role), caller) {
+        panic!("missing role");
+    }
+}
+
+fn require_known_role(e: &Env, role: &Symbol) {
+    if !KNOWN_ROLES.iter().any(|r| Symbol::new(e, r) == *role) {
+        panic!("unknown role");
+    }
+}
+
+fn read_listing(e: &Env, asset_code: Symbol, seller: Address) -> Listing {
+    e.storage()
+        .persistent()
+        .get::<DataKey, Listing>(&DataKey::Listing(asset_code, seller))
+        .unwrap_or_else(|| panic!("listing not found"))
+}
+
+fn read_unit_label(e: &Env, asset_code: &Symbol) -> Symbol {
+    e.storage()
+        .instance()
+        .get(&ExtKey::UnitLabel(asset_code.clone()))
+        .unwrap_or(symbol_short!("TCO2E"))
+}
+
+fn read_min_price(e: &Env, asset_code: Symbol) -> i128 {
+    e.storage()
+        .instance()
+        .get::<DataKey, i128>(&DataKey::MinPrice(asset_code))
+        .unwrap_or(1)
+}
+
+fn read_min_retire_amount(e: &Env, asset_code: Symbol) -> i128 {
+    e.storage()
+        .instance()
+        .get::<DataKey, i128>(&DataKey::MinRetireAmount(asset_code))
+        .unwrap_or(1)
+}
+
+/// Shared by every retirement entrypoint.
+fn check_retire_amount(e: &Env, asset_code: Symbol, amount: i128) {
+    let min = read_min_retire_amount(e, asset_code);
+    if amount < min {
+        panic!("retirement amount {} below minimum {}", amount, min);
+    }
+}
+
+fn read_max_single_trade_xml(e: &Env) -> i128 {
+    e.storage()
+        .instance()
+        .get(&DataKey::MaxSingleTradeXml)
+        .unwrap_or(0)
+}
+
+/// Roughly one day of ledgers at ~5 seconds each.
+const DAY_IN_LEDGERS: u32 = 17_280;
+
+/// Per-account and per-listing persistent entries are extended to live this
+/// many more ledgers whenever they're written, at most once a day.
+const PERSISTENT_TTL: u32 = 30 * DAY_IN_LEDGERS;
+const PERSISTENT_TTL_THRESHOLD: u32 = PERSISTENT_TTL - DAY_IN_LEDGERS;
+
+/// Write a per-account / per-listing persistent entry and keep it alive.
+fn write_persistent<K, V>(e: &Env, key: &K, value: &V)
+where
+    K: IntoVal<Env, Val>,
+    V: IntoVal<Env, Val>,
+{
+    let storage = e.storage().persistent();
+    storage.set(key, value);
+    storage.extend_ttl(key, PERSISTENT_TTL_THRESHOLD, PERSISTENT_TTL);
+}
+
+/// Persistent counterpart of `read_counter` for per-account balances.
+fn read_persistent_counter(e: &Env, key: &DataKey) -> i128 {
+    e.storage().persistent().get(key).unwrap_or(0)
+}
+
+/// Store a per-account balance, dropping the entry once it reaches zero.
+fn write_persistent_counter(e: &Env, key: &DataKey, value: i128) {
+    if value == 0 {
+        e.storage().persistent().remove(key);
+    } else {
+        write_persistent(e, key, &value);
+    }
+}
+
+fn read_counter(e: &Env, key: &DataKey) -> i128 {
+    e.storage()
+        .instance()
+        .get::<DataKey, i128>(key)
+        .unwrap_or(0)
+}
+
+fn add_to_counter(e: &Env, key: &DataKey, delta: i128) {
+    let total = read_counter(e, key)
+        .checked_add(delta)
+        .expect("counter overflow");
+    e.storage().instance().set(key, &total);
+}
+
+/// Units minted through the controller and not yet retired.
+fn outstanding_supply(e: &Env, asset_code: &Symbol) -> i128 {
+    read_counter(e, &DataKey::Minted(asset_code.clone()))
+        - read_counter(e, &DataKey::TotalRetired(asset_code.clone()))
+}
+
+fn read_seller_stats(e: &Env, seller: &Address) -> SellerStats {
+    e.storage()
+        .persistent()
+        .get::<DataKey, SellerStats>(&DataKey::SellerStats(seller.clone()))
+        .unwrap_or_default()
+}
+
+fn update_seller_stats(e: &Env, seller: &Address, f: impl FnOnce(&mut SellerStats)) {
+    let mut stats = read_seller_stats(e, seller);
+    f(&mut stats);
+    write_persistent(e, &DataKey::SellerStats(seller.clone()), &stats);
+}
+
+fn read_listings_of(e: &Env, seller: &Address) -> Vec<Symbol> {
+    e.storage()
+        .persistent()
+        .get(&DataKey::ListingsOf(seller.clone()))
+        .unwrap_or_else(|| Vec::new(e))
+}
+
+fn write_listings_of(e: &Env, seller: &Address, codes: &Vec<Symbol>) {
+    let key = DataKey::ListingsOf(seller.clone());
+    if codes.is_empty() {
+        e.storage().persistent().remove(&key);
+    } else {
+        write_persistent(e, &key, codes);
+    }
+}
+
+fn read_listing_sellers(e: &Env, asset_code: &Symbol) -> Vec<Address> {
+    e.storage()
+        .persistent()
+        .get(&DataKey::ListingSellers(asset_code.clone()))
+        .unwrap_or_else(|| Vec::new(e))
+}
+
+fn write_listing_sellers(e: &Env, asset_code: &Symbol, sellers: &Vec<Address>) {
+    let key = DataKey::ListingSellers(asset_code.clone());
+    if sellers.is_empty() {
+        e.storage().persistent().remove(&key);
+    } else {
+        write_persistent(e, &key, sellers);
+    }
+}
+
+fn read_listed_total(e: &Env, asset_code: &Symbol, seller: &Address) -> i128 {
+    read_persistent_counter(e, &DataKey::ListedTotal(asset_code.clone(), seller.clone()))
+}
+
+fn adjust_listed_total(e: &Env, asset_code: &Symbol, seller: &Address, delta: i128) {
+    let key = DataKey::ListedTotal(asset_code.clone(), seller.clone());
+    let total = read_persistent_counter(e, &key)
+        .checked_add(delta)
+        .unwrap_or_else(|| panic!("overflow in listed total"));
+    write_persistent_counter(e, &key, total);
+}
+
+/// Wallet listings count against the seller's `ListedTotal`. Custodial
+/// ones are already backed by the seller's `CustodyBalance`.
+fn adjust_committed(e: &Env, listing: &Listing, delta: i128) {
+    if !listing.custodial {
+        adjust_listed_total(e, &listing.asset_code, &listing.seller, delta);
+    }
+}
+
+fn read_custody_balance(e: &Env, asset_code: &Symbol, seller: &Address) -> i128 {
+    read_persistent_counter(
+        e,
+        &DataKey::CustodyBalance(asset_code.clone(), seller.clone()),
+    )
+}
+
+/// Move a seller's custody balance and the asset's `Custodied` total together.
+fn adjust_custody(e: &Env, asset_code: &Symbol, seller: &Address, delta: i128) {
+    let key = DataKey::CustodyBalance(asset_code.clone(), seller.clone());
+    let balance = read_persistent_counter(e, &key)
+        .checked_add(delta)
+        .unwrap_or_else(|| panic!("overflow in custody balance"));
+    if balance < 0 {
+        panic!("custody balance underflow");
+    }
+    write_persistent_counter(e, &key, balance);
+    add_to_counter(e, &DataKey::Custodied(asset_code.clone()), delta);
+    adjust_custody_lots(e, asset_code, seller, delta);
+}
+
+fn read_custody_lots(e: &Env, asset_code: &Symbol, seller: &Address) -> Vec<(i128, u64)> {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::CustodyLots(asset_code.clone(), seller.clone()))
+        .unwrap_or_else(|| Vec::new(e))
+}
+
+/// Lot accounting behind a custody balance: deposits open a lot tagged
+/// with the ledger they arrived in, and sales and withdrawals consume the
+/// oldest lots first (FIFO), so vintage / tax-lot reporting can tell which
+/// deposit each unit came from. Deposits in the same ledger share a lot,
+/// and once `MAX_CUSTODY_LOTS` are open further deposits join the newest.
+fn adjust_custody_lots(e: &Env, asset_code: &Symbol, seller: &Address, delta: i128) {
+    let key = ExtKey::CustodyLots(asset_code.clone(), seller.clone());
+    let mut lots = read_custody_lots(e, asset_code, seller);
+    if delta > 0 {
+        let ledger = e.ledger().sequence() as u64;
+        match lots.last() {
+            Some((amount, deposited))
+                if deposited == ledger || lots.len() >= MAX_CUSTODY_LOTS =>
+            {
+                lots.set(lots.len() - 1, (amount + delta, deposited));
+            }
+            _ => lots.push_back((delta, ledger)),
+        }
+    } else {
+        let mut remaining = -delta;
+        while remaining > 0 {
+            let (amount, deposited) = lots
+                .pop_front()
+                .unwrap_or_else(|| panic!("custody lots underflow"));
+            if amount > remaining {
+                lots.push_front((amount - remaining, deposited));
+            }
+            remaining -= amount.min(remaining);
+        }
+    }
+    if lots.is_empty() {
+        e.storage().persistent().remove(&key);
+    } else {
+        write_persistent(e, &key, &lots);
+    }
+}
+
+/// Listings and Dutch auctions settle from the seller's wallet, so together
+/// they may never promise more than the seller holds. `released` is the
+/// amount of the entry being replaced, `added` its new amount.
+fn require_uncommitted_balance(
+    e: &Env,
+    asset_code: &Symbol,
+    seller: &Address,
+    released: i128,
+    added: i128,
+) {
+    let meta = read_asset(e, asset_code.clone());
+    let committed = read_listed_total(e, asset_code, seller) - released + added;
+    if committed > TokenClient::new(e, &meta.token).balance(seller) {
+        panic!("listed total exceeds seller balance");
+    }
+}
+
+/// `limit` items starting at `start`; `limit` is capped at `MAX_BATCH_SIZE`.
+fn page<T>(items: &Vec<T>, start: u32, limit: u32) -> Vec<T> {
+    let from = start.min(items.len());
+    let to = from
+        .saturating_add(limit.min(MAX_BATCH_SIZE))
+        .min(items.len());
+    items.slice(from..to)
+}
+
+fn index_len(e: &Env, index: &Index) -> u32 {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::IndexLen(index.clone()))
+        .unwrap_or(0)
+}
+
+fn read_index_page<T>(e: &Env, index: &Index, page: u32) -> Vec<T>
+where
+    T: IntoVal<Env, Val> + TryFromVal<Env, Val>,
+{
+    e.storage()
+        .persistent()
+        .get(&ExtKey::IndexPage(index.clone(), page))
+        .unwrap_or_else(|| Vec::new(e))
+}
+
+/// Number of pages an index spans, for callers that walk all of it.
+fn index_pages(e: &Env, index: &Index) -> u32 {
+    index_len(e, index).div_ceil(INDEX_PAGE_LEN)
+}
+
+/// Position of `item` in a paged index, if present. Scans page by page, so
+/// only for indexes that stay small or for rare admin paths.
+fn index_position<T>(e: &Env, index: &Index, item: &T) -> Option<u32>
+where
+    T: PartialEq + IntoVal<Env, Val> + TryFromVal<Env, Val>,
+{
+    for page_no in 0..index_pages(e, index) {
+        let page: Vec<T> = read_index_page(e, index, page_no);
+        if let Some(i) = page.first_index_of(item) {
+            return Some(page_no * INDEX_PAGE_LEN + i);
+        }
+    }
+    None
+}
+
+/// Append `item` to a paged index, starting a new page once the last one
+/// holds `INDEX_PAGE_LEN` entries. Returns the item's position.
+fn index_push<T>(e: &Env, index: &Index, item: T) -> u32
+where
+    T: IntoVal<Env, Val> + TryFromVal<Env, Val>,
+{
+    let len = index_len(e, index);
+    let page_no = len / INDEX_PAGE_LEN;
+    let mut page: Vec<T> = read_index_page(e, index, page_no);
+    page.push_back(item);
+    write_persistent(e, &ExtKey::IndexPage(index.clone(), page_no), &page);
+    write_persistent(e, &ExtKey::IndexLen(index.clone()), &(len + 1));
+    len
+}
+
+/// Entries `start..start + limit` of a paged index (`limit` capped at
+/// `MAX_BATCH_SIZE`), reading only the pages that range touches.
+fn index_range<T>(e: &Env, index: &Index, start: u32, limit: u32) -> Vec<T>
+where
+    T: IntoVal<Env, Val> + TryFromVal<Env, Val>,
+{
+    let end = start
+        .saturating_add(limit.min(MAX_BATCH_SIZE))
+        .min(index_len(e, index));
+    let mut items = Vec::new(e);
+    let mut pos = start;
+    while pos < end {
+        let page_no = pos / INDEX_PAGE_LEN;
+        let page_start = page_no * INDEX_PAGE_LEN;
+        let page_end = (end - page_start).min(INDEX_PAGE_LEN);
+        let page: Vec<T> = read_index_page(e, index, page_no);
+        items.append(&page.slice(pos - page_start..page_end));
+        pos = page_start + page_end;
+    }
+    items
+}
+
+fn write_index_page<T>(e: &Env, index: &Index, page: u32, items: &Vec<T>)
+where
+    T: IntoVal<Env, Val> + TryFromVal<Env, Val>,
+{
+    let key = ExtKey::IndexPage(index.clone(), page);
+    if items.is_empty() {
+        e.storage().persistent().remove(&key);
+    } else {
+        write_persistent(e, &key, items);
+    }
+}
+
+/// Remove the entry at `pos` of a paged index by moving the last entry
+/// into its place, so order isn't kept. Returns the moved entry, if any,
+/// for callers that track positions.
+fn index_swap_remove<T>(e: &Env, index: &Index, pos: u32) -> Option<T>
+where
+    T: Clone + IntoVal<Env, Val> + TryFromVal<Env, Val>,
+{
+    let last_pos = index_len(e, index) - 1;
+    let last_page_no = last_pos / INDEX_PAGE_LEN;
+    let mut last_page: Vec<T> = read_index_page(e, index, last_page_no);
+    let last = last_page.pop_back().unwrap();
+
+    let moved = if pos == last_pos {
+        None
+    } else if pos / INDEX_PAGE_LEN == last_page_no {
+        last_page.set(pos % INDEX_PAGE_LEN, last.clone());
+        Some(last)
+    } else {
+        let page_no = pos / INDEX_PAGE_LEN;
+        let mut page: Vec<T> = read_index_page(e, index, page_no);
+        page.set(pos % INDEX_PAGE_LEN, last.clone());
+        write_index_page(e, index, page_no, &page);
+        Some(last)
+    };
+    write_index_page(e, index, last_page_no, &last_page);
+
+    let len_key = ExtKey::IndexLen(index.clone());
+    if last_pos == 0 {
+        e.storage().persistent().remove(&len_key);
+    } else {
+        write_persistent(e, &len_key, &last_pos);
+    }
+    moved
+}
+
+/// Store a listing, adding it to the seller and asset indexes the first time
+/// it appears. All listing writes go through here and `remove_listing` so
+/// the indexes can't drift from the actual entries.
+fn store_listing(e: &Env, listing: &Listing) {
+    let key = DataKey::Listing(listing.asset_code.clone(), listing.seller.clone());
+    let previous = e.storage().persistent().get::<DataKey, Listing>(&key);
+    let delta = listing.amount - previous.as_ref().map_or(0, |l| l.amount);
+    adjust_committed(e, listing, delta);
+    adjust_total_listed(e, &listing.asset_code, delta);
+    if previous.is_none() {
+        update_global_stats(e, |s| s.active_listings += 1);
+        let mut codes = read_listings_of(e, &listing.seller);
+        codes.push_back(listing.asset_code.clone());
+        write_listings_of(e, &listing.seller, &codes);
+
+        let mut sellers = read_listing_sellers(e, &listing.asset_code);
+        sellers.push_back(listing.seller.clone());
+        write_listing_sellers(e, &listing.asset_code, &sellers);
+    }
+    write_persistent(e, &key, listing);
+}
+
+fn read_total_listed(e: &Env, asset_code: &Symbol) -> i128 {
+    e.storage()
+        .instance()
+        .get(&ExtKey::TotalListed(asset_code.clone()))
+        .unwrap_or(0)
+}
+
+/// Every listing write goes through `store_listing` / `remove_listing`,
+/// so creates, updates, partial fills, sell-outs and cancels all land here.
+fn adjust_total_listed(e: &Env, asset_code: &Symbol, delta: i128) {
+    let total = read_total_listed(e, asset_code)
+        .checked_add(delta)
+        .unwrap_or_else(|| panic!("overflow in listed total"));
+    e.storage()
+        .instance()
+        .set(&ExtKey::TotalListed(asset_code.clone()), &total);
+}
+
+/// Remove a listing and drop it from the seller and asset indexes.
+fn remove_listing(e: &Env, asset_code: &Symbol, seller: &Address) {
+    let key = DataKey::Listing(asset_code.clone(), seller.clone());
+    if let Some(listing) = e.storage().persistent().get::<DataKey, Listing>(&key) {
+        adjust_committed(e, &listing, -listing.amount);
+        adjust_total_listed(e, asset_code, -listing.amount);
+        update_global_stats(e, |s| s.active_listings -= 1);
+    }
+    e.storage().persistent().remove(&key);
+
+    let mut codes = read_listings_of(e, seller);
+    if let Some(i) = codes.first_index_of(asset_code) {
+        codes.remove(i);
+    }
+    write_listings_of(e, seller, &codes);
+
+    let mut sellers = read_listing_sellers(e, asset_code);
+    if let Some(i) = sellers.first_index_of(seller) {
+        sellers.remove(i);
+    }
+    write_listing_sellers(e, asset_code, &sellers);
+}
+
+fn read_bid_buyers(e: &Env, asset_code: &Symbol) -> Vec<Address> {
+    e.storage()
+        .instance()
+        .get(&ExtKey::BidBuyers(asset_code.clone()))
+        .unwrap_or_else(|| Vec::new(e))
+}
+
+fn write_bid_buyers(e: &Env, asset_code: &Symbol, buyers: &Vec<Address>) {
+    let key = ExtKey::BidBuyers(asset_code.clone());
+    if buyers.is_empty() {
+        e.storage().instance().remove(&key);
+    } else {
+        e.storage().instance().set(&key, buyers);
+    }
+}
+
+fn read_bids_of(e: &Env, buyer: &Address) -> Vec<Symbol> {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::BidsOf(buyer.clone()))
+        .unwrap_or_else(|| Vec::new(e))
+}
+
+fn write_bids_of(e: &Env, buyer: &Address, codes: &Vec<Symbol>) {
+    let key = ExtKey::BidsOf(buyer.clone());
+    if codes.is_empty() {
+        e.storage().persistent().remove(&key);
+    } else {
+        write_persistent(e, &key, codes);
+    }
+}
+
+fn read_limit_bid(e: &Env, asset_code: &Symbol, buyer: &Address) -> LimitBid {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::Bid(asset_code.clone(), buyer.clone()))
+        .unwrap_or_else(|| panic!("limit bid not found"))
 }
 
-/// Simple listing: seller offers `amount` units of `asset_code` at `price` XML per unit.
-/// All values are i128 with 7 decimals (same as tokens).
-#[contracttype]
-#[derive(Clone)]
-pub struct Listing {
-    pub asset_code: Symbol,
-    pub seller: Address,
-    pub amount: i128,
-    pub price: i128, // price per 1 unit in XML (scaled by 10^7)
-}
+/// Write a limit bid, adding it to the asset's bid book and the buyer's
+/// index if new.
+fn store_limit_bid(e: &Env, bid: &LimitBid) {
+    let key = ExtKey::Bid(bid.asset_code.clone(), bid.buyer.clone());
+    if !e.storage().persistent().has(&key) {
+        let mut buyers = read_bid_buyers(e, &bid.asset_code);
+        if buyers.len() >= MAX_LIMIT_BIDS {
+            panic!("limit bid book full");
+        }
+        buyers.push_back(bid.buyer.clone());
+        write_bid_buyers(e, &bid.asset_code, &buyers);
+
+        let mut codes = read_bids_of(e, &bid.buyer);
+        codes.push_back(bid.asset_code.clone());
+        write_bids_of(e, &bid.buyer, &codes);
+    }
+    write_persistent(e, &key, bid);
+}
+
+fn remove_limit_bid(e: &Env, asset_code: &Symbol, buyer: &Address) {
+    e.storage()
+        .persistent()
+        .remove(&ExtKey::Bid(asset_code.clone(), buyer.clone()));
+
+    let mut buyers = read_bid_buyers(e, asset_code);
+    if let Some(i) = buyers.first_index_of(buyer) {
+        buyers.remove(i);
+    }
+    write_bid_buyers(e, asset_code, &buyers);
+
+    let mut codes = read_bids_of(e, buyer);
+    if let Some(i) = codes.first_index_of(asset_code) {
+        codes.remove(i);
+    }
+    write_bids_of(e, buyer, &codes);
+}
+
+fn is_limit_bid_expired(e: &Env, bid: &LimitBid) -> bool {
+    bid.expires_at != 0 && e.ledger().timestamp() >= bid.expires_at
+}
+
+/// Remove a limit bid the buyer didn't cancel themselves (e.g. it expired)
+/// and say why. Bids escrow nothing, so there is nothing to refund.
+fn close_limit_bid(e: &Env, asset_code: &Symbol, buyer: &Address, reason: &str) {
+    remove_limit_bid(e, asset_code, buyer);
+
+    BidClosedEvent {
+        asset_code: asset_code.clone(),
+        buyer: buyer.clone(),
+        reason: String::from_str(e, reason),
+    }
+    .publish(e);
+}
+
+fn read_global_stats(e: &Env) -> GlobalStats {
+    e.storage()
+        .instance()
+        .get::<DataKey, GlobalStats>(&DataKey::GlobalStats)
+        .unwrap_or_default()
+}
+
+fn update_global_stats(e: &Env, f: impl FnOnce(&mut GlobalStats)) {
+    let mut stats = read_global_stats(e);
+    f(&mut stats);
+    e.storage().instance().set(&DataKey::GlobalStats, &stats);
+}
+
+fn read_daily_stats(e: &Env, asset_code: &Symbol, day: u64) -> DailyStats {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::DailyStats(asset_code.clone(), day))
+        .unwrap_or_default()
+}
+
+/// Bucket activity under today's UTC day (ledger timestamp / 86400).
+fn update_daily_stats(e: &Env, asset_code: &Symbol, f: impl FnOnce(&mut DailyStats)) {
+    let day = e.ledger().timestamp() / SECONDS_PER_DAY;
+    let mut stats = read_daily_stats(e, asset_code, day);
+    f(&mut stats);
+    write_persistent(e, &ExtKey::DailyStats(asset_code.clone(), day), &stats);
+}
+
+/// Bump the per-asset and contract-wide volume counters for a settled trade.
+fn record_trade(e: &Env, asset_code: &Symbol, amount: i128, cost_xml: i128) {
+    add_to_counter(e, &DataKey::Volume(asset_code.clone()), amount);
+    add_to_counter(e, &DataKey::XmlVolume(asset_code.clone()), cost_xml);
+    update_daily_stats(e, asset_code, |d| {
+        d.volume = d.volume.saturating_add(amount);
+        d.xml_volume = d.xml_volume.saturating_add(cost_xml);
+        d.trade_count += 1;
+    });
+    update_global_stats(e, |s| {
+        s.traded_volume = s
+            .traded_volume
+            .checked_add(amount)
+            .unwrap_or_else(|| panic!("overflow in global traded volume"));
+        s.xml_volume = s
+            .xml_volume
+            .checked_add(cost_xml)
+            .unwrap_or_else(|| panic!("overflow in global xml volume"));
+    });
+}
+
+/// Drop a listing before it sells out, without an event of its own.
+/// Unsold custodial tokens stay in the seller's custody balance for
+/// `withdraw_inventory`. A disputed listing only goes on an admin takedown.
+fn take_down_listing(e: &Env, asset_code: &Symbol, seller: &Address, admin_forced: bool) {
+    if !admin_forced && read_listing(e, asset_code.clone(), seller.clone()).disputed {
+        panic!("listing under review");
+    }
+    remove_listing(e, asset_code, seller);
+}
+
+/// Drop a listing and tell indexers why it went away.
+fn close_listing(e: &Env, asset_code: Symbol, seller: Address, reason: String, admin_forced: bool) {
+    take_down_listing(e, &asset_code, &seller, admin_forced);
+
+    if event_enabled(e, EVENT_LISTING_CLOSED) {
+        ListingClosedEvent {
+            asset_code,
+            seller,
+            reason,
+            admin_forced,
+        }
+        .publish(e);
+    }
+}
+
+fn read_event_config(e: &Env) -> u32 {
+    e.storage()
+        .instance()
+        .get(&ExtKey::EventConfig)
+        .unwrap_or(ALL_OPTIONAL_EVENTS)
+}
+
+fn event_enabled(e: &Env, flag: u32) -> bool {
+    read_event_config(e) & flag != 0
+}
+
+/// Signer set and threshold guarding an asset's privileged actions.
+/// Assets that never configured one behave as a 1-of-1 set of `meta.admin`.
+fn read_asset_admins(e: &Env, asset_code: Symbol, meta: &CarbonAssetMeta) -> (Vec<Address>, u32) {
+    e.storage()
+        .instance()
+        .get(&DataKey::AssetAdmins(asset_code))
+        .unwrap_or_else(|| (Vec::from_array(e, [meta.admin.clone()]), 1))
+}
+
+/// Require at least `threshold` distinct members of the asset's admin set
+/// to have signed. `signers` names who is signing this invocation.
+fn require_asset_quorum(
+    e: &Env,
+    asset_code: Symbol,
+    meta: &CarbonAssetMeta,
+    signers: &Vec<Address>,
+) {
+    let (admins, threshold) = read_asset_admins(e, asset_code, meta);
+
+    for (i, signer) in signers.iter().enumerate() {
+        if !admins.contains(&signer) {
+            panic!("signer is not an asset admin");
+        }
+        if signers.first_index_of(&signer) != Some(i as u32) {
+            panic!("duplicate signer");
+        }
+    }
+    if signers.len() < threshold {
+        panic!("not enough admin signers");
+    }
+
+    for signer in signers.iter() {
+        signer.require_auth();
+    }
+}
+
+/// Store (or update) an asset registration. Callers check the admin's auth.
+fn register(
+    e: &Env,
+    asset_code: Symbol,
+    project_id: i64,
+    vintage_year: i32,
+    token: Address,
+    admin: Address,
+    provenance: Provenance,
+) {
+    require_sane_decimals(e, &token);
+    require_matching_decimals(e, &token);
+    if provenance.standard == Symbol::new(e, "") {
+        panic!("standard must not be empty");
+    }
+    if provenance.serial_end < provenance.serial_start {
+        panic!("serial_end must not be below serial_start");
+    }
+
+    if has_pending_migration(e, asset_code.clone()) {
+        panic!("asset token migration in progress");
+    }
+
+    // Re-registering keeps the descriptive metadata managed by the setters below
+    let key = DataKey::Asset(asset_code.clone());
+    let existing = e.storage().instance().get::<DataKey, CarbonAssetMeta>(&key);
+
+    // Only the current asset admin may re-register (and only while the
+    // asset has no multisig set), and never to swap the token: that goes
+    // through `migrate_asset_token`
+    if let Some(current) = existing.as_ref() {
+        if current.admin != admin {
+            let signers = Vec::from_array(e, [current.admin.clone()]);
+            require_asset_quorum(e, asset_code.clone(), current, &signers);
+        }
+        if current.token != token {
+            panic!("asset token can only change via migrate_asset_token");
+        }
+        if current.standard != provenance.standard
+            || current.serial_start != provenance.serial_start
+            || current.serial_end != provenance.serial_end
+        {
+            panic!("provenance is fixed at registration");
+        }
+        if provenance.retire_not_before < current.retire_not_before {
+            panic!("retirement lock can only be extended");
+        }
+    }
+
+    // Keep the project index in step when an asset moves between projects
+    let previous_project = existing.as_ref().map(|m| m.project_id);
+    if previous_project != Some(project_id) {
+        if let Some(old) = previous_project {
+            let old_index = Index::ProjectAssets(old);
+            if let Some(pos) = index_position(e, &old_index, &asset_code) {
+                index_swap_remove::<Symbol>(e, &old_index, pos);
+            }
+        }
+        index_push(e, &Index::ProjectAssets(project_id), asset_code.clone());
+    }
+
+    if existing.is_none() {
+        update_global_stats(e, |s| s.total_assets += 1);
+        index_push(e, &Index::AssetCodes, asset_code.clone());
+    }
+
+    if existing.as_ref().is_none_or(|m| m.admin != admin) {
+        let history_key = DataKey::AdminHistory(asset_code.clone());
+        let mut history: Vec<(Address, u64)> = e
+            .storage()
+            .instance()
+            .get(&history_key)
+            .unwrap_or_else(|| Vec::new(e));
+        if history.len() >= MAX_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back((admin.clone(), e.ledger().timestamp()));
+        e.storage().instance().set(&history_key, &history);
+    }
+
+    let meta = match existing {
+        Some(existing) => CarbonAssetMeta {
+            project_id,
+            vintage_year,
+            token,
+            admin: admin.clone(),
+            retire_not_before: provenance.retire_not_before,
+            ..existing
+        },
+        None => CarbonAssetMeta {
+            project_id,
+            vintage_year,
+            token,
+            admin: admin.clone(),
+            metadata_uri: String::from_str(e, ""),
+            standard: provenance.standard,
+            serial_start: provenance.serial_start,
+            serial_end: provenance.serial_end,
+            retire_not_before: provenance.retire_not_before,
+            burnable: true,
+            retire_sink: None,
+            region: Symbol::new(e, ""),
+            closed: false,
+            asset_class: Symbol::new(e, ""),
+            conservation_bps: 0,
+            conservation_fund: None,
+            max_holder_balance: 0,
+            retire_fee: 0,
+        },
+    };
+
+    write_asset(e, asset_code, &meta);
+}
+
+/// Mint through the asset's SAC and keep the `Minted` counter in step.
+/// `attestation_ref` is only carried into the `MintEvent`.
+fn mint_credits(
+    e: &Env,
+    asset_code: Symbol,
+    meta: &CarbonAssetMeta,
+    issuer: &Address,
+    amount: i128,
+    attestation_ref: Option<String>,
+) {
+    require_not_halted(e, &asset_code);
+    if meta.closed {
+        panic!("asset closed");
+    }
+    // Credits minted to the controller itself would be stranded
+    if *issuer == e.current_contract_address() {
+        panic!("cannot mint to the controller contract");
+    }
+
+    check_holder_cap(e, meta, issuer, amount);
+
+    // Admin client: has `mint`
+    let sac_client = StellarAssetClient::new(e, &meta.token);
+    sac_client.mint(issuer, &amount);
+
+    add_to_counter(e, &DataKey::Minted(asset_code.clone()), amount);
+    write_persistent(
+        e,
+        &ExtKey::MintedAt(asset_code.clone(), issuer.clone()),
+        &e.ledger().timestamp(),
+    );
+
+    let issuer_key = DataKey::Issuer(asset_code.clone());
+    if !e.storage().instance().has(&issuer_key) {
+        e.storage().instance().set(&issuer_key, issuer);
+    }
+
+    MintEvent {
+        asset_code,
+        issuer: issuer.clone(),
+        region: meta.region.clone(),
+        amount,
+        attestation_ref,
+    }
+    .publish(e);
+}
+
+/// Concentration limit: `holder` may not end up above the asset's
+/// `max_holder_balance` after receiving `incoming` more units.
+fn check_holder_cap(e: &Env, meta: &CarbonAssetMeta, holder: &Address, incoming: i128) {
+    if meta.max_holder_balance == 0 {
+        return;
+    }
+    let resulting = TokenClient::new(e, &meta.token)
+        .balance(holder)
+        .saturating_add(incoming);
+    if resulting > meta.max_holder_balance {
+        panic!(
+            "holder balance {} would exceed cap {}",
+            resulting, meta.max_holder_balance
+        );
+    }
+}
+
+fn read_mint_lockup(e: &Env, asset_code: &Symbol) -> u64 {
+    e.storage()
+        .instance()
+        .get(&ExtKey::MintLockup(asset_code.clone()))
+        .unwrap_or(0)
+}
+
+fn read_minted_at(e: &Env, asset_code: &Symbol, holder: &Address) -> Option<u64> {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::MintedAt(asset_code.clone(), holder.clone()))
+}
+
+/// Anti pump-and-dump: a mint recipient can't list or auction the asset
+/// until the asset's `MintLockup` has passed since their latest mint.
+fn require_mint_unlocked(e: &Env, asset_code: &Symbol, seller: &Address) {
+    let lockup = read_mint_lockup(e, asset_code);
+    if lockup == 0 {
+        return;
+    }
+    if let Some(minted_at) = read_minted_at(e, asset_code, seller) {
+        let unlocks_at = minted_at.saturating_add(lockup);
+        if e.ledger().timestamp() < unlocks_at {
+            panic!("minted credits locked until {}", unlocks_at);
+        }
+    }
+}
+
+/// Every hard check a new or updated listing must pass.
+fn check_listing(e: &Env, seller: &Address, asset_code: Symbol, amount: i128, price: i128) {
+    // Ensure the asset exists (panic if not)
+    let meta = read_asset(e, asset_code.clone());
+    require_trading_enabled(e, asset_code.clone());
+    require_holder_not_frozen(e, seller);
+    read_settlement_token(e, &meta);
+
+    if amount <= 0 {
+        panic!("amount must be positive");
+    }
+    if price <= 0 {
+        panic!("price must be positive");
+    }
+    if price < read_min_price(e, asset_code) {
+        panic!("price below minimum");
+    }
+    // A full fill must be priceable, or the listing could never be bought out
+    if checked_xml_cost(e, amount, price).is_none() {
+        panic!(
+            "listing too large: amount {} * price {} overflows",
+            amount, price
+        );
+    }
+}
+
+/// Validate and store a listing (creating or replacing the seller's entry).
+/// Returns the listing id: updates keep the existing id, new listings take
+/// the next one from the seller's counter. A listing can't switch between
+/// wallet-settled and `custodial`; the caller moves custodial tokens.
+/// `starts_at` of `None` keeps an existing listing's start (0 for new ones).
+fn put_listing(
+    e: &Env,
+    seller: Address,
+    proceeds_to: Address,
+    asset_code: Symbol,
+    amount: i128,
+    price: i128,
+    custodial: bool,
+    starts_at: Option<u64>,
+) -> u64 {
+    check_listing(e, &seller, asset_code.clone(), amount, price);
+    require_mint_unlocked(e, &asset_code, &seller);
+
+    let existing: Option<Listing> = e
+        .storage()
+        .persistent()
+        .get(&DataKey::Listing(asset_code.clone(), seller.clone()));
+    if existing.as_ref().is_some_and(|l| l.disputed) {
+        panic!("listing under review");
+    }
+    if existing.as_ref().is_some_and(|l| l.custodial != custodial) {
+        panic!("listing custody mode can't change; cancel it first");
+    }
+    if !custodial {
+        let released = existing.as_ref().map_or(0, |l| l.amount);
+        require_uncommitted_balance(e, &asset_code, &seller, released, amount);
+    }
+    if existing.as_ref().is_none_or(|l| l.price != price) {
+        require_price_cooldown(e, &asset_code, &seller, price);
+        record_price(e, &asset_code, &seller, price);
+    }
+    let starts_at = starts_at.unwrap_or_else(|| existing.as_ref().map_or(0, |l| l.starts_at));
+    let expires_at = listing_expiry(e);
+    if starts_at != 0 && expires_at != 0 && starts_at >= expires_at {
+        panic!("listing would expire before it starts");
+    }
+    let (listing_id, filled, created_at, accepted_tokens) = match existing {
+        Some(listing) => (
+            listing.listing_id,
+            listing.filled,
+            listing.created_at,
+            listing.accepted_tokens,
+        ),
+        None => {
+            check_listing_caps(e, &seller);
+            (next_listing_id(e, &seller), 0, e.ledger().timestamp(), Map::new(e))
+        }
+    };
+
+    let is_primary = e
+        .storage()
+        .instance()
+        .get::<DataKey, Address>(&DataKey::Issuer(asset_code.clone()))
+        .is_some_and(|issuer| issuer == seller);
+
+    let listing = Listing {
+        asset_code,
+        seller,
+        amount,
+        price,
+        proceeds_to,
+        listing_id,
+        is_primary,
+        custodial,
+        disputed: false,
+        expires_at,
+        starts_at,
+        created_at,
+        filled,
+        accepted_tokens,
+    };
+
+    store_listing(e, &listing);
+    listing_id
+}
+
+fn read_price_history(e: &Env, asset_code: &Symbol, seller: &Address) -> Vec<(i128, u64)> {
+    e.storage()
+        .persistent()
+        .get(&DataKey::PriceHistory(asset_code.clone(), seller.clone()))
+        .unwrap_or_else(|| Vec::new(e))
+}
+
+fn read_min_update_interval(e: &Env) -> u64 {
+    e.storage()
+        .instance()
+        .get(&ExtKey::MinUpdateInterval)
+        .unwrap_or(0)
+}
+
+/// Anti-spoofing throttle: a seller may not move their price on an asset
+/// again within `MinUpdateInterval` of the last change in its price
+/// history, so cancelling and relisting doesn't dodge it either.
+fn require_price_cooldown(e: &Env, asset_code: &Symbol, seller: &Address, price: i128) {
+    let interval = read_min_update_interval(e);
+    if interval == 0 {
+        return;
+    }
+    if let Some((last_price, changed_at)) = read_price_history(e, asset_code, seller).last() {
+        if last_price != price && e.ledger().timestamp() < changed_at.saturating_add(interval) {
+            panic!(
+                "price updated too recently; next change allowed at {}",
+                changed_at.saturating_add(interval)
+            );
+        }
+    }
+}
+
+/// Append a listing's new price, keeping the last `MAX_HISTORY_LEN`. The
+/// history outlives the listing so closed listings stay auditable.
+fn record_price(e: &Env, asset_code: &Symbol, seller: &Address, price: i128) {
+    let mut history = read_price_history(e, asset_code, seller);
+    if history.len() >= MAX_HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back((price, e.ledger().timestamp()));
+    write_persistent(
+        e,
+        &DataKey::PriceHistory(asset_code.clone(), seller.clone()),
+        &history,
+    );
+}
+
+fn read_trade_log(e: &Env) -> Vec<TradeRecord> {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::TradeLog)
+        .unwrap_or_else(|| Vec::new(e))
+}
+
+/// Append a settled purchase, keeping the last `MAX_TRADE_LOG_LEN`.
+fn record_trade_log(e: &Env, record: TradeRecord) {
+    let mut log = read_trade_log(e);
+    if log.len() >= MAX_TRADE_LOG_LEN {
+        log.pop_front();
+    }
+    log.push_back(record);
+    write_persistent(e, &ExtKey::TradeLog, &log);
+}
+
+fn read_max_listing_duration(e: &Env) -> u64 {
+    e.storage()
+        .instance()
+        .get(&DataKey::MaxListingDuration)
+        .unwrap_or(0)
+}
+
+/// `expires_at` for a listing written now. Every create or update restarts
+/// the clock, so only listings nobody touches expire.
+fn listing_expiry(e: &Env) -> u64 {
+    match read_max_listing_duration(e) {
+        0 => 0,
+        duration => e.ledger().timestamp().saturating_add(duration),
+    }
+}
+
+fn is_listing_expired(e: &Env, listing: &Listing) -> bool {
+    listing.expires_at != 0 && e.ledger().timestamp() >= listing.expires_at
+}
+
+/// Inside the listing's `[starts_at, expires_at)` window.
+fn is_listing_active(e: &Env, listing: &Listing) -> bool {
+    e.ledger().timestamp() >= listing.starts_at && !is_listing_expired(e, listing)
+}
+
+fn read_listing_caps(e: &Env) -> (u32, u32) {
+    e.storage()
+        .instance()
+        .get(&DataKey::ListingCaps)
+        .unwrap_or((0, 0))
+}
+
+/// Storage-bloat guard for opening a new listing (updates don't count).
+fn check_listing_caps(e: &Env, seller: &Address) {
+    let (max_active, max_per_seller) = read_listing_caps(e);
+    if max_active > 0 && read_global_stats(e).active_listings >= max_active as u64 {
+        panic!("active listing cap of {} reached", max_active);
+    }
+    if max_per_seller > 0 && read_listings_of(e, seller).len() >= max_per_seller {
+        panic!("seller listing cap of {} reached", max_per_seller);
+    }
+}
+
+fn next_listing_id(e: &Env, seller: &Address) -> u64 {
+    let key = DataKey::NextListingId(seller.clone());
+    let id: u64 = e.storage().persistent().get(&key).unwrap_or(0);
+    write_persistent(e, &key, &(id + 1));
+    id
+}
+
+fn is_trading_enabled(e: &Env, asset_code: Symbol) -> bool {
+    !e.storage()
+        .instance()
+        .get::<DataKey, bool>(&DataKey::TradingDisabled(asset_code))
+        .unwrap_or(false)
+}
+
+fn has_pending_migration(e: &Env, asset_code: Symbol) -> bool {
+    e.storage().instance().has(&DataKey::Migration(asset_code))
+}
+
+fn read_pause(e: &Env) -> Option<u64> {
+    e.storage().instance().get(&DataKey::Paused)
+}
+
+/// A pause with a `resume_at` lifts itself once the ledger reaches it.
+fn is_paused(e: &Env) -> bool {
+    read_pause(e).is_some_and(|resume_at| resume_at == 0 || e.ledger().timestamp() < resume_at)
+}
+
+fn is_asset_frozen(e: &Env, asset_code: &Symbol) -> bool {
+    e.storage()
+        .instance()
+        .has(&ExtKey::Frozen(asset_code.clone()))
+}
+
+fn is_holder_frozen(e: &Env, who: &Address) -> bool {
+    e.storage()
+        .persistent()
+        .has(&ExtKey::FrozenHolder(who.clone()))
+}
+
+/// A frozen holder can't list, buy, sell into a buy or retire, on any asset.
+fn require_holder_not_frozen(e: &Env, who: &Address) {
+    if is_holder_frozen(e, who) {
+        panic!("holder frozen");
+    }
+}
+
+/// The global pause and a compliance freeze stop every operation on an
+/// asset, minting and retirement included.
+fn require_not_halted(e: &Env, asset_code: &Symbol) {
+    if is_paused(e) {
+        panic!("contract paused");
+    }
+    if is_asset_frozen(e, asset_code) {
+        panic!("asset frozen");
+    }
+}
+
+/// Secondary trading (listings, buys, auctions) can be paused per asset
+/// while retirement and minting stay live. A pending token migration
+/// pauses it the same way; a global `pause` or compliance freeze halts it
+/// along with everything else.
+fn require_trading_enabled(e: &Env, asset_code: Symbol) {
+    require_not_halted(e, &asset_code);
+    if let Some(canonical) = read_alias(e, &asset_code) {
+        panic!("asset code deprecated, trade {:?} instead", canonical);
+    }
+    if read_asset(e, asset_code.clone()).closed {
+        panic!("asset closed");
+    }
+    if !is_trading_enabled(e, asset_code.clone()) {
+        panic!("trading disabled for asset");
+    }
+    if has_pending_migration(e, asset_code) {
+        panic!("asset token migration in progress");
+    }
+}
+
+fn read_xml_token(e: &Env) -> Address {
+    e.storage()
+        .instance()
+        .get(&DataKey::XmlToken)
+        .unwrap_or_else(|| panic!("XML token not set"))
+}
+
+/// Settlement token for trading an asset: the token routed to its
+/// `asset_class`, falling back to the global XML token.
+fn settlement_token(e: &Env, meta: &CarbonAssetMeta) -> Option<Address> {
+    if meta.asset_class != Symbol::new(e, "") {
+        let routed = e
+            .storage()
+            .instance()
+            .get(&DataKey::SettlementFor(meta.asset_class.clone()));
+        if routed.is_some() {
+            return routed;
+        }
+    }
+    e.storage().instance().get(&DataKey::XmlToken)
+}
+
+fn read_settlement_token(e: &Env, meta: &CarbonAssetMeta) -> Address {
+    settlement_token(e, meta).unwrap_or_else(|| panic!("no settlement token configured"))
+}
+
+/// Sales can't be created before there is a currency to settle them in;
+/// otherwise they'd sit in the book unbuyable.
+fn require_settlement_token(e: &Env) {
+    if !e.storage().instance().has(&DataKey::XmlToken) {
+        panic!("no settlement token configured");
+    }
+}
+
+/// Oversized trades only go through if the contract admin co-signs.
+fn check_trade_size(e: &Env, asset_code: Symbol, buyer: &Address, cost_xml: i128) {
+    let threshold = read_max_single_trade_xml(e);
+    if threshold > 0 && cost_xml > threshold {
+        read_admin(e).require_auth();
+
+        CircuitBreakerEvent {
+            asset_code,
+            buyer: buyer.clone(),
+            cost_xml,
+            threshold,
+        }
+        .publish(e);
+    }
+}
+
+/// Decimal places listing prices carry beyond the XML token's base unit
+/// (0 unless `set_price_scale` was called).
+fn read_price_scale(e: &Env) -> u32 {
+    e.storage()
+        .instance()
+        .get(&DataKey::PriceScale)
+        .unwrap_or(0)
+}
+
+/// `(a * b / denom, remainder != 0)` for non-negative operands, or `None`
+/// if the quotient doesn't fit in i128. When `a * b` itself overflows, one
+/// factor is split as `q * denom + r` so that
+/// `a * b / denom = q * b + r * b / denom`; if `r * b` still overflows for
+/// both orderings the result is reported as not fitting.
+fn mul_div_parts(a: i128, b: i128, denom: i128) -> Option<(i128, bool)> {
+    if denom <= 0 {
+        panic!("mul_div denominator must be positive");
+    }
+    if a < 0 || b < 0 {
+        panic!("mul_div operands must not be negative");
+    }
+    if let Some(product) = a.checked_mul(b) {
+        return Some((product / denom, product % denom != 0));
+    }
+    let split = |x: i128, y: i128| {
+        let low = (x % denom).checked_mul(y)?;
+        let quotient = (x / denom).checked_mul(y)?.checked_add(low / denom)?;
+        Some((quotient, low % denom != 0))
+    };
+    split(a, b).or_else(|| split(b, a))
+}
+
+/// `a * b / denom` rounded down, or `None` if it doesn't fit.
+fn checked_mul_div(a: i128, b: i128, denom: i128) -> Option<i128> {
+    mul_div_parts(a, b, denom).map(|(quotient, _)| quotient)
+}
+
+/// The one helper for ratio math (bps splits, price scaling, interpolation):
+/// `a * b / denom` rounded down, panicking if the result doesn't fit.
+fn mul_div(a: i128, b: i128, denom: i128) -> i128 {
+    checked_mul_div(a, b, denom)
+        .unwrap_or_else(|| panic!("overflow in mul_div: {} * {} / {}", a, b, denom))
+}
+
+/// `mul_div` rounded up instead of down.
+fn mul_div_ceil(a: i128, b: i128, denom: i128) -> i128 {
+    mul_div_parts(a, b, denom)
+        .and_then(|(quotient, inexact)| quotient.checked_add(inexact as i128))
+        .unwrap_or_else(|| panic!("overflow in mul_div: {} * {} / {}", a, b, denom))
+}
+
+/// XML owed for `amount` units at `price` per unit. Every cost calculation
+/// goes through here so quotes always match what a trade charges:
+/// `amount * price / 10^price_scale`. A non-zero scale lets prices be
+/// quoted more finely than the XML token's base unit; any remainder rounds
+/// up so a fill is never under-paid.
+fn xml_cost(e: &Env, amount: i128, price: i128) -> i128 {
+    mul_div_ceil(amount, price, 10i128.pow(read_price_scale(e)))
+}
+
+/// `xml_cost`, or `None` where it would overflow. For up-front checks that
+/// a listing can be priced at all.
+fn checked_xml_cost(e: &Env, amount: i128, price: i128) -> Option<i128> {
+    mul_div_parts(amount, price, 10i128.pow(read_price_scale(e)))
+        .and_then(|(quotient, inexact)| quotient.checked_add(inexact as i128))
+}
+
+/// `available - amount`, panicking instead of going negative. Callers check
+/// availability up front; this keeps the accounting safe if that ever moves.
+fn remaining_after(available: i128, amount: i128) -> i128 {
+    match available.checked_sub(amount) {
+        Some(left) if left >= 0 => left,
+        _ => panic!(
+            "invariant violated: {} taken from {} available",
+            amount, available
+        ),
+    }
+}
+
+/// Accepted `purpose` codes; a standard set until the admin replaces it.
+fn read_retire_purposes(e: &Env) -> Vec<Symbol> {
+    e.storage()
+        .instance()
+        .get(&DataKey::RetirePurposes)
+        .unwrap_or_else(|| {
+            Vec::from_array(
+                e,
+                [
+                    symbol_short!("OFFSET"),
+                    Symbol::new(e, "COMPLIANCE"),
+                    Symbol::new(e, "VOLUNTARY"),
+                ],
+            )
+        })
+}
+
+fn read_retire_quorum(e: &Env) -> Option<(i128, Address)> {
+    e.storage().instance().get(&DataKey::RetireQuorum)
+}
+
+fn read_fee_config(e: &Env) -> Option<(u32, Address)> {
+    e.storage().instance().get(&DataKey::Fee)
+}
+
+fn read_fee_splits(e: &Env) -> Vec<(Address, u32)> {
+    e.storage()
+        .instance()
+        .get(&ExtKey::FeeSplits)
+        .unwrap_or_else(|| Vec::new(e))
+}
+
+/// Who receives a marketplace fee of `fee_xml` and how much: the fee
+/// splits pro rata if configured, rounded down with the remainder going to
+/// the first collector so the shares sum exactly to `fee_xml`; otherwise
+/// all of it to the fee config's collector.
+fn fee_payouts(e: &Env, fee_xml: i128) -> Vec<(Address, i128)> {
+    let splits = read_fee_splits(e);
+    let mut payouts = Vec::new(e);
+    if splits.is_empty() {
+        let (_, collector) = read_fee_config(e).unwrap_or_else(|| panic!("fee collector not set"));
+        payouts.push_back((collector, fee_xml));
+        return payouts;
+    }
+    let mut assigned = 0;
+    for (collector, share_bps) in splits.iter() {
+        let share = mul_div(fee_xml, share_bps as i128, BPS_DENOMINATOR);
+        assigned += share;
+        payouts.push_back((collector, share));
+    }
+    let (first, share) = payouts.get_unchecked(0);
+    payouts.set(0, (first, share + fee_xml - assigned));
+    payouts
+}
+
+fn require_valid_fee_bps(fee_bps: u32) {
+    if fee_bps > MAX_FEE_BPS {
+        panic!("fee_bps above maximum of {}", MAX_FEE_BPS);
+    }
+}
+
+/// Cap on fee plus conservation bps for any one sale (`set_max_bps_sum`).
+fn read_max_bps_sum(e: &Env) -> u32 {
+    e.storage()
+        .instance()
+        .get(&ExtKey::MaxBpsSum)
+        .unwrap_or(DEFAULT_MAX_BPS_SUM)
+}
+
+/// Highest fee any sale of the asset can be charged as configured (asset
+/// override or global fee, or the primary fee), even while no fee
+/// collector is set.
+fn configured_fee_bps(e: &Env, asset_code: &Symbol) -> u32 {
+    let secondary = e
+        .storage()
+        .instance()
+        .get(&DataKey::AssetFee(asset_code.clone()))
+        .or_else(|| read_fee_config(e).map(|(bps, _)| bps))
+        .unwrap_or(0);
+    let primary: u32 = e
+        .storage()
+        .instance()
+        .get(&DataKey::PrimaryFeeBps)
+        .unwrap_or(0);
+    secondary.max(primary)
+}
+
+/// Called after `setting` was stored: the asset's fee plus conservation
+/// share must stay within `MaxBpsSum`, or every trade on it would pay out
+/// more than intended.
+fn validate_bps_sum(e: &Env, asset_code: &Symbol, setting: &str) {
+    let meta = read_asset(e, asset_code.clone());
+    let total = configured_fee_bps(e, asset_code) + meta.conservation_bps;
+    let cap = read_max_bps_sum(e);
+    if total > cap {
+        panic!(
+            "{} takes fee + conservation for {:?} to {} bps, above the {} bps cap",
+            setting, asset_code, total, cap
+        );
+    }
+}
+
+fn validate_all_bps_sums(e: &Env, setting: &str) {
+    for page_no in 0..index_pages(e, &Index::AssetCodes) {
+        let codes: Vec<Symbol> = read_index_page(e, &Index::AssetCodes, page_no);
+        for code in codes.iter() {
+            validate_bps_sum(e, &code, setting);
+        }
+    }
+}
+
+/// Fee rate for trades on an asset: the primary-sale rate for primary
+/// listings if set, else the per-asset override, else the global rate, and
+/// 0 while no collector is configured.
+fn effective_fee_bps(e: &Env, asset_code: Symbol, is_primary: bool) -> u32 {
+    let Some((global_bps, _)) = read_fee_config(e) else {
+        return 0;
+    };
+    if is_primary {
+        if let Some(primary_bps) = e.storage().instance().get(&DataKey::PrimaryFeeBps) {
+            return primary_bps;
+        }
+    }
+    e.storage()
+        .instance()
+        .get(&DataKey::AssetFee(asset_code))
+        .unwrap_or(global_bps)
+}
+
+/// Fee a buy costing `cost_xml` from `listing` pays. Shared by the trade
+/// path and the quotes so a preview always matches execution.
+fn listing_fee(e: &Env, listing: &Listing, cost_xml: i128) -> i128 {
+    fee_amount(
+        cost_xml,
+        effective_fee_bps(e, listing.asset_code.clone(), listing.is_primary),
+    )
+}
+
+/// Conservation share of a sale costing `cost_xml`, rounded like the fee.
+fn conservation_amount(meta: &CarbonAssetMeta, cost_xml: i128) -> i128 {
+    fee_amount(cost_xml, meta.conservation_bps)
+}
+
+/// Marketplace cut of `cost_xml`, rounded down so the seller never gets less
+/// than the quoted split.
+fn fee_amount(cost_xml: i128, fee_bps: u32) -> i128 {
+    mul_div(cost_xml, fee_bps as i128, BPS_DENOMINATOR)
+}
+
+/// Route for buying `amount` of an asset across listings, cheapest first
+/// (ties go to the earliest listing, like `best_ask`; disputed listings and
+/// those outside their active window are skipped, as are sellers in `exclude`
+/// and listings offering less than `min_fill`).
+/// Returns `(seller, fill_amount, fill_cost)` per listing hit,
+/// stopping short of `amount` if the book runs out. Anything that routes across sellers
+/// must plan through here so previews match execution.
+fn plan_fill(
+    e: &Env,
+    asset_code: &Symbol,
+    amount: i128,
+    exclude: &Vec<Address>,
+    min_fill: i128,
+) -> Vec<(Address, i128, i128)> {
+    let mut book: Vec<Listing> = Vec::new(e);
+    for seller in read_listing_sellers(e, asset_code).iter() {
+        if exclude.contains(&seller) || is_holder_frozen(e, &seller) {
+            continue;
+        }
+        let listing = read_listing(e, asset_code.clone(), seller);
+        if !listing.disputed && is_listing_active(e, &listing) && listing.amount >= min_fill {
+            book.push_back(listing);
+        }
+    }
+
+    let mut fills = Vec::new(e);
+    let mut remaining = amount;
+    while remaining > 0 && !book.is_empty() {
+        let mut best = 0;
+        for i in 1..book.len() {
+            if book.get_unchecked(i).price < book.get_unchecked(best).price {
+                best = i;
+            }
+        }
+        let listing = book.get_unchecked(best);
+        book.remove(best);
+
+        let fill = remaining.min(listing.amount);
+        fills.push_back((listing.seller, fill, xml_cost(e, fill, listing.price)));
+        remaining -= fill;
+    }
+    fills
+}
+
+/// `plan_fill` for exactly `amount`, with its total cost; panics if the
+/// book can't fill all of it.
+fn plan_full_fill(
+    e: &Env,
+    asset_code: &Symbol,
+    amount: i128,
+    exclude: &Vec<Address>,
+    min_fill: i128,
+) -> (Vec<(Address, i128, i128)>, i128) {
+    let fills = plan_fill(e, asset_code, amount, exclude, min_fill);
+    let mut total_amount = 0;
+    let mut total_cost: i128 = 0;
+    for (_, fill, cost) in fills.iter() {
+        total_amount += fill;
+        total_cost = total_cost
+            .checked_add(cost)
+            .unwrap_or_else(|| panic!("overflow in fill cost"));
+    }
+    if total_amount < amount {
+        panic!("not enough listed amount");
+    }
+    (fills, total_cost)
+}
+
+/// Whether a resting bid can take `fill` units at `cost_xml` without anyone
+/// signing now: the buyer's allowance and balance cover it, they aren't
+/// frozen on the settlement token, the trade is under the circuit breaker
+/// and the fill keeps them within the holder cap. Bids that fail are left
+/// resting.
+fn limit_bid_can_settle(
+    e: &Env,
+    bid: &LimitBid,
+    meta: &CarbonAssetMeta,
+    fill: i128,
+    cost_xml: i128,
+) -> bool {
+    let threshold = read_max_single_trade_xml(e);
+    if (threshold > 0 && cost_xml > threshold) || is_holder_frozen(e, &bid.buyer) {
+        return false;
+    }
+    if meta.max_holder_balance > 0
+        && TokenClient::new(e, &meta.token)
+            .balance(&bid.buyer)
+            .saturating_add(fill)
+            > meta.max_holder_balance
+    {
+        return false;
+    }
+    // A buyer the carbon SAC won't credit (deauthorized, or no trustline)
+    // would revert the whole call on the transfer
+    match StellarAssetClient::new(e, &meta.token).try_authorized(&bid.buyer) {
+        Ok(Ok(false)) => return false,
+        Err(Ok(err)) if err.is_type(ScErrorType::Contract) => return false,
+        _ => {}
+    }
+    let xml_token = read_settlement_token(e, meta);
+    if matches!(
+        StellarAssetClient::new(e, &xml_token).try_authorized(&bid.buyer),
+        Ok(Ok(false))
+    ) {
+        return false;
+    }
+    let xml_client = TokenClient::new(e, &xml_token);
+    xml_client.allowance(&bid.buyer, &e.current_contract_address()) >= cost_xml
+        && xml_client.balance(&bid.buyer) >= cost_xml
+}
+
+/// Cross the asset's limit-bid book with its listings: repeatedly pair the
+/// cheapest active listing (ties go to the earliest) with the highest
+/// eligible bid at or above its price (ties go to the earliest bid) and
+/// settle the overlap at the listing's price through the regular purchase
+/// path, one `PurchaseEvent` per match. A buyer never matches their own
+/// listing, and wallet listings only fill as far as the seller's approval
+/// and balance cover. Stops after `MAX_MATCHES_PER_CALL` matches or once nothing
+/// crosses; anything left waits for the next trigger. Expired bids met on
+/// the way are dropped. Returns the number of matches.
+fn match_orders(e: &Env, asset_code: &Symbol) -> u32 {
+    let meta = read_asset(e, asset_code.clone());
+
+    let mut bids: Vec<LimitBid> = Vec::new(e);
+    for buyer in read_bid_buyers(e, asset_code).iter() {
+        let bid = read_limit_bid(e, asset_code, &buyer);
+        if is_limit_bid_expired(e, &bid) {
+            close_limit_bid(e, asset_code, &buyer, "expired");
+        } else {
+            bids.push_back(bid);
+        }
+    }
+    if bids.is_empty() {
+        return 0;
+    }
+    let mut asks: Vec<Listing> = Vec::new(e);
+    for seller in read_listing_sellers(e, asset_code).iter() {
+        let listing = read_listing(e, asset_code.clone(), seller);
+        if !listing.disputed
+            && is_listing_active(e, &listing)
+            && !is_holder_frozen(e, &listing.seller)
+        {
+            asks.push_back(listing);
+        }
+    }
+
+    let carbon_client = TokenClient::new(e, &meta.token);
+    let contract = e.current_contract_address();
+    let mut matches = 0;
+    while matches < MAX_MATCHES_PER_CALL && !asks.is_empty() && !bids.is_empty() {
+        let mut ask_i = 0;
+        for i in 1..asks.len() {
+            if asks.get_unchecked(i).price < asks.get_unchecked(ask_i).price {
+                ask_i = i;
+            }
+        }
+        let mut ask = asks.get_unchecked(ask_i);
+
+        let mut bid_i: Option<u32> = None;
+        for (i, bid) in bids.iter().enumerate() {
+            let better = bid_i.is_none_or(|b| bid.limit_price > bids.get_unchecked(b).limit_price);
+            if bid.limit_price >= ask.price && bid.buyer != ask.seller && better {
+                bid_i = Some(i as u32);
+            }
+        }
+        let Some(bid_i) = bid_i else {
+            asks.remove(ask_i);
+            continue;
+        };
+        let mut bid = bids.get_unchecked(bid_i);
+
+        let mut fill = bid.amount.min(ask.amount);
+        if !ask.custodial {
+            fill = fill
+                .min(carbon_client.allowance(&ask.seller, &contract))
+                .min(carbon_client.balance(&ask.seller));
+        }
+        if fill <= 0 {
+            asks.remove(ask_i);
+            continue;
+        }
+        let cost_xml = xml_cost(e, fill, ask.price);
+        if !limit_bid_can_settle(e, &bid, &meta, fill, cost_xml) {
+            bids.remove(bid_i);
+            continue;
+        }
+
+        let buyer = bid.buyer.clone();
+        bid.amount -= fill;
+        if bid.amount == 0 {
+            remove_limit_bid(e, asset_code, &buyer);
+            bids.remove(bid_i);
+        } else {
+            store_limit_bid(e, &bid);
+            bids.set(bid_i, bid);
+        }
+
+        let purchase = prepare_purchase(
+            e,
+            &buyer,
+            asset_code.clone(),
+            ask.seller.clone(),
+            fill,
+            cost_xml,
+        );
+        pay_for_purchase(e, &purchase, &buyer, false);
+        finish_purchase(e, purchase, &buyer, &buyer, &buyer, fill, None);
+        matches += 1;
+
+        ask.amount -= fill;
+        if ask.amount == 0 {
+            asks.remove(ask_i);
+        } else {
+            asks.set(ask_i, ask);
+        }
+    }
+    matches
+}
+
+/// Execute planned fills leg by leg, each as a `buy_with_xml` by `buyer`
+/// capped at its planned cost.
+fn settle_fills(e: &Env, buyer: &Address, asset_code: &Symbol, fills: &Vec<(Address, i128, i128)>) {
+    for (seller, fill, cost) in fills.iter() {
+        let purchase = prepare_purchase(e, buyer, asset_code.clone(), seller, fill, cost);
+        pay_for_purchase(e, &purchase, buyer, false);
+        finish_purchase(e, purchase, buyer, buyer, buyer, fill, None);
+    }
+}
+
+fn read_basket(e: &Env, basket_code: &Symbol) -> Vec<(Symbol, u32)> {
+    e.storage()
+        .instance()
+        .get(&ExtKey::Basket(basket_code.clone()))
+        .unwrap_or_else(|| panic!("basket not defined"))
+}
+
+/// Everything a buy resolves and validates before any tokens move.
+struct Purchase {
+    listing: Listing,
+    meta: CarbonAssetMeta,
+    xml_token: Address,     // token the buyer pays in; see `prepare_purchase_in`
+    cost_xml: i128,         // in `xml_token`
+    xml_value: i128,        // the same fill at the listing's settlement-token price
+    fee_xml: i128,          // part of `cost_xml` going to the fee collector
+    conservation_xml: i128, // part of `cost_xml` going to the asset's conservation fund
+}
+
+/// Name the reason an allowance-funded payment would fail instead of
+/// leaving it to an opaque host error from `transfer_from`. The frozen check
+/// only applies to tokens that answer SAC's `authorized`.
+fn require_xml_funds(e: &Env, xml_token: &Address, payer: &Address, cost_xml: i128) {
+    if matches!(
+        StellarAssetClient::new(e, xml_token).try_authorized(payer),
+        Ok(Ok(false))
+    ) {
+        panic!("xml token frozen for buyer");
+    }
+    let xml_client = TokenClient::new(e, xml_token);
+    if xml_client.allowance(payer, &e.current_contract_address()) < cost_xml {
+        panic!("xml allowance too low");
+    }
+    if xml_client.balance(payer) < cost_xml {
+        panic!("xml balance insufficient");
+    }
+}
+
+/// XML leg of a listing purchase: `cost_xml - fee_xml - conservation_xml`
+/// to the listing's `proceeds_to`, `fee_xml` to the fee collector and
+/// `conservation_xml` to the asset's conservation fund. Pays out of the
+/// controller's own balance when the buyer has already deposited, otherwise
+/// pulls from `payer` through the controller's allowance.
+///
+/// With credit fallback enabled the full cost is first pulled into the
+/// controller and the seller's payout is attempted with `try_transfer`. A
+/// failing token call (e.g. a classic asset without a trustline) is caught
+/// and rolled back on its own, and the proceeds are booked as a withdrawable
+/// `Credit` instead, so the buyer's trade still completes.
+fn pay_for_purchase(e: &Env, purchase: &Purchase, payer: &Address, deposited: bool) {
+    if !deposited {
+        require_xml_funds(e, &purchase.xml_token, payer, purchase.cost_xml);
+    }
+    let xml_client = TokenClient::new(e, &purchase.xml_token);
+    let contract = e.current_contract_address();
+    let fallback = is_credit_fallback_enabled(e);
+    if fallback && !deposited {
+        xml_client.transfer_from(&contract, payer, &contract, &purchase.cost_xml);
+    }
+    let held = deposited || fallback;
+    let pay = |to: &Address, amount: i128| {
+        if held {
+            xml_client.transfer(&contract, to, &amount);
+        } else {
+            xml_client.transfer_from(&contract, payer, to, &amount);
+        }
+    };
+
+    let proceeds = purchase.cost_xml - purchase.fee_xml - purchase.conservation_xml;
+    let proceeds_to = &purchase.listing.proceeds_to;
+    if fallback {
+        if !matches!(
+            xml_client.try_transfer(&contract, proceeds_to, &proceeds),
+            Ok(Ok(()))
+        ) {
+            add_credit(e, &purchase.xml_token, proceeds_to, proceeds);
+        }
+    } else {
+        pay(proceeds_to, proceeds);
+    }
+    if purchase.fee_xml > 0 {
+        for (collector, share) in fee_payouts(e, purchase.fee_xml).iter() {
+            if share > 0 {
+                pay(&collector, share);
+            }
+        }
+    }
+    if purchase.conservation_xml > 0 {
+        let fund = purchase
+            .meta
+            .conservation_fund
+            .as_ref()
+            .unwrap_or_else(|| panic!("conservation fund not set"));
+        pay(fund, purchase.conservation_xml);
+    }
+}
+
+fn read_waive_buyback_fees(e: &Env) -> bool {
+    e.storage()
+        .instance()
+        .get(&ExtKey::WaiveBuybackFees)
+        .unwrap_or(false)
+}
+
+fn is_credit_fallback_enabled(e: &Env) -> bool {
+    e.storage()
+        .instance()
+        .get(&DataKey::CreditFallback)
+        .unwrap_or(false)
+}
+
+fn add_credit(e: &Env, token: &Address, who: &Address, amount: i128) {
+    let key = DataKey::Credit(who.clone(), token.clone());
+    let total = read_persistent_counter(e, &key)
+        .checked_add(amount)
+        .expect("counter overflow");
+    write_persistent_counter(e, &key, total);
+
+    CreditedEvent {
+        who: who.clone(),
+        token: token.clone(),
+        amount,
+    }
+    .publish(e);
+}
+
+fn prepare_purchase(
+    e: &Env,
+    buyer: &Address,
+    asset_code: Symbol,
+    seller: Address,
+    amount: i128,
+    max_xml: i128,
+) -> Purchase {
+    prepare_purchase_in(e, buyer, asset_code, seller, amount, max_xml, None)
+}
+
+/// `prepare_purchase` settling in `pay_token`: one of the listing's
+/// `accepted_tokens`, at the price the seller set for it, or the asset's
+/// settlement token at the listing price (also what `None` means).
+/// `max_xml` and fees are in the token paid; the circuit breaker and the
+/// XML volume counters use the fill's value at the listing price.
+fn prepare_purchase_in(
+    e: &Env,
+    buyer: &Address,
+    asset_code: Symbol,
+    seller: Address,
+    amount: i128,
+    max_xml: i128,
+    pay_token: Option<Address>,
+) -> Purchase {
+    if amount <= 0 {
+        panic!("amount must be positive");
+    }
+    require_holder_not_frozen(e, buyer);
+    require_holder_not_frozen(e, &seller);
+
+    // Read listing
+    let listing = read_listing(e, asset_code.clone(), seller);
+
+    if amount > listing.amount {
+        panic!("not enough listed amount");
+    }
+    if listing.disputed {
+        panic!("listing under review");
+    }
+    if is_listing_expired(e, &listing) {
+        panic!("listing expired");
+    }
+    if !is_listing_active(e, &listing) {
+        panic!("listing not yet active");
+    }
+
+    // Read asset meta (to get carbon token contract)
+    let meta = read_asset(e, asset_code.clone());
+    require_trading_enabled(e, asset_code.clone());
+
+    let settlement = read_settlement_token(e, &meta);
+    let (xml_token, price) = match pay_token {
+        Some(token) if token != settlement => {
+            let price = listing
+                .accepted_tokens
+                .get(token.clone())
+                .unwrap_or_else(|| panic!("listing does not accept this token"));
+            (token, price)
+        }
+        _ => (settlement, listing.price),
+    };
+
+    let cost_xml = xml_cost(e, amount, price);
+
+    if cost_xml > max_xml {
+        panic!("price exceeds max_xml");
+    }
+
+    let xml_value = xml_cost(e, amount, listing.price);
+    check_trade_size(e, asset_code.clone(), buyer, xml_value);
+
+    let fee_xml = listing_fee(e, &listing, cost_xml);
+    let conservation_xml = conservation_amount(&meta, cost_xml);
+
+    Purchase {
+        listing,
+        meta,
+        xml_token,
+        cost_xml,
+        xml_value,
+        fee_xml,
+        conservation_xml,
+    }
+}
+
+/// Assign the next trade id and its settlement id: the SHA-256 of the
+/// XDR-encoded `ScVal` tuple `(trade_id: u64, asset_code: Symbol,
+/// ledger_sequence: u32)`, so operators can recompute it off-chain. It's
+/// meant to be echoed into a classic transaction memo: use it whole as a
+/// `MEMO_HASH`, or truncate it for a 28-byte `MEMO_TEXT` (e.g. the first
+/// 14 bytes hex-encoded); any prefix of a hash is still evenly spread.
+fn next_settlement(e: &Env, asset_code: &Symbol) -> (u64, BytesN<32>) {
+    let trade_id: u64 = e
+        .storage()
+        .instance()
+        .get(&ExtKey::NextTradeId)
+        .unwrap_or(0);
+    e.storage()
+        .instance()
+        .set(&ExtKey::NextTradeId, &(trade_id + 1));
+
+    let preimage = (trade_id, asset_code.clone(), e.ledger().sequence()).to_xdr(e);
+    (trade_id, e.crypto().sha256(&preimage).into())
+}
+
+/// Second half of every buy: deliver the carbon to `recipient` (the buyer
+/// unless bought via `buy_for`), update the listing, then publish the
+/// `PurchaseEvent`. With `auto_retire` (the recipient's `AutoRetire` note)
+/// the carbon goes to the controller instead and is retired on the
+/// recipient's behalf right after. Returns the trade's settlement id.
+fn finish_purchase(
+    e: &Env,
+    purchase: Purchase,
+    buyer: &Address,
+    recipient: &Address,
+    funding_source: &Address,
+    amount: i128,
+    auto_retire: Option<String>,
+) -> BytesN<32> {
+    let Purchase {
+        mut listing,
+        meta,
+        xml_token,
+        cost_xml,
+        xml_value,
+        fee_xml,
+        conservation_xml,
+    } = purchase;
+    let asset_code = listing.asset_code.clone();
+    let seller = listing.seller.clone();
+
+    // Carbon: seller -> recipient, spending the allowance granted to the
+    // controller, or straight out of custody for a custodial listing.
+    // Credits about to be auto-retired stay with the controller.
+    let carbon_client = TokenClient::new(e, &meta.token);
+    let contract = e.current_contract_address();
+    let deliver_to = if auto_retire.is_some() {
+        &contract
+    } else {
+        check_holder_cap(e, &meta, recipient, amount);
+        recipient
+    };
+    if listing.custodial {
+        if deliver_to != &contract {
+            carbon_client.transfer(&contract, deliver_to, &amount);
+        }
+        adjust_custody(e, &asset_code, &seller, -amount);
+    } else {
+        carbon_client.transfer_from(&contract, &seller, deliver_to, &amount);
+    }
+
+    record_trade(e, &asset_code, amount, xml_value);
+
+    // Update or remove listing
+    listing.amount = remaining_after(listing.amount, amount);
+    listing.filled = listing
+        .filled
+        .checked_add(amount)
+        .unwrap_or_else(|| panic!("overflow in listing filled total"));
+    if listing.amount > 0 {
+        store_listing(e, &listing);
+    } else {
+        remove_listing(e, &asset_code, &seller);
+        update_seller_stats(e, &seller, |s| s.completed += 1);
+    }
+
+    let (trade_id, settlement_id) = next_settlement(e, &asset_code);
+    record_trade_log(
+        e,
+        TradeRecord {
+            asset_code: asset_code.clone(),
+            buyer: buyer.clone(),
+            seller: seller.clone(),
+            amount,
+            price: listing.price,
+            ledger: e.ledger().sequence(),
+            trade_id,
+        },
+    );
+    PurchaseEvent {
+        asset_code: asset_code.clone(),
+        buyer: buyer.clone(),
+        region: meta.region,
+        seller: seller.clone(),
+        recipient: recipient.clone(),
+        funding_source: funding_source.clone(),
+        amount,
+        pay_token: xml_token,
+        cost_xml,
+        seller_proceeds: cost_xml - fee_xml - conservation_xml,
+        fee_xml,
+        conservation_xml,
+        is_primary: listing.is_primary,
+        remaining: listing.amount,
+        trade_id,
+        settlement_id: settlement_id.clone(),
+    }
+    .publish(e);
+
+    // Price feed from today's bucket, which `record_trade` just updated
+    let today = read_daily_stats(e, &asset_code, e.ledger().timestamp() / SECONDS_PER_DAY);
+    PriceFeedEvent {
+        asset_code: asset_code.clone(),
+        last_price: listing.price,
+        vwap: mul_div(today.xml_volume, 10i128.pow(read_price_scale(e)), today.volume),
+        volume: today.volume,
+        ledger: e.ledger().sequence(),
+    }
+    .publish(e);
+
+    if listing.amount == 0 && event_enabled(e, EVENT_LISTING_CONSUMED) {
+        ListingConsumedEvent {
+            asset_code: asset_code.clone(),
+            seller,
+            listing_id: listing.listing_id,
+            final_buyer: buyer.clone(),
+            total_volume: listing.filled,
+        }
+        .publish(e);
+    }
+
+    if let Some(note) = auto_retire {
+        retire_credits(
+            e,
+            asset_code,
+            recipient.clone(),
+            amount,
+            note,
+            None,
+            None,
+            None,
+            false,
+            true,
+        );
+    }
+    settlement_id
+}
+
+fn read_auto_retire(e: &Env, who: &Address) -> Option<String> {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::AutoRetire(who.clone()))
+}
+
+fn read_total_supply(e: &Env, token: &Address) -> Option<i128> {
+    match TokenSupplyClient::new(e, token).try_total_supply() {
+        Ok(Ok(supply)) => Some(supply),
+        _ => None,
+    }
+}
+
+fn read_dutch_auction(e: &Env, asset_code: Symbol, seller: Address) -> DutchAuction {
+    e.storage()
+        .persistent()
+        .get::<DataKey, DutchAuction>(&DataKey::DutchAuction(asset_code, seller))
+        .unwrap_or_else(|| panic!("auction not found"))
+}
+
+/// Current per-unit price of a Dutch auction by linear interpolation.
+fn dutch_auction_price(e: &Env, auction: &DutchAuction) -> i128 {
+    let now = e.ledger().timestamp();
+    if now <= auction.start {
+        return auction.start_price;
+    }
+    if now >= auction.end {
+        return auction.end_price;
+    }
+
+    let elapsed = (now - auction.start) as i128;
+    let duration = (auction.end - auction.start) as i128;
+    let drop = mul_div(auction.start_price - auction.end_price, elapsed, duration);
+    auction.start_price - drop
+}
+
+fn read_english_auction(e: &Env, auction_id: u64) -> EnglishAuction {
+    e.storage()
+        .persistent()
+        .get::<DataKey, EnglishAuction>(&DataKey::EnglishAuction(auction_id))
+        .unwrap_or_else(|| panic!("auction not found"))
+}
+
+fn read_certificate(e: &Env, certificate_id: u64) -> RetirementCertificate {
+    e.storage()
+        .persistent()
+        .get::<DataKey, RetirementCertificate>(&DataKey::Certificate(certificate_id))
+        .unwrap_or_else(|| panic!("certificate not found"))
+}
+
+fn read_cert_transfer_lock(e: &Env) -> u64 {
+    e.storage()
+        .instance()
+        .get(&ExtKey::CertTransferLock)
+        .unwrap_or(0)
+}
+
+/// Add a certificate to `owner`'s paged list, remembering its slot so a
+/// later transfer can take it out without scanning the list.
+fn add_cert_of(e: &Env, owner: &Address, certificate_id: u64) {
+    let slot = index_push(e, &Index::OwnedCerts(owner.clone()), certificate_id);
+    write_persistent(e, &ExtKey::CertSlot(certificate_id), &slot);
+}
+
+fn remove_cert_of(e: &Env, owner: &Address, certificate_id: u64) {
+    let slot_key = ExtKey::CertSlot(certificate_id);
+    let slot: u32 = e
+        .storage()
+        .persistent()
+        .get(&slot_key)
+        .unwrap_or_else(|| panic!("certificate not indexed"));
+    e.storage().persistent().remove(&slot_key);
+    if let Some(moved) = index_swap_remove::<u64>(e, &Index::OwnedCerts(owner.clone()), slot) {
+        write_persistent(e, &ExtKey::CertSlot(moved), &slot);
+    }
+}
+
+/// When the deployment opts in, refuse carbon tokens whose decimals differ
+/// from the XML token's: `amount * price` assumes both use the same base
+/// unit. Skipped until the XML token is set.
+fn require_matching_decimals(e: &Env, token: &Address) {
+    let enabled = e
+        .storage()
+        .instance()
+        .get(&ExtKey::RequireMatchingDecimals)
+        .unwrap_or(false);
+    let xml_token: Option<Address> = e.storage().instance().get(&DataKey::XmlToken);
+    if let (true, Some(xml_token)) = (enabled, xml_token) {
+        if TokenClient::new(e, token).decimals() != TokenClient::new(e, &xml_token).decimals() {
+            panic!("decimals mismatch; enable decimal-aware pricing");
+        }
+    }
+}
+
+/// Refuse tokens that report a nonsensical number of decimals.
+fn require_sane_decimals(e: &Env, token: &Address) {
+    let decimals = TokenClient::new(e, token).decimals();
+    if decimals > MAX_TOKEN_DECIMALS {
+        panic!("token decimals out of range");
+    }
+}
+
+/// Why a retirement of `amount` by `from` would fail, as warning codes
+/// (empty if it would go through). Shared by `preview_retire` and
+/// `retire_batch_best_effort`; keep in step with `retire_credits`.
+fn retire_warnings(
+    e: &Env,
+    asset_code: &Symbol,
+    meta: &CarbonAssetMeta,
+    from: &Address,
+    amount: i128,
+) -> Vec<Symbol> {
+    let mut warnings = Vec::new(e);
+    if is_paused(e) || is_asset_frozen(e, asset_code) || is_holder_frozen(e, from) {
+        warnings.push_back(symbol_short!("HALTED"));
+    }
+    if amount <= 0 {
+        warnings.push_back(symbol_short!("AMOUNT"));
+    } else if amount < read_min_retire_amount(e, asset_code.clone()) {
+        warnings.push_back(symbol_short!("BELOW_MIN"));
+    }
+    if e.ledger().timestamp() < meta.retire_not_before {
+        warnings.push_back(symbol_short!("LOCKED"));
+    }
+    if !meta.burnable && meta.retire_sink.is_none() {
+        warnings.push_back(symbol_short!("NO_PATH"));
+    }
+    // Whether the signer will co-sign can't be known ahead of the call
+    if read_retire_quorum(e).is_some_and(|(threshold, _)| amount > threshold) {
+        warnings.push_back(symbol_short!("QUORUM"));
+    }
+    if TokenClient::new(e, &meta.token).balance(from) < amount {
+        warnings.push_back(symbol_short!("BALANCE"));
+    }
+    // Only SACs expose `authorized`; other tokens just skip this check
+    if matches!(
+        StellarAssetClient::new(e, &meta.token).try_authorized(from),
+        Ok(Ok(false))
+    ) {
+        warnings.push_back(symbol_short!("FROZEN"));
+    }
+    if meta.retire_fee > 0 {
+        let fee_ok = match (settlement_token(e, meta), read_fee_config(e)) {
+            (Some(xml_token), Some(_)) => {
+                let xml_client = TokenClient::new(e, &xml_token);
+                xml_client.allowance(from, &e.current_contract_address()) >= meta.retire_fee
+                    && xml_client.balance(from) >= meta.retire_fee
+            }
+            _ => false,
+        };
+        if !fee_ok {
+            warnings.push_back(symbol_short!("FEE"));
+        }
+    }
+    if let Some(oracle) = read_retirement_oracle(e) {
+        if !matches!(
+            RetirementOracleClient::new(e, &oracle).try_is_valid(asset_code, &amount),
+            Ok(Ok(true))
+        ) {
+            warnings.push_back(symbol_short!("INVALID"));
+        }
+    }
+    warnings
+}
+
+fn read_total_retired_by(e: &Env, who: &Address) -> i128 {
+    e.storage()
+        .persistent()
+        .get(&ExtKey::TotalRetiredBy(who.clone()))
+        .unwrap_or(0)
+}
+
+fn read_retirement_oracle(e: &Env) -> Option<Address> {
+    e.storage().instance().get(&ExtKey::RetirementOracle)
+}
+
+/// Collect an asset's flat `retire_fee` from `from` into the fee collector,
+/// through the allowance `from` granted the controller. Returns the fee.
+fn collect_retire_fee(e: &Env, meta: &CarbonAssetMeta, from: &Address) -> i128 {
+    if meta.retire_fee == 0 {
+        return 0;
+    }
+    let (_, collector) =
+        read_fee_config(e).unwrap_or_else(|| panic!("no fee collector configured"));
+    let contract = e.current_contract_address();
+    TokenClient::new(e, &read_settlement_token(e, meta)).transfer_from(
+        &contract,
+        from,
+        &collector,
+        &meta.retire_fee,
+    );
+    meta.retire_fee
+}
+
+/// Shared retirement path: checks, burns (or sinks) `amount`, issues the
+/// `RetirementCertificate` and emits the retire events. The caller has
+/// already checked `from`'s auth, unless `held_by_controller`: then the
+/// controller already holds the units for `from` (auto-retire) and burns
+/// its own. Returns the certificate id.
+fn retire_credits(
+    e: &Env,
+    asset_code: Symbol,
+    from: Address,
+    amount: i128,
+    note: String,
+    beneficiary_name: Option<String>,
+    retirement_reference: Option<String>,
+    purpose: Option<Symbol>,
+    buyback: bool,
+    held_by_controller: bool,
+) -> u64 {
+    // Clone because we also want to use asset_code in the event
+    let meta = read_asset(e, asset_code.clone());
+
+    require_not_halted(e, &asset_code);
+    require_holder_not_frozen(e, &from);
+    if e.ledger().timestamp() < meta.retire_not_before {
+        panic!("retirement locked until {}", meta.retire_not_before);
+    }
+    check_retire_amount(e, asset_code.clone(), amount);
+    require_string_len(&note, MAX_STRING_LEN, "note");
+    if let Some(purpose) = purpose.as_ref() {
+        if !read_retire_purposes(e).contains(purpose) {
+            panic!("unknown retirement purpose");
+        }
+    }
+    if let Some(name) = beneficiary_name.as_ref() {
+        require_string_len(name, MAX_BENEFICIARY_NAME_LEN, "beneficiary_name");
+    }
+    if let Some(reference) = retirement_reference.as_ref() {
+        require_string_len(reference, MAX_REFERENCE_LEN, "retirement_reference");
+        let key = DataKey::UsedReference(reference.clone());
+        if e.storage().persistent().has(&key) {
+            panic!("reference already retired");
+        }
+        write_persistent(e, &key, &true);
+    }
+
+    // Large retirements need the compliance signer as a second authorizer
+    if let Some((threshold, compliance_signer)) = read_retire_quorum(e) {
+        if amount > threshold {
+            compliance_signer.require_auth();
+        }
+    }
+
+    if let Some(oracle) = read_retirement_oracle(e) {
+        if !RetirementOracleClient::new(e, &oracle).is_valid(&asset_code, &amount) {
+            panic!("retirement oracle rejected the credits");
+        }
+    }
+
+    // Standard token interface for burn / transfer
+    let token_client = TokenClient::new(e, &meta.token);
+    let source = if held_by_controller {
+        e.current_contract_address()
+    } else {
+        from.clone()
+    };
+    let balance = token_client.balance(&source);
+    if balance < amount {
+        panic!(
+            "insufficient balance to retire: have {}, need {}",
+            balance, amount
+        );
+    }
+    let fee_xml = collect_retire_fee(e, &meta, &from);
+    if meta.burnable {
+        token_client.burn(&source, &amount);
+    } else if let Some(sink) = meta.retire_sink.as_ref() {
+        token_client.transfer(&source, sink, &amount);
+    } else {
+        panic!("asset token does not support retirement");
+    }
+
+    add_to_counter(e, &DataKey::TotalRetired(asset_code.clone()), amount);
+    let retired_by_key = ExtKey::TotalRetiredBy(from.clone());
+    let retired_by = read_total_retired_by(e, &from)
+        .checked_add(amount)
+        .unwrap_or_else(|| panic!("overflow in holder retired total"));
+    write_persistent(e, &retired_by_key, &retired_by);
+    update_daily_stats(e, &asset_code, |d| d.retired = d.retired.saturating_add(amount));
+    update_global_stats(e, |s| {
+        s.total_retired = s
+            .total_retired
+            .checked_add(amount)
+            .unwrap_or_else(|| panic!("overflow in global retired total"));
+    });
+
+    let certificate_id: u64 = e
+        .storage()
+        .instance()
+        .get(&DataKey::NextCertificateId)
+        .unwrap_or(0);
+    e.storage()
+        .instance()
+        .set(&DataKey::NextCertificateId, &(certificate_id + 1));
+
+    let certificate = RetirementCertificate {
+        id: certificate_id,
+        asset_code: asset_code.clone(),
+        holder: from.clone(),
+        amount,
+        project_id: meta.project_id,
+        vintage_year: meta.vintage_year,
+        note: note.clone(),
+        timestamp: e.ledger().timestamp(),
+        beneficiary_name: beneficiary_name.clone(),
+        retirement_reference,
+        purpose: purpose.clone(),
+        owner: from.clone(),
+        transfer_locked_until: match read_cert_transfer_lock(e) {
+            0 => 0,
+            lock => e.ledger().timestamp() + lock,
+        },
+    };
+    write_persistent(e, &DataKey::Certificate(certificate_id), &certificate);
+    add_cert_of(e, &from, certificate_id);
+
+    index_push(
+        e,
+        &Index::CertsByVintage(meta.project_id, meta.vintage_year),
+        certificate_id,
+    );
+
+    index_push(
+        e,
+        &Index::CertsByDay(certificate.timestamp / SECONDS_PER_DAY),
+        certificate_id,
+    );
+
+    // A failing proof mint reverts the whole retirement
+    if let Some(nft) = e
+        .storage()
+        .instance()
+        .get::<ExtKey, Address>(&ExtKey::RetirementNftContract)
+    {
+        RetirementNftClient::new(e, &nft).mint_proof(
+            &from,
+            &certificate_id,
+            &amount,
+            &meta.project_id,
+            &meta.vintage_year,
+        );
+    }
+
+    let has_provenance = meta.standard != Symbol::new(e, "") || !meta.metadata_uri.is_empty();
+    if has_provenance {
+        RegistryRetireEvent {
+            asset_code: asset_code.clone(),
+            certificate_id,
+            holder: from.clone(),
+            amount,
+            project_id: meta.project_id,
+            vintage_year: meta.vintage_year,
+            standard: meta.standard.clone(),
+            serial_start: meta.serial_start,
+            serial_end: meta.serial_end,
+            metadata_uri: meta.metadata_uri,
+        }
+        .publish(e);
+    }
+
+    // Emit a carbon-specific event your indexer / backend can listen to
+    CarbonRetireEvent {
+        asset_code,
+        holder: from,
+        region: meta.region,
+        amount,
+        project_id: meta.project_id,
+        vintage_year: meta.vintage_year,
+        note,
+        standard: meta.standard,
+        serial_start: meta.serial_start,
+        serial_end: meta.serial_end,
+        beneficiary_name,
+        purpose,
+        fee_xml,
+        buyback,
+    }
+    .publish(e);
+
+    certificate_id
+}
+
+/// Carbon credit retirement event, indexed off-chain.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CarbonRetireEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub holder: Address,
+    #[topic]
+    pub region: Symbol,
+    pub amount: i128,
+    pub project_id: i64,
+    pub vintage_year: i32,
+    pub note: String,
+    pub standard: Symbol,
+    pub serial_start: i64,
+    pub serial_end: i64,
+    pub beneficiary_name: Option<String>,
+    pub purpose: Option<Symbol>,
+    pub fee_xml: i128, // asset's `retire_fee` collected for this retirement
+    pub buyback: bool, // issuer bought the credits back to retire them (`buyback_and_retire`)
+}
+
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssetClosedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    pub reason: String,
+    pub outstanding_supply: i128, // minted - retired at close
+}
+
+/// Seller proceeds couldn't be delivered and were booked as a credit.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CreditedEvent {
+    #[topic]
+    pub who: Address,
+    pub token: Address,
+    pub amount: i128,
+}
+
+/// A listing purchase settled (`buy_with_xml` / `buy_with_xml_exact` / `buy_for`).
+/// `buyer` is the payer.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PurchaseEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub buyer: Address,
+    #[topic]
+    pub region: Symbol,
+    pub seller: Address,
+    pub recipient: Address, // who got the credits; the buyer unless bought via `buy_for`
+    pub funding_source: Address, // account that funded the XML (AML); not a topic, all 4 are taken
+    pub amount: i128,
+    pub pay_token: Address, // token paid in; the amounts below are in it
+    pub cost_xml: i128,
+    pub seller_proceeds: i128, // paid to the listing's `proceeds_to`
+    pub fee_xml: i128,         // kept as the marketplace fee
+    pub conservation_xml: i128, // sent to the conservation fund; the three sum to `cost_xml`
+    pub is_primary: bool,
+    pub remaining: i128, // units still listed after this fill (0 = listing removed)
+    pub trade_id: u64,   // sequential across all listing purchases
+    pub settlement_id: BytesN<32>, // memo-ready reconciliation key, see `next_settlement`
+}
+
+/// Reference price published after every listing purchase, for contracts
+/// and indexers using the marketplace as a price oracle. Prices are per
+/// unit at the listing price scale; `vwap` and `volume` cover the current
+/// UTC day so far (see `daily_stats`).
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PriceFeedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    pub last_price: i128, // listing price of this trade
+    pub vwap: i128,       // volume-weighted average price today
+    pub volume: i128,     // units traded today
+    pub ledger: u32,      // ledger sequence of this trade
+}
+
+/// Credits minted to an issuer through the controller.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MintEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub issuer: Address,
+    #[topic]
+    pub region: Symbol,
+    pub amount: i128,
+    pub attestation_ref: Option<String>, // issuance document, for `mint_with_attestation`
+}
+
+/// Issuance document an attested mint was made against.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MintAttestation {
+    pub asset_code: Symbol,
+    pub issuer: Address,
+    pub amount: i128,
+    pub attestation_ref: String,
+    pub timestamp: u64,
+}
+
+/// Attestation in the shape external carbon registries ingest. Published
+/// next to `CarbonRetireEvent` when the asset has provenance recorded.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RegistryRetireEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub certificate_id: u64,
+    pub holder: Address,
+    pub amount: i128,
+    pub project_id: i64,
+    pub vintage_year: i32,
+    pub standard: Symbol,
+    pub serial_start: i64,
+    pub serial_end: i64,
+    pub metadata_uri: String,
+}
+
+/// On-chain record of a retirement, kept so buyers can prove offsets later.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RetirementCertificate {
+    pub id: u64,
+    pub asset_code: Symbol,
+    pub holder: Address,
+    pub amount: i128,
+    pub project_id: i64,
+    pub vintage_year: i32,
+    pub note: String,
+    pub timestamp: u64,
+    pub beneficiary_name: Option<String>, // off-chain organisation named on the certificate
+    pub retirement_reference: Option<String>, // external registry serial / reference
+    pub purpose: Option<Symbol>, // e.g. OFFSET / COMPLIANCE / VOLUNTARY, from the allowlist
+    pub owner: Address, // current owner; starts as `holder`, changed by `transfer_certificate`
+    pub transfer_locked_until: u64, // no transfers before this ledger timestamp (0 = none)
+}
+
+/// What `retire` would record right now, for confirmation screens.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RetirePreview {
+    pub certificate_id: u64, // id the certificate would get if retired now
+    pub asset_code: Symbol,
+    pub holder: Address,
+    pub amount: i128,
+    pub project_id: i64,
+    pub vintage_year: i32,
+    pub decimals: u32, // of the asset token, for rendering `amount`
+    pub region: Symbol,
+    pub standard: Symbol,
+    pub serial_start: i64,
+    pub serial_end: i64,
+    pub warnings: Vec<Symbol>, // empty if `retire` would succeed
+}
+
+/// The settlement (XML) token was set or replaced.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmlTokenChangedEvent {
+    #[topic]
+    pub caller: Address,
+    pub previous: Option<Address>,
+    pub xml_token: Address,
+}
+
+/// A basket was bought; `components` lists `(asset_code, amount, xml_cost)`
+/// per component.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BasketPurchaseEvent {
+    #[topic]
+    pub basket_code: Symbol,
+    #[topic]
+    pub buyer: Address,
+    pub amount: i128,
+    pub total_xml: i128,
+    pub components: Vec<(Symbol, i128, i128)>,
+}
+
+/// A retirement certificate changed owner.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CertTransferEvent {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub to: Address,
+    pub certificate_id: u64,
+}
+
+/// The contract admin proposed handing the role over to `proposed`.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminProposedEvent {
+    #[topic]
+    pub admin: Address,
+    pub proposed: Address,
+}
+
+/// A proposed contract admin accepted the role.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminChangedEvent {
+    #[topic]
+    pub previous: Address,
+    #[topic]
+    pub admin: Address,
+}
+
+/// Seller took unsold tokens back out of custody.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawInventoryEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub seller: Address,
+    pub amount: i128,
+    pub remaining: i128, // custody balance left, still listed or not
+}
+
+/// Emitted whenever a listing is removed before it sells out.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListingClosedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub seller: Address,
+    pub reason: String,
+    pub admin_forced: bool, // true for compliance takedowns
+}
+
+/// A buy drained a listing to zero ("your listing sold out"), with the
+/// listing's lifetime numbers.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListingConsumedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub seller: Address,
+    pub listing_id: u64,
+    pub final_buyer: Address,
+    pub total_volume: i128, // units sold over the listing's life, across updates
+}
+
+/// A seller rewrote an existing listing in place with `replace_listing`.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListingReplacedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub seller: Address,
+    pub listing_id: u64,
+    pub old_amount: i128,
+    pub old_price: i128,
+    pub new_amount: i128,
+    pub new_price: i128,
+}
+
+/// `verify_listing` shrank a non-custodial listing to what the seller's
+/// balance and allowance still back.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListingCappedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub seller: Address,
+    pub old_amount: i128,
+    pub new_amount: i128,
+}
+
+/// Summary of a `reprice_batch` run with `summarize`, replacing its
+/// per-listing events.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BatchListingEvent {
+    #[topic]
+    pub seller: Address,
+    pub count: u32,               // cancels plus new / updated listings
+    pub asset_codes: Vec<Symbol>, // every asset touched, once each
+}
+
+/// Contract admin put a listing under review or cleared it.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListingFlaggedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub seller: Address,
+    pub disputed: bool,
+}
+
+/// Emitted when a trade trips the single-trade size breaker and was
+/// allowed through on the admin's co-signature.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CircuitBreakerEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub buyer: Address,
+    pub cost_xml: i128,
+    pub threshold: i128,
+}
+
+/// Asset admin paused or resumed secondary trading for an asset.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssetTradingToggledEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    pub enabled: bool,
+    pub outstanding_supply: i128, // minted - retired at the time of the toggle
+}
+
+/// Contract admin put an asset under (or lifted) a compliance freeze.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssetFrozenEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    pub frozen: bool,
+    pub reason: String,
+}
+
+/// A role was granted to or revoked from `who`. `expires_at` is 0 for
+/// revocations and for grants without expiry.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RoleChangedEvent {
+    #[topic]
+    pub role: Symbol,
+    #[topic]
+    pub who: Address,
+    pub granted: bool,
+    pub expires_at: u64,
+}
+
+/// Contract admin froze or unfroze one account across all assets.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HolderFrozenEvent {
+    #[topic]
+    pub who: Address,
+    pub frozen: bool,
+}
+
+/// Contract admin paused (`resume_at` as passed to `pause`) or unpaused
+/// the whole contract.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractPausedEvent {
+    pub paused: bool,
+    pub resume_at: u64,
+}
+
+/// A buyer placed or replaced a resting limit bid.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LimitBidPlacedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub buyer: Address,
+    pub amount: i128,
+    pub limit_price: i128,
+    pub expires_at: u64,
+}
+
+/// A buyer withdrew their resting limit bid.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LimitBidCancelledEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub buyer: Address,
+    pub amount: i128, // units that were still wanted
+}
+
+/// A resting limit bid was removed without the buyer cancelling it.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BidClosedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub buyer: Address,
+    pub reason: String,
+}
+
+/// Rollup of an asset's finished UTC day, published once per day.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DailySummaryEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub day: u64,
+    pub volume: i128,
+    pub retired: i128,
+    pub trade_count: u32,
+}
+
+/// Emitted when `buy_with_xml_exact` returns the unspent part of a deposit.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RefundEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    #[topic]
+    pub buyer: Address,
+    pub deposited: i128,
+    pub refunded: i128,
+}
+
+/// How a seller's listings have ended so far, as a lightweight trust signal.
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SellerStats {
+    pub completed: u64,       // sold out through `buy_with_xml`
+    pub cancelled: u64,       // withdrawn by the seller
+    pub failed_approval: u64, // pruned because the controller lost its allowance
+}
+
+/// Contract-wide headline numbers for dashboards.
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GlobalStats {
+    pub total_assets: u64,    // distinct asset codes ever registered
+    pub active_listings: u64, // listings currently open
+    pub traded_volume: i128,  // lifetime carbon units traded, all assets
+    pub xml_volume: i128,     // lifetime XML paid, all assets
+    pub total_retired: i128,  // lifetime units retired, all assets
+}
+
+/// Which optional behaviours this deployment has configured, so a generic
+/// frontend can adapt. Each flag reflects whether the matching setting is
+/// present; auctions, escrow and limit bids are always available.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeatureFlags {
+    pub fees_enabled: bool,         // a marketplace fee and collector are set
+    pub primary_fee_override: bool, // primary sales use their own fee_bps
+    pub credit_fallback: bool,      // undeliverable proceeds become credits
+    pub retire_quorum: bool,        // large retirements need a compliance co-sign
+    pub retirement_nft: bool,       // retirements mint a proof NFT
+    pub retirement_oracle: bool,    // retirements must pass an external oracle
+    pub listing_expiry: bool,       // listings expire after `max_listing_duration`
+    pub price_cooldown: bool,       // sellers wait between price changes
+    pub matching_decimals: bool,    // registration requires XML's decimals
+    pub buyback_fees_waived: bool,  // issuer buybacks skip the marketplace fee
+    pub paused: bool,               // global trading halt in force
+}
+
+/// One asset's activity on one UTC day, rolled up by `emit_daily_summary`.
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DailyStats {
+    pub volume: i128,     // carbon units traded
+    pub xml_volume: i128, // XML paid
+    pub retired: i128,    // units retired
+    pub trade_count: u32, // purchases settled
+}
+
+/// One settled purchase as kept in the global trade log, so indexers that
+/// missed a `PurchaseEvent` can backfill it.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TradeRecord {
+    pub asset_code: Symbol,
+    pub buyer: Address,
+    pub seller: Address,
+    pub amount: i128,
+    pub price: i128, // listing price per unit at the time of the fill
+    pub ledger: u32,
+    pub trade_id: u64,
+}
+
+/// Simple listing: seller offers `amount` units of `asset_code` at `price` XML per unit.
+/// All values are i128 with 7 decimals (same as tokens).
+#[contracttype]
+#[derive(Clone)]
+pub struct Listing {
+    pub asset_code: Symbol,
+    pub seller: Address,
+    pub amount: i128,
+    pub price: i128, // XML base units per carbon base unit, divided by 10^price_scale
+    pub proceeds_to: Address, // who receives the XML; `seller` unless listed via `list_for`
+    pub listing_id: u64,      // per-seller id, kept across updates, never reused
+    pub is_primary: bool,     // seller is the asset's issuer (primary issuance sale)
+    pub custodial: bool,      // tokens held by the controller (`deposit_and_list`), not the seller
+    pub disputed: bool,       // under admin review: frozen for buys, updates and seller cancels
+    pub expires_at: u64,      // ledger timestamp the listing stops being buyable (0 = never)
+    pub starts_at: u64,       // ledger timestamp the listing becomes buyable (0 = immediately)
+    pub created_at: u64,      // ledger timestamp the listing was first created, kept across updates
+    pub filled: i128,         // units sold from this listing over its life, across updates
+    pub accepted_tokens: Map<Address, i128>, // extra settlement tokens -> price in that token
+}
+
+/// Buyer's resting order to take up to `amount` units at or below
+/// `limit_price`. Settles from the XML allowance the buyer granted the
+/// controller whenever a matching listing appears.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LimitBid {
+    pub asset_code: Symbol,
+    pub buyer: Address,
+    pub amount: i128,      // units still wanted; shrinks as fills happen
+    pub limit_price: i128, // highest price per unit, in listing price units
+    pub expires_at: u64,   // ledger timestamp the bid lapses (0 = never)
+    pub placed_at: u64,    // ledger timestamp of the last placement
+}
+
+/// Cost of buying from a listing plus whether the seller's side would
+/// currently settle, so clients can warn before asking the buyer to sign.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Quote {
+    pub cost_xml: i128,
+    pub fee_bps: u32,  // effective marketplace fee for the asset
+    pub fee_xml: i128, // part of `cost_xml` kept as the fee
+
+    pub seller_approval_ok: bool, // controller allowance covers `amount`
+    pub seller_balance_ok: bool,  // seller still holds `amount`
+}
+
+/// Every halt that can apply to an asset, for one authoritative status read.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssetStatus {
+    pub frozen: bool,         // compliance freeze: nothing moves, retirement included
+    pub trading_paused: bool, // asset admin paused trading; minting and retirement still work
+    pub closed: bool,         // terminal: no more listings, trades or mints
+    pub global_paused: bool,  // contract-wide pause in effect right now
+    pub tradable: bool,       // listings and buys would pass every halt check now
+}
+
+/// Simulated multi-seller fill: the per-seller legs plus their totals.
+/// `total_amount` is below the requested amount if the book is too thin.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FillPreview {
+    pub fills: Vec<(Address, i128, i128)>, // (seller, fill_amount, fill_cost)
+    pub total_amount: i128,
+    pub total_cost: i128,
+}
+
+/// What `buy_best_price` executed, leg by leg. The route must fill in
+/// full or the call reverts, so `shortfall` is always 0 today; it is kept
+/// so a partial-fill mode can report its unfilled remainder.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FillReceipt {
+    pub total_amount: i128,
+    pub total_cost: i128,
+    pub fills: Vec<(Address, i128, i128)>, // (seller, fill_amount, fill_cost)
+    pub shortfall: i128,                   // units requested but not bought
+}
+
+/// Descending-price sale: the per-unit price falls linearly from
+/// `start_price` at `start` to `end_price` at `end` (ledger timestamps).
+/// Settles like a listing, so the seller must approve the controller.
+#[contracttype]
+#[derive(Clone)]
+pub struct DutchAuction {
+    pub asset_code: Symbol,
+    pub seller: Address,
+    pub amount: i128,
+    pub start_price: i128,
+    pub end_price: i128,
+    pub start: u64,
+    pub end: u64,
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct AuctionBid {
+    pub bidder: Address,
+    pub amount_xml: i128, // total XML escrowed by this bidder
+}
+
+/// Ascending-bid sale. The seller's carbon and every bid's XML are held by
+/// the controller until `settle_auction` pays out after `end`.
+#[contracttype]
+#[derive(Clone)]
+pub struct EnglishAuction {
+    pub id: u64,
+    pub asset_code: Symbol,
+    pub seller: Address,
+    pub amount: i128,
+    pub min_bid: i128, // reserve, total XML for the whole lot
+    pub end: u64,
+    pub bids: Vec<AuctionBid>,
+    pub bid_token: Address, // settlement token at creation; escrow, refunds and payout use it
+}
+
+/// An asset's token contract is being replaced. Trading stays paused until
+/// `complete_migration` switches the asset over to `new_token`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenMigration {
+    pub old_token: Address,
+    pub new_token: Address,
+    pub snapshot_total: i128, // holder supply the admins claim to be carrying over
+    pub started_at: u64,
+}
+
+/// A token migration was started for an asset.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenMigrationEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    pub old_token: Address,
+    pub new_token: Address,
+    pub snapshot_total: i128,
+}
+
+/// A token migration finished and the asset now points at `new_token`.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenMigrationCompletedEvent {
+    #[topic]
+    pub asset_code: Symbol,
+    pub new_token: Address,
+}
+
+/// A bid was placed or raised on an English auction.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BidPlacedEvent {
+    #[topic]
+    pub auction_id: u64,
+    #[topic]
+    pub bidder: Address,
+    pub amount_xml: i128,
+}
+
+/// An English auction closed. `winner` is `None` when nobody bid and the
+/// carbon went back to the seller.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuctionSettledEvent {
+    #[topic]
+    pub auction_id: u64,
+    pub asset_code: Symbol,
+    pub seller: Address,
+    pub winner: Option<Address>,
+    pub amount: i128,
+    pub price_xml: i128,
+}
+
+/// A project's vintages were registered together by `register_vintages`.
+#[contractevent]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VintagesRegisteredEvent {
+    #[topic]
+    pub base_code: Symbol,
+    #[topic]
+    pub project_id: i64,
+    pub asset_codes: Vec<Symbol>, // in the order given
+}
+
+/// Every mutating entry point moves tokens first, then writes storage, and
+/// publishes its events last, so an event always describes the state the
+/// call leaves behind (e.g. `PurchaseEvent.remaining` matches the stored
+/// listing). `CircuitBreakerEvent` is the one exception: it records the
+/// admin co-signature during validation and carries no state.
+#[contractimpl]
+impl CarbonController {
+    /// Register an asset once you have deployed its token contract.
+    /// This is typically called by the marketplace admin. `provenance` names
+    /// the verification standard and the registry serial range the credits
+    /// were issued from (`serial_end >= serial_start`), which can't change
+    /// afterwards, and the date before which they can't be retired.
+    /// Re-registering an existing code also needs its current asset admin
+    /// and must keep the same token and provenance (the retirement lock may
+    /// only move later).
+    pub fn register_asset(
+        e: Env,
+        asset_code: Symbol,
+        project_id: i64,
+        vintage_year: i32,
+        token: Address,
+        admin: Address,
+        provenance: Provenance,
+    ) {
+        // Only the provided admin can (re)register
+        admin.require_auth();
+
+        register(&e, asset_code, project_id, vintage_year, token, admin, provenance);
+    }
+
+    /// Register several vintages of one project in a single atomic call,
+    /// each `(vintage_year, asset_code, token, provenance)` under the shared
+    /// `project_id` and `admin`, exactly as `register_asset` would.
+    /// `base_code` names the project family the vintages are announced
+    /// under in the `VintagesRegisteredEvent`. Vintage years must lie in
+    /// `MIN_VINTAGE_YEAR..=MAX_VINTAGE_YEAR`; asset codes must be distinct.
+    pub fn register_vintages(
+        e: Env,
+        admin: Address,
+        project_id: i64,
+        base_code: Symbol,
+        vintages: Vec<(i32, Symbol, Address, Provenance)>,
+    ) {
+        admin.require_auth();
+
+        if vintages.is_empty() {
+            panic!("no vintages given");
+        }
+        if vintages.len() > MAX_BATCH_SIZE {
+            panic!("batch too large");
+        }
+        let mut asset_codes = Vec::new(&e);
+        for (vintage_year, asset_code, _, _) in vintages.iter() {
+            if !(MIN_VINTAGE_YEAR..=MAX_VINTAGE_YEAR).contains(&vintage_year) {
+                panic!("vintage year {} out of range", vintage_year);
+            }
+            if asset_codes.contains(&asset_code) {
+                panic!("duplicate asset code in batch");
+            }
+            asset_codes.push_back(asset_code);
+        }
+
+        for (vintage_year, asset_code, token, provenance) in vintages.iter() {
+            register(
+                &e,
+                asset_code,
+                project_id,
+                vintage_year,
+                token,
+                admin.clone(),
+                provenance,
+            );
+        }
+
+        VintagesRegisteredEvent {
+            base_code,
+            project_id,
+            asset_codes,
+        }
+        .publish(&e);
+    }
+
+    /// One-shot provisioning: deploy the Stellar Asset Contract for
+    /// `serialized_asset` (the classic `Asset` XDR) and register the asset
+    /// against it, returning the token address. A SAC's address is derived
+    /// from its asset, so the asset bytes take the place of a deploy salt.
+    /// Gated by `admin` exactly like `register_asset`. The SAC's admin is the
+    /// classic issuer, which still has to `set_admin` the controller before
+    /// `mint_to_issuer` works.
+    pub fn register_and_deploy(
+        e: Env,
+        asset_code: Symbol,
+        project_id: i64,
+        vintage_year: i32,
+        admin: Address,
+        provenance: Provenance,
+        serialized_asset: Bytes,
+    ) -> Address {
+        let token = e.deployer().with_stellar_asset(serialized_asset).deploy();
+
+        Self::register_asset(
+            e,
+            asset_code,
+            project_id,
+            vintage_year,
+            token.clone(),
+            admin,
+            provenance,
+        );
+        token
+    }
+
+    /// Launch a new asset in one transaction: register it with its
+    /// `provenance`, mint `initial_mint` to `issuer` and list `list_amount`
+    /// of it at `price` from the issuer's wallet (the issuer still has to
+    /// `approve` the controller before buys can settle). `admin` signs for the
+    /// registration and mint, `issuer` for the listing. Any failed step
+    /// aborts the whole call. Returns the new listing id.
+    pub fn launch_asset(
+        e: Env,
+        admin: Address,
+        issuer: Address,
+        asset_code: Symbol,
+        project_id: i64,
+        vintage_year: i32,
+        token: Address,
+        provenance: Provenance,
+        initial_mint: i128,
+        list_amount: i128,
+        price: i128,
+    ) -> u64 {
+        admin.require_auth();
+        issuer.require_auth();
+
+        if e.storage().instance().has(&DataKey::Asset(asset_code.clone())) {
+            panic!("asset already registered");
+        }
+        if initial_mint <= 0 {
+            panic!("initial mint must be positive");
+        }
+        if list_amount > initial_mint {
+            panic!("list amount exceeds initial mint");
+        }
+
+        register(
+            &e,
+            asset_code.clone(),
+            project_id,
+            vintage_year,
+            token,
+            admin,
+            provenance,
+        );
+        let meta = read_asset(&e, asset_code.clone());
+        mint_credits(&e, asset_code.clone(), &meta, &issuer, initial_mint, None);
+
+        let listing_id = put_listing(
+            &e,
+            issuer.clone(),
+            issuer,
+            asset_code.clone(),
+            list_amount,
+            price,
+            false,
+            None,
+        );
+        match_orders(&e, &asset_code);
+        listing_id
+    }
+
+    /// Who held the asset's admin role and since when, as
+    /// `(admin, timestamp)`, oldest first, capped at the last 20 changes.
+    /// The admin only changes through (re-)registration.
+    pub fn admin_history(e: Env, asset_code: Symbol) -> Vec<(Address, u64)> {
+        e.storage()
+            .instance()
+            .get(&DataKey::AdminHistory(asset_code))
+            .unwrap_or_else(|| Vec::new(&e))
+    }
+
+    /// Asset admin points the asset at its off-chain verification documents
+    /// (e.g. a Verra / Gold Standard PDF).
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_metadata_uri(e: Env, asset_code: Symbol, signers: Vec<Address>, uri: String) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        require_string_len(&uri, MAX_URI_LEN, "metadata_uri");
+
+        meta.metadata_uri = uri;
+        write_asset(&e, asset_code, &meta);
+    }
+
+    /// Mint tokens to issuer when a tokenization_request is APPROVED.
+    /// Only the configured admin for that asset can call this.
+    /// The admin's `require_auth` already covers exactly this invocation:
+    /// Soroban binds it to the contract, the function and all of
+    /// `(asset_code, issuer, amount)`, so the signature can't be spent on a
+    /// different issuer or amount, even within one transaction.
+    pub fn mint_to_issuer(e: Env, asset_code: Symbol, issuer: Address, amount: i128) {
+        let meta = read_asset(&e, asset_code.clone());
+        // Require marketplace admin signature (enough on its own for 1-of-1 assets)
+        let signers = Vec::from_array(&e, [meta.admin.clone()]);
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        mint_credits(&e, asset_code, &meta, &issuer, amount, None);
+    }
+
+    /// `mint_to_issuer` that ties the new supply to its issuance document.
+    /// `attestation_ref` (e.g. a registry document hash or URI) is stored
+    /// under a new mint id and carried in the `MintEvent`. Each reference can
+    /// back only one mint, so one verification can't be issued twice.
+    /// Returns the mint id.
+    pub fn mint_with_attestation(
+        e: Env,
+        asset_code: Symbol,
+        issuer: Address,
+        amount: i128,
+        attestation_ref: String,
+    ) -> u64 {
+        let meta = read_asset(&e, asset_code.clone());
+        let signers = Vec::from_array(&e, [meta.admin.clone()]);
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        require_string_len(&attestation_ref, MAX_URI_LEN, "attestation_ref");
+        if attestation_ref.is_empty() {
+            panic!("attestation_ref must not be empty");
+        }
+        let used_key = ExtKey::UsedAttestation(attestation_ref.clone());
+        if e.storage().persistent().has(&used_key) {
+            panic!("attestation already used");
+        }
+
+        let mint_id: u64 = e
+            .storage()
+            .instance()
+            .get(&ExtKey::NextMintId)
+            .unwrap_or(0);
+        e.storage()
+            .instance()
+            .set(&ExtKey::NextMintId, &(mint_id + 1));
+        write_persistent(&e, &used_key, &mint_id);
+        write_persistent(
+            &e,
+            &ExtKey::MintAttestation(mint_id),
+            &MintAttestation {
+                asset_code: asset_code.clone(),
+                issuer: issuer.clone(),
+                amount,
+                attestation_ref: attestation_ref.clone(),
+                timestamp: e.ledger().timestamp(),
+            },
+        );
+
+        mint_credits(&e, asset_code, &meta, &issuer, amount, Some(attestation_ref));
+        mint_id
+    }
+
+    /// The issuance document behind an attested mint.
+    pub fn mint_attestation(e: Env, mint_id: u64) -> MintAttestation {
+        e.storage()
+            .persistent()
+            .get(&ExtKey::MintAttestation(mint_id))
+            .unwrap_or_else(|| panic!("mint attestation not found"))
+    }
+
+    /// Mint id an attestation reference was used for, if any.
+    pub fn attestation_mint(e: Env, attestation_ref: String) -> Option<u64> {
+        e.storage()
+            .persistent()
+            .get(&ExtKey::UsedAttestation(attestation_ref))
+    }
+
+    /// `mint_to_issuer` for assets guarded by an m-of-n admin set: every
+    /// address in `signers` must sign and they must reach the threshold.
+    pub fn mint_to_issuer_multisig(
+        e: Env,
+        asset_code: Symbol,
+        signers: Vec<Address>,
+        issuer: Address,
+        amount: i128,
+    ) {
+        let meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        mint_credits(&e, asset_code, &meta, &issuer, amount, None);
+    }
+
+    /// Replace the asset's admin signer set. Needs a quorum of the current
+    /// set, so a lone admin can't undo a multisig once it's in place.
+    pub fn set_asset_admins(
+        e: Env,
+        asset_code: Symbol,
+        signers: Vec<Address>,
+        admins: Vec<Address>,
+        threshold: u32,
+    ) {
+        let meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        if admins.is_empty() || admins.len() > MAX_ASSET_ADMINS {
+            panic!("invalid admin set size");
+        }
+        for (i, admin) in admins.iter().enumerate() {
+            if admins.first_index_of(&admin) != Some(i as u32) {
+                panic!("duplicate admin");
+            }
+        }
+        if threshold == 0 || threshold > admins.len() {
+            panic!("invalid threshold");
+        }
+
+        e.storage()
+            .instance()
+            .set(&DataKey::AssetAdmins(asset_code), &(admins, threshold));
+    }
+
+    /// Admin signer set and threshold for an asset (1-of-1 by default).
+    pub fn asset_admins(e: Env, asset_code: Symbol) -> (Vec<Address>, u32) {
+        let meta = read_asset(&e, asset_code.clone());
+        read_asset_admins(&e, asset_code, &meta)
+    }
+
+    /// Same as `mint_to_issuer`, but the receiving issuer must co-sign so they
+    /// explicitly consent to taking custody of the minted credits.
+    pub fn mint_to_issuer_cosigned(e: Env, asset_code: Symbol, issuer: Address, amount: i128) {
+        let meta = read_asset(&e, asset_code.clone());
+        let signers = Vec::from_array(&e, [meta.admin.clone()]);
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+        issuer.require_auth();
+
+        mint_credits(&e, asset_code, &meta, &issuer, amount, None);
+    }
+
+    /// Retire carbon credits by burning tokens from the holder (or moving them
+    /// to the asset's retirement sink if its token can't burn).
+    /// The holder must sign the transaction.
+    /// `beneficiary_name` names the organisation the offset is claimed for
+    /// (e.g. "Acme Corp FY24") when that differs from the retiring wallet.
+    /// `retirement_reference` is the external registry serial / reference
+    /// being retired; each one can only be retired once, so the same credit
+    /// can't be claimed twice.
+    /// `purpose` is an optional structured reason code from the accepted
+    /// list (see `retire_purposes`), kept next to the free-form `note` so
+    /// indexers can aggregate by purpose.
+    /// Returns the id of the `RetirementCertificate` it issues.
+    pub fn retire(
+        e: Env,
+        asset_code: Symbol,
+        from: Address,
+        amount: i128,
+        note: String,
+        beneficiary_name: Option<String>,
+        retirement_reference: Option<String>,
+        purpose: Option<Symbol>,
+    ) -> u64 {
+        // Holder must authorize the burn
+        from.require_auth();
+
+        retire_credits(
+            &e,
+            asset_code,
+            from,
+            amount,
+            note,
+            beneficiary_name,
+            retirement_reference,
+            purpose,
+            false,
+            false,
+        )
+    }
+
+    /// Units `who` has retired over its lifetime across every asset and
+    /// project: the headline number for an offset report. Auto-retired
+    /// purchases count for the account they were bought for.
+    pub fn total_retired_by(e: Env, who: Address) -> i128 {
+        read_total_retired_by(&e, &who)
+    }
+
+    /// Treasury opt-in: credits `who` buys through `buy_with_xml`, or is
+    /// sent through `buy_for`, are retired in the same call with `note`
+    /// instead of landing in its balance. The asset's `retire_fee`, if any,
+    /// is drawn from `who`'s XML allowance to the controller.
+    pub fn set_auto_retire(e: Env, who: Address, note: String) {
+        who.require_auth();
+
+        require_string_len(&note, MAX_STRING_LEN, "note");
+        write_persistent(&e, &ExtKey::AutoRetire(who), &note);
+    }
+
+    /// Opt back out of `set_auto_retire`.
+    pub fn clear_auto_retire(e: Env, who: Address) {
+        who.require_auth();
+
+        e.storage().persistent().remove(&ExtKey::AutoRetire(who));
+    }
+
+    /// Retirement note `who` auto-retires purchases with, if opted in.
+    pub fn auto_retire(e: Env, who: Address) -> Option<String> {
+        read_auto_retire(&e, &who)
+    }
+
+    /// Simulate `retire` without burning anything: the certificate and event
+    /// fields it would produce, plus warning codes for anything that would
+    /// make it fail (empty if it would succeed): `AMOUNT`, `BELOW_MIN`,
+    /// `LOCKED`, `NO_PATH` (token can't burn and no sink), `BALANCE`,
+    /// `FROZEN` (holder deauthorized on a SAC), `FEE` (the asset's
+    /// `retire_fee` can't be collected), `INVALID` (the retirement oracle
+    /// rejects the credits), `QUORUM` (above the retire quorum threshold, so
+    /// the compliance signer must co-sign).
+    /// Keep in step with the checks in `retire`.
+    pub fn preview_retire(
+        e: Env,
+        asset_code: Symbol,
+        from: Address,
+        amount: i128,
+    ) -> RetirePreview {
+        let meta = read_asset(&e, asset_code.clone());
+        let warnings = retire_warnings(&e, &asset_code, &meta, &from, amount);
+
+        RetirePreview {
+            certificate_id: e
+                .storage()
+                .instance()
+                .get(&DataKey::NextCertificateId)
+                .unwrap_or(0),
+            asset_code,
+            holder: from,
+            amount,
+            project_id: meta.project_id,
+            vintage_year: meta.vintage_year,
+            decimals: TokenClient::new(&e, &meta.token).decimals(),
+            region: meta.region,
+            standard: meta.standard,
+            serial_start: meta.serial_start,
+            serial_end: meta.serial_end,
+            warnings,
+        }
+    }
+
+    /// Best-effort batch retirement of `(asset_code, amount)` items: each item
+    /// that would fail (unknown asset, balance, lock, minimum, frozen
+    /// holder...) is skipped instead of reverting the whole call, the rest
+    /// are retired with one certificate each. Items above the retire quorum
+    /// threshold are skipped too, since the co-signature can't be checked
+    /// without reverting; retire those with `retire`. Returns
+    /// `(asset_code, retired)` per item, in order.
+    pub fn retire_batch_best_effort(
+        e: Env,
+        from: Address,
+        items: Vec<(Symbol, i128)>,
+        note: String,
+    ) -> Vec<(Symbol, bool)> {
+        from.require_auth();
+
+        if items.len() > MAX_BATCH_SIZE {
+            panic!("batch too large");
+        }
+        // A bad note would fail every item, so reject the call outright
+        require_string_len(&note, MAX_STRING_LEN, "note");
+
+        let mut results = Vec::new(&e);
+        for (asset_code, amount) in items.iter() {
+            let meta: Option<CarbonAssetMeta> = e
+                .storage()
+                .instance()
+                .get(&DataKey::Asset(asset_code.clone()));
+            let retired = match meta {
+                Some(meta) if retire_warnings(&e, &asset_code, &meta, &from, amount).is_empty() => {
+                    retire_credits(
+                        &e,
+                        asset_code.clone(),
+                        from.clone(),
+                        amount,
+                        note.clone(),
+                        None,
+                        None,
+                        None,
+                        false,
+                        false,
+                    );
+                    true
+                }
+                _ => false,
+            };
+            results.push_back((asset_code, retired));
+        }
+        results
+    }
+
+    /// Retire `amount` from any vintages of a project the holder owns,
+    /// oldest vintage first, so a buyer offsetting "against project 42"
+    /// needn't pick an asset code. Vintages still retirement-locked, without
+    /// a retirement path, frozen (asset, or the holder on its SAC), or whose
+    /// slice would fall below their minimum retirement are skipped. Panics
+    /// unless the remaining vintages cover the whole amount. Issues one certificate per asset
+    /// touched and returns their ids.
+    pub fn retire_project(
+        e: Env,
+        from: Address,
+        project_id: i64,
+        amount: i128,
+        note: String,
+    ) -> Vec<u64> {
+        from.require_auth();
+
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+
+        // Oldest vintage first (stable for equal years)
+        let mut ordered: Vec<(i32, Symbol)> = Vec::new(&e);
+        let index = Index::ProjectAssets(project_id);
+        let mut codes: Vec<Symbol> = Vec::new(&e);
+        for page_no in 0..index_pages(&e, &index) {
+            codes.append(&read_index_page(&e, &index, page_no));
+        }
+        for code in codes.iter() {
+            let meta = read_asset(&e, code.clone());
+            let retirable = meta.burnable || meta.retire_sink.is_some();
+            if !retirable
+                || e.ledger().timestamp() < meta.retire_not_before
+                || is_asset_frozen(&e, &code)
+                || matches!(
+                    StellarAssetClient::new(&e, &meta.token).try_authorized(&from),
+                    Ok(Ok(false))
+                )
+            {
+                continue;
+            }
+            match ordered
+                .iter()
+                .position(|(year, _)| year > meta.vintage_year)
+            {
+                Some(i) => ordered.insert(i as u32, (meta.vintage_year, code)),
+                None => ordered.push_back((meta.vintage_year, code)),
+            }
+        }
+
+        let mut parts: Vec<(Symbol, i128)> = Vec::new(&e);
+        let mut remaining = amount;
+        for (_, code) in ordered.iter() {
+            if remaining == 0 {
+                break;
+            }
+            let token = read_asset(&e, code.clone()).token;
+            let take = TokenClient::new(&e, &token).balance(&from).min(remaining);
+            if take > 0 && take >= read_min_retire_amount(&e, code.clone()) {
+                parts.push_back((code, take));
+                remaining -= take;
+            }
+        }
+        if remaining > 0 {
+            panic!(
+                "insufficient project balance to retire: short by {}",
+                remaining
+            );
+        }
+
+        let mut certificate_ids = Vec::new(&e);
+        for (code, take) in parts.iter() {
+            certificate_ids.push_back(retire_credits(
+                &e,
+                code,
+                from.clone(),
+                take,
+                note.clone(),
+                None,
+                None,
+                None,
+                false,
+                false,
+            ));
+        }
+        certificate_ids
+    }
+
+    /// Asset codes registered under a project, one per vintage, one page at
+    /// a time (`limit` capped at 50). Registration order, except that moving
+    /// an asset to another project fills its slot with the newest code.
+    pub fn assets_for_project(e: Env, project_id: i64, start: u32, limit: u32) -> Vec<Symbol> {
+        index_range(&e, &Index::ProjectAssets(project_id), start, limit)
+    }
+
+    /// Current owner of a certificate hands it to `to`, e.g. to gift or sell
+    /// the proof of an offset. The retirement record itself (`holder`,
+    /// amount, asset) is unchanged. Rejected while the certificate is inside
+    /// its `transfer_locked_until` window.
+    pub fn transfer_certificate(e: Env, from: Address, to: Address, certificate_id: u64) {
+        from.require_auth();
+
+        let mut certificate = read_certificate(&e, certificate_id);
+        if certificate.owner != from {
+            panic!("not the certificate owner");
+        }
+        if from == to {
+            panic!("cannot transfer to self");
+        }
+        if e.ledger().timestamp() < certificate.transfer_locked_until {
+            panic!("certificate transfer locked");
+        }
+
+        certificate.owner = to.clone();
+        write_persistent(&e, &DataKey::Certificate(certificate_id), &certificate);
+        remove_cert_of(&e, &from, certificate_id);
+        add_cert_of(&e, &to, certificate_id);
+
+        CertTransferEvent {
+            from,
+            to,
+            certificate_id,
+        }
+        .publish(&e);
+    }
+
+    /// Certificate ids `owner` currently holds, one page at a time
+    /// (`limit` capped at 50). Ids are listed in the order acquired, except
+    /// that transferring one out moves the newest into its place.
+    pub fn certificates_of(e: Env, owner: Address, start: u32, limit: u32) -> Vec<u64> {
+        index_range(&e, &Index::OwnedCerts(owner), start, limit)
+    }
+
+    /// Contract admin points retirements at a proof-of-retirement NFT
+    /// contract (see `RetirementNft`); every retirement then mints a proof
+    /// to the holder after the burn, and reverts if that mint fails. `None`
+    /// turns the bridge off.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_retirement_nft(e: Env, caller: Address, nft_contract: Option<Address>) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        match nft_contract {
+            Some(nft) => e
+                .storage()
+                .instance()
+                .set(&ExtKey::RetirementNftContract, &nft),
+            None => e
+                .storage()
+                .instance()
+                .remove(&ExtKey::RetirementNftContract),
+        }
+    }
+
+    /// Proof-of-retirement NFT contract retirements mint on, if any.
+    pub fn retirement_nft(e: Env) -> Option<Address> {
+        e.storage()
+            .instance()
+            .get(&ExtKey::RetirementNftContract)
+    }
+
+    /// Contract admin gates every retirement on an external verification
+    /// oracle (see `RetirementOracle`): before burning, the controller asks
+    /// `is_valid(asset_code, amount)` and reverts on `false` or if the
+    /// oracle call fails. `None` turns the gate off.
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn set_retirement_oracle(e: Env, caller: Address, oracle: Option<Address>) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        match oracle {
+            Some(oracle) => e
+                .storage()
+                .instance()
+                .set(&ExtKey::RetirementOracle, &oracle),
+            None => e.storage().instance().remove(&ExtKey::RetirementOracle),
+        }
+    }
+
+    /// Oracle retirements must pass, if any.
+    pub fn retirement_oracle(e: Env) -> Option<Address> {
+        read_retirement_oracle(&e)
+    }
+
+    /// Contract admin sets how long after issue new certificates stay
+    /// non-transferable (0 = immediately transferable). Certificates already
+    /// issued keep their lock.
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn set_cert_transfer_lock(e: Env, caller: Address, seconds: u64) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        e.storage()
+            .instance()
+            .set(&ExtKey::CertTransferLock, &seconds);
+    }
+
+    /// Seconds new certificates stay non-transferable after issue.
+    pub fn cert_transfer_lock(e: Env) -> u64 {
+        read_cert_transfer_lock(&e)
+    }
+
+    /// Certificate ids issued on UTC day `day` (ledger timestamp / 86400),
+    /// oldest first, one page at a time (`limit` capped at 50). Exporters
+    /// walk the days of their range, and the pages of each day.
+    pub fn certificates_in_day(e: Env, day: u64, start: u32, limit: u32) -> Vec<u64> {
+        index_range(&e, &Index::CertsByDay(day), start, limit)
+    }
+
+    /// Certificate ids issued for a project vintage, oldest first, one page
+    /// at a time (`limit` capped at 50). Resolve them with `get_certificate`.
+    pub fn certificates_for_vintage(
+        e: Env,
+        project_id: i64,
+        vintage_year: i32,
+        start: u32,
+        limit: u32,
+    ) -> Vec<u64> {
+        index_range(&e, &Index::CertsByVintage(project_id, vintage_year), start, limit)
+    }
+
+    /// Whether an external registry reference has already been retired here.
+    pub fn is_reference_retired(e: Env, reference: String) -> bool {
+        e.storage()
+            .persistent()
+            .has(&DataKey::UsedReference(reference))
+    }
+
+    /// Retirement certificate issued by `retire`.
+    pub fn get_certificate(e: Env, certificate_id: u64) -> RetirementCertificate {
+        read_certificate(&e, certificate_id)
+    }
+
+    /// Simple read method to debug / inspect from frontend
+    pub fn asset_info(e: Env, asset_code: Symbol) -> CarbonAssetMeta {
+        read_asset(&e, asset_code)
+    }
+
+    /// Contract admin deprecates `old_code` in favour of `new_code`: reads
+    /// of `old_code` without their own entry resolve to `new_code`, and
+    /// trading under `old_code` is refused so clients move to the canonical
+    /// code. `new_code` must be registered and not itself an alias, which
+    /// keeps every redirect one level deep and rules out cycles.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn alias_asset(e: Env, caller: Address, old_code: Symbol, new_code: Symbol) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        if old_code == new_code {
+            panic!("asset cannot alias itself");
+        }
+        if read_alias(&e, &new_code).is_some() {
+            panic!("alias target is itself an alias");
+        }
+        if !e
+            .storage()
+            .instance()
+            .has(&DataKey::Asset(new_code.clone()))
+        {
+            panic!("alias target not registered");
+        }
+        e.storage()
+            .instance()
+            .set(&DataKey::Alias(old_code), &new_code);
+    }
+
+    /// Canonical code `asset_code` redirects to, if it has been deprecated.
+    pub fn asset_alias(e: Env, asset_code: Symbol) -> Option<Symbol> {
+        read_alias(&e, &asset_code)
+    }
+
+    /// Whether `who` is the admin of `asset_code`; false for unknown assets.
+    pub fn is_asset_admin(e: Env, asset_code: Symbol, who: Address) -> bool {
+        e.storage()
+            .instance()
+            .get::<DataKey, CarbonAssetMeta>(&DataKey::Asset(asset_code))
+            .is_some_and(|meta| meta.admin == who)
+    }
+
+    /// Asset token's total supply, or `None` if the token doesn't implement
+    /// a `total_supply` view.
+    pub fn token_total_supply(e: Env, asset_code: Symbol) -> Option<i128> {
+        let meta = read_asset(&e, asset_code);
+        read_total_supply(&e, &meta.token)
+    }
+
+    /// Lifetime units minted for an asset through the controller.
+    pub fn minted(e: Env, asset_code: Symbol) -> i128 {
+        read_counter(&e, &DataKey::Minted(asset_code))
+    }
+
+    /// Lifetime units retired for an asset.
+    pub fn total_retired(e: Env, asset_code: Symbol) -> i128 {
+        read_counter(&e, &DataKey::TotalRetired(asset_code))
+    }
+
+    /// "Most retired" leaderboard, one page of the asset registry at a time:
+    /// ranks the `limit` (capped at 50) assets registered from position
+    /// `start` and returns those with any retirements as
+    /// `(asset_code, total_retired)`, highest first. Ties keep registration
+    /// order. Clients walk the registry and merge the pages.
+    pub fn top_assets_by_retirement(e: Env, start: u32, limit: u32) -> Vec<(Symbol, i128)> {
+        let codes: Vec<Symbol> = index_range(&e, &Index::AssetCodes, start, limit);
+
+        let mut top: Vec<(Symbol, i128)> = Vec::new(&e);
+        for code in codes.iter() {
+            let retired = read_counter(&e, &DataKey::TotalRetired(code.clone()));
+            if retired == 0 {
+                continue;
+            }
+            match top.iter().position(|(_, r)| r < retired) {
+                Some(i) => top.insert(i as u32, (code, retired)),
+                None => top.push_back((code, retired)),
+            }
+        }
+        top
+    }
+
+    /// `(minted_counter, token_total_supply, total_retired)` so operators can
+    /// check `token_total_supply == minted - retired` and spot out-of-band
+    /// mints or burns. Supply is `None` if the token has no supply view.
+    pub fn supply_reconciliation(e: Env, asset_code: Symbol) -> (i128, Option<i128>, i128) {
+        let meta = read_asset(&e, asset_code.clone());
+        (
+            read_counter(&e, &DataKey::Minted(asset_code.clone())),
+            read_total_supply(&e, &meta.token),
+            read_counter(&e, &DataKey::TotalRetired(asset_code)),
+        )
+    }
+
+    /// Asset admin pushes the retirement lock (see `Provenance`) back to a
+    /// later verification date (ledger timestamp). The lock can't be moved
+    /// earlier or lifted.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_retire_not_before(
+        e: Env,
+        asset_code: Symbol,
+        signers: Vec<Address>,
+        retire_not_before: u64,
+    ) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        if retire_not_before < meta.retire_not_before {
+            panic!("retirement lock can only be extended");
+        }
+        meta.retire_not_before = retire_not_before;
+        write_asset(&e, asset_code, &meta);
+    }
+
+    /// Asset admin permanently closes an asset (fully retired or delisted).
+    /// Listing, buying, auctions and minting stop; reads and retirement of
+    /// credits still in circulation keep working. There is no reopen.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn close_asset(e: Env, asset_code: Symbol, signers: Vec<Address>, reason: String) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        if meta.closed {
+            panic!("asset already closed");
+        }
+        require_string_len(&reason, MAX_STRING_LEN, "reason");
+        meta.closed = true;
+        write_asset(&e, asset_code.clone(), &meta);
+
+        AssetClosedEvent {
+            outstanding_supply: outstanding_supply(&e, &asset_code),
+            asset_code,
+            reason,
+        }
+        .publish(&e);
+    }
+
+    /// Asset admin tags the asset with a marketplace region (e.g. "EU"), which
+    /// retire, purchase and mint events carry as a topic. At most 9
+    /// characters; an empty symbol clears it.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_region(e: Env, asset_code: Symbol, signers: Vec<Address>, region: Symbol) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        // Symbols longer than 9 chars don't fit in a Val and become host objects
+        if region.to_val().is_object() {
+            panic!("region too long");
+        }
+
+        meta.region = region;
+        write_asset(&e, asset_code, &meta);
+    }
+
+    /// Asset admin caps how many units any single holder may reach through
+    /// mints and marketplace buys (listings and Dutch auctions). 0 removes
+    /// the cap. Tokens moved peer-to-peer outside the controller aren't seen.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_max_holder_balance(
+        e: Env,
+        asset_code: Symbol,
+        signers: Vec<Address>,
+        max_holder_balance: i128,
+    ) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        if max_holder_balance < 0 {
+            panic!("max_holder_balance must not be negative");
+        }
+
+        meta.max_holder_balance = max_holder_balance;
+        write_asset(&e, asset_code, &meta);
+    }
+
+    /// Asset admin routes `conservation_bps` of every listing sale to a
+    /// conservation fund. Like the marketplace fee it comes out of the
+    /// seller's proceeds, so the buyer never pays more. Capped at 10%; pass
+    /// 0 and `None` to stop.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_conservation(
+        e: Env,
+        asset_code: Symbol,
+        signers: Vec<Address>,
+        conservation_bps: u32,
+        conservation_fund: Option<Address>,
+    ) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        if conservation_bps > MAX_CONSERVATION_BPS {
+            panic!("conservation_bps above maximum of {}", MAX_CONSERVATION_BPS);
+        }
+        if conservation_bps > 0 && conservation_fund.is_none() {
+            panic!("conservation fund required");
+        }
+
+        meta.conservation_bps = conservation_bps;
+        meta.conservation_fund = conservation_fund;
+        write_asset(&e, asset_code.clone(), &meta);
+        validate_bps_sum(&e, &asset_code, "conservation_bps");
+    }
+
+    /// Asset admin charges a flat `retire_fee` (XML base units) on every
+    /// retirement of the asset to fund registry operations, separate from
+    /// trading fees. It is pulled from the retiring holder into the fee
+    /// collector before the burn, so the holder must approve the controller
+    /// for it; the retirement fails if it can't be collected. 0 = free.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_retire_fee(e: Env, asset_code: Symbol, signers: Vec<Address>, retire_fee: i128) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        if retire_fee < 0 {
+            panic!("retire_fee cannot be negative");
+        }
+
+        meta.retire_fee = retire_fee;
+        write_asset(&e, asset_code, &meta);
+    }
+
+    /// Asset admin sets the smallest amount a single retirement may burn,
+    /// to keep dust out of retirement events and certificates.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_min_retire_amount(
+        e: Env,
+        asset_code: Symbol,
+        signers: Vec<Address>,
+        min_amount: i128,
+    ) {
+        let meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        if min_amount <= 0 {
+            panic!("min_amount must be positive");
+        }
+        e.storage()
+            .instance()
+            .set(&DataKey::MinRetireAmount(asset_code), &min_amount);
+    }
+
+    /// Smallest amount accepted by `retire` (defaults to 1).
+    pub fn min_retire_amount(e: Env, asset_code: Symbol) -> i128 {
+        read_min_retire_amount(&e, asset_code)
+    }
+
+    /// Asset admin sets how many seconds a mint recipient must wait after
+    /// their latest mint before listing or auctioning the asset (0 = no
+    /// lockup, the default). Applies to mints made before it was set too.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_mint_lockup(e: Env, asset_code: Symbol, signers: Vec<Address>, seconds: u64) {
+        let meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        e.storage()
+            .instance()
+            .set(&ExtKey::MintLockup(asset_code), &seconds);
+    }
+
+    /// Seconds a mint recipient waits before listing the asset.
+    pub fn mint_lockup(e: Env, asset_code: Symbol) -> u64 {
+        read_mint_lockup(&e, &asset_code)
+    }
+
+    /// Timestamp of `holder`'s latest mint of the asset, if any.
+    pub fn minted_at(e: Env, asset_code: Symbol, holder: Address) -> Option<u64> {
+        read_minted_at(&e, &asset_code, &holder)
+    }
+
+    /// Asset admin names the unit one whole token represents (e.g. `TCO2E`,
+    /// `MWH`), for impact displays.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_unit_label(e: Env, asset_code: Symbol, signers: Vec<Address>, label: Symbol) {
+        let meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        e.storage()
+            .instance()
+            .set(&ExtKey::UnitLabel(asset_code), &label);
+    }
+
+    /// Unit one whole token of the asset represents (defaults to `TCO2E`).
+    pub fn unit_label(e: Env, asset_code: Symbol) -> Symbol {
+        read_unit_label(&e, &asset_code)
+    }
+
+    /// Asset admin declares whether the token supports `burn`. Non-burnable
+    /// tokens retire by transfer to `retire_sink`; without a sink, `retire`
+    /// fails up front instead of deep inside the token.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_retirement_mode(
+        e: Env,
+        asset_code: Symbol,
+        signers: Vec<Address>,
+        burnable: bool,
+        retire_sink: Option<Address>,
+    ) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        meta.burnable = burnable;
+        meta.retire_sink = retire_sink;
+        write_asset(&e, asset_code, &meta);
+    }
+
+    /// Start replacing an asset's token contract (e.g. SAC -> SEP-41).
+    /// Needs both the asset's admin quorum and the contract admin. Records the old
+    /// token, the claimed holder snapshot and the new token, and pauses
+    /// trading on the asset until `complete_migration`.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn migrate_asset_token(
+        e: Env,
+        caller: Address,
+        asset_code: Symbol,
+        signers: Vec<Address>,
+        new_token: Address,
+        snapshot_total: i128,
+    ) {
+        let meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        if has_pending_migration(&e, asset_code.clone()) {
+            panic!("migration already pending");
+        }
+        if new_token == meta.token {
+            panic!("asset already uses this token");
+        }
+        if read_counter(&e, &DataKey::Custodied(asset_code.clone())) > 0 {
+            panic!("custodied inventory must be withdrawn before migrating");
+        }
+        if read_counter(&e, &DataKey::EscrowedCarbon(asset_code.clone())) > 0 {
+            panic!("open English auctions must settle before migrating");
+        }
+        if snapshot_total < 0 {
+            panic!("snapshot_total must not be negative");
+        }
+        require_sane_decimals(&e, &new_token);
+
+        let migration = TokenMigration {
+            old_token: meta.token,
+            new_token: new_token.clone(),
+            snapshot_total,
+            started_at: e.ledger().timestamp(),
+        };
+        e.storage()
+            .instance()
+            .set(&DataKey::Migration(asset_code.clone()), &migration);
+
+        TokenMigrationEvent {
+            asset_code,
+            old_token: migration.old_token,
+            new_token,
+            snapshot_total,
+        }
+        .publish(&e);
+    }
+
+    /// Finish a pending migration: point the asset at the new token and
+    /// resume trading.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn complete_migration(e: Env, caller: Address, asset_code: Symbol, signers: Vec<Address>) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        let key = DataKey::Migration(asset_code.clone());
+        let migration: TokenMigration = e
+            .storage()
+            .instance()
+            .get(&key)
+            .unwrap_or_else(|| panic!("no pending migration"));
+
+        meta.token = migration.new_token.clone();
+        write_asset(&e, asset_code.clone(), &meta);
+        e.storage().instance().remove(&key);
+
+        TokenMigrationCompletedEvent {
+            asset_code,
+            new_token: migration.new_token,
+        }
+        .publish(&e);
+    }
+
+    /// Pending token migration for an asset, if any.
+    pub fn pending_migration(e: Env, asset_code: Symbol) -> Option<TokenMigration> {
+        e.storage().instance().get(&DataKey::Migration(asset_code))
+    }
+
+    /// Batch version of `asset_info` for catalog pages. Results line up with
+    /// `codes`; unknown codes come back as `None` instead of aborting the read.
+    pub fn assets_info(e: Env, codes: Vec<Symbol>) -> Vec<Option<CarbonAssetMeta>> {
+        if codes.len() > MAX_BATCH_SIZE {
+            panic!("too many asset codes");
+        }
+
+        let mut out = Vec::new(&e);
+        for code in codes.iter() {
+            out.push_back(e.storage().instance().get(&DataKey::Asset(code)));
+        }
+        out
+    }
+
+    /// Sets the contract-wide admin. Runs once, as part of the deployment
+    /// itself, so nobody can claim the admin role between deploying the
+    /// contract and configuring it.
+    pub fn __constructor(e: Env, admin: Address) {
+        e.storage().instance().set(&DataKey::Admin, &admin);
+    }
+
+    /// Current contract-wide admin.
+    pub fn admin(e: Env) -> Address {
+        read_admin(&e)
+    }
+
+    /// First step of handing over the contract admin role: the current
+    /// admin names `new_admin`, who must then call `accept_admin`. Nothing
+    /// changes until they do, so a mistyped address can't lock the contract.
+    /// Proposing again replaces the pending admin.
+    pub fn propose_admin(e: Env, new_admin: Address) {
+        let admin = read_admin(&e);
+        admin.require_auth();
+
+        e.storage()
+            .instance()
+            .set(&ExtKey::PendingAdmin, &new_admin);
+
+        AdminProposedEvent {
+            admin,
+            proposed: new_admin,
+        }
+        .publish(&e);
+    }
+
+    /// Second step: the proposed admin signs to take over the role.
+    pub fn accept_admin(e: Env, new_admin: Address) {
+        new_admin.require_auth();
+
+        let pending: Address = e
+            .storage()
+            .instance()
+            .get(&ExtKey::PendingAdmin)
+            .unwrap_or_else(|| panic!("no admin proposed"));
+        if pending != new_admin {
+            panic!("not the proposed admin");
+        }
+
+        let previous = read_admin(&e);
+        e.storage().instance().set(&DataKey::Admin, &new_admin);
+        e.storage().instance().remove(&ExtKey::PendingAdmin);
+
+        AdminChangedEvent {
+            previous,
+            admin: new_admin,
+        }
+        .publish(&e);
+    }
+
+    /// Admin proposed by `propose_admin` and not yet accepted.
+    pub fn pending_admin(e: Env) -> Option<Address> {
+        e.storage().instance().get(&ExtKey::PendingAdmin)
+    }
+
+    /// Contract admin or an `ADMIN` role holder gives `who` one of the
+    /// roles `ADMIN`, `COMPLIANCE`, `FEE_MANAGER` or `PAUSER`. With
+    /// `expires_at` the role lapses on its own once the ledger timestamp
+    /// passes it; without, it lasts until revoked. Granting a role `who`
+    /// already holds replaces its expiry.
+    pub fn grant_role(
+        e: Env,
+        caller: Address,
+        role: Symbol,
+        who: Address,
+        expires_at: Option<u64>,
+    ) {
+        require_role(&e, &caller, ROLE_ADMIN);
+        require_known_role(&e, &role);
+
+        let expires_at = expires_at.unwrap_or(0);
+        if expires_at != 0 && expires_at <= e.ledger().timestamp() {
+            panic!("expiry in the past");
+        }
+        write_persistent(&e, &ExtKey::Role(role.clone(), who.clone()), &expires_at);
+
+        RoleChangedEvent {
+            role,
+            who,
+            granted: true,
+            expires_at,
+        }
+        .publish(&e);
+    }
+
+    /// Takes a role back. The contract admin's implicit roles can't be
+    /// revoked; hand the admin over with `propose_admin` instead.
+    pub fn revoke_role(e: Env, caller: Address, role: Symbol, who: Address) {
+        require_role(&e, &caller, ROLE_ADMIN);
+        require_known_role(&e, &role);
+
+        e.storage()
+            .persistent()
+            .remove(&ExtKey::Role(role.clone(), who.clone()));
+
+        RoleChangedEvent {
+            role,
+            who,
+            granted: false,
+            expires_at: 0,
+        }
+        .publish(&e);
+    }
+
+    /// Whether `who` holds `role`, counting the contract admin as holding
+    /// every role.
+    pub fn has_role(e: Env, role: Symbol, who: Address) -> bool {
+        holds_role(&e, &role, &who)
+    }
+
+    /// Timestamp after which `who`'s grant of `role` lapses. 0 for grants
+    /// without expiry, for the contract admin, and when there is no grant
+    /// at all; check `has_role` to tell those apart.
+    pub fn role_expiry(e: Env, role: Symbol, who: Address) -> u64 {
+        read_role_expiry(&e, &role, &who).unwrap_or(0)
+    }
+
+    /// Contract admin chooses which optional events are emitted, as a
+    /// bitmask: 1 = listing closed, 2 = listing consumed, 4 = batch listing
+    /// summary, 8 = listing flagged, 16 = bid placed. Unknown bits are
+    /// rejected. Events not listed here can't be switched off.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_event_config(e: Env, caller: Address, flags: u32) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        if flags & !ALL_OPTIONAL_EVENTS != 0 {
+            panic!("unknown event flag");
+        }
+        e.storage().instance().set(&ExtKey::EventConfig, &flags);
+    }
+
+    /// Bitmask of optional events currently emitted (see `set_event_config`).
+    pub fn event_config(e: Env) -> u32 {
+        read_event_config(&e)
+    }
+
+    /// Contract admin halts every asset operation contract-wide: minting,
+    /// trading and retirement. Admin configuration keeps working. With
+    /// `resume_at` set (ledger timestamp) the halt lifts on its own at that
+    /// time, so a lost admin key can't freeze the market for good; 0 pauses
+    /// until `unpause`.
+    /// `caller` is the contract admin or a `PAUSER` role holder.
+    pub fn pause(e: Env, caller: Address, resume_at: u64) {
+        require_role(&e, &caller, ROLE_PAUSER);
+
+        if resume_at != 0 && resume_at <= e.ledger().timestamp() {
+            panic!("resume_at is in the past");
+        }
+        e.storage().instance().set(&DataKey::Paused, &resume_at);
+
+        ContractPausedEvent {
+            paused: true,
+            resume_at,
+        }
+        .publish(&e);
+    }
+
+    /// Contract admin lifts a pause early.
+    /// `caller` is the contract admin or a `PAUSER` role holder.
+    pub fn unpause(e: Env, caller: Address) {
+        require_role(&e, &caller, ROLE_PAUSER);
+
+        e.storage().instance().remove(&DataKey::Paused);
+
+        ContractPausedEvent {
+            paused: false,
+            resume_at: 0,
+        }
+        .publish(&e);
+    }
+
+    /// `(paused_now, resume_at)`; `resume_at` is 0 when not paused or paused
+    /// until an explicit `unpause`.
+    pub fn pause_status(e: Env) -> (bool, u64) {
+        let paused = is_paused(&e);
+        (
+            paused,
+            if paused {
+                read_pause(&e).unwrap_or(0)
+            } else {
+                0
+            },
+        )
+    }
+
+    /// Ledger timestamp the contract uses for deadlines, locks and auctions,
+    /// so clients agree with it on the current time.
+    pub fn now(e: Env) -> u64 {
+        e.ledger().timestamp()
+    }
+
+    /// Admin sets the XML cost above which a single trade needs the admin's
+    /// co-signature. 0 disables the breaker.
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn set_max_single_trade_xml(e: Env, caller: Address, max_xml: i128) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        if max_xml < 0 {
+            panic!("max_xml must not be negative");
+        }
+        e.storage()
+            .instance()
+            .set(&DataKey::MaxSingleTradeXml, &max_xml);
+    }
+
+    /// Single-trade XML threshold (0 = disabled).
+    pub fn max_single_trade_xml(e: Env) -> i128 {
+        read_max_single_trade_xml(&e)
+    }
+
+    /// Contract admin bounds listing lifetimes: listings written from now on
+    /// expire `seconds` after their last create / update, and expired ones
+    /// can't be bought and may be pruned by anyone. 0 = listings never
+    /// expire. Existing listings keep the expiry they were written with.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_max_listing_duration(e: Env, caller: Address, seconds: u64) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        e.storage()
+            .instance()
+            .set(&DataKey::MaxListingDuration, &seconds);
+    }
+
+    /// Seconds a listing stays live after its last write (0 = forever).
+    pub fn max_listing_duration(e: Env) -> u64 {
+        read_max_listing_duration(&e)
+    }
+
+    /// Contract admin sets the minimum seconds between price changes on a
+    /// seller's listing for an asset (0 = no cooldown, the default).
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_min_update_interval(e: Env, caller: Address, seconds: u64) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        e.storage()
+            .instance()
+            .set(&ExtKey::MinUpdateInterval, &seconds);
+    }
+
+    /// Minimum seconds between a seller's price changes on an asset.
+    pub fn min_update_interval(e: Env) -> u64 {
+        read_min_update_interval(&e)
+    }
+
+    /// Contract admin replaces the accepted retirement `purpose` codes
+    /// (default `OFFSET`, `COMPLIANCE`, `VOLUNTARY`).
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn set_retire_purposes(e: Env, caller: Address, purposes: Vec<Symbol>) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        if purposes.len() > MAX_BATCH_SIZE {
+            panic!("too many purposes");
+        }
+        e.storage()
+            .instance()
+            .set(&DataKey::RetirePurposes, &purposes);
+    }
+
+    /// Retirement `purpose` codes `retire` accepts.
+    pub fn retire_purposes(e: Env) -> Vec<Symbol> {
+        read_retire_purposes(&e)
+    }
+
+    /// Contract admin requires `compliance_signer` to co-sign every
+    /// retirement of more than `threshold` units (all retire entry points).
+    /// Smaller retirements need only the holder. `threshold` 0 turns the
+    /// quorum off.
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn set_retire_quorum(e: Env, caller: Address, threshold: i128, compliance_signer: Address) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        if threshold < 0 {
+            panic!("threshold must not be negative");
+        }
+        if threshold == 0 {
+            e.storage().instance().remove(&DataKey::RetireQuorum);
+        } else {
+            e.storage()
+                .instance()
+                .set(&DataKey::RetireQuorum, &(threshold, compliance_signer));
+        }
+    }
+
+    /// `(threshold, compliance_signer)` for co-signed retirements, or `None`
+    /// if every retirement is single-signed.
+    pub fn retire_quorum(e: Env) -> Option<(i128, Address)> {
+        read_retire_quorum(&e)
+    }
+
+    /// Contract admin sets the global marketplace fee, taken out of the
+    /// seller's proceeds on listing purchases and paid to `collector`.
+    /// `caller` is the contract admin or a `FEE_MANAGER` role holder.
+    pub fn set_fee(e: Env, caller: Address, fee_bps: u32, collector: Address) {
+        require_role(&e, &caller, ROLE_FEE_MANAGER);
+
+        require_valid_fee_bps(fee_bps);
+        e.storage()
+            .instance()
+            .set(&DataKey::Fee, &(fee_bps, collector));
+        validate_all_bps_sums(&e, "fee_bps");
+    }
+
+    /// Global `(fee_bps, collector)`, or `None` if no fee is configured.
+    pub fn fee(e: Env) -> Option<(u32, Address)> {
+        read_fee_config(&e)
+    }
+
+    /// Contract admin splits the marketplace fee on purchases between
+    /// several collectors (a consortium), as `(collector, share_bps)` summing
+    /// to 10000; rounding dust goes to the first. Empty restores the single
+    /// `set_fee` collector, which also still receives retirement fees.
+    /// `caller` is the contract admin or a `FEE_MANAGER` role holder.
+    pub fn set_fee_splits(e: Env, caller: Address, splits: Vec<(Address, u32)>) {
+        require_role(&e, &caller, ROLE_FEE_MANAGER);
+
+        if splits.is_empty() {
+            e.storage().instance().remove(&ExtKey::FeeSplits);
+            return;
+        }
+        if splits.len() > MAX_FEE_COLLECTORS {
+            panic!("too many fee collectors");
+        }
+        let mut total: u32 = 0;
+        for (i, (collector, share_bps)) in splits.iter().enumerate() {
+            if share_bps == 0 {
+                panic!("fee share must be positive");
+            }
+            if splits.iter().position(|(c, _)| c == collector) != Some(i) {
+                panic!("duplicate fee collector");
+            }
+            total = total.saturating_add(share_bps);
+        }
+        if total != BPS_DENOMINATOR as u32 {
+            panic!("fee shares must sum to 10000 bps, got {}", total);
+        }
+        e.storage().instance().set(&ExtKey::FeeSplits, &splits);
+    }
+
+    /// Marketplace fee shares as `(collector, share_bps)`; empty when the
+    /// whole fee goes to the `set_fee` collector.
+    pub fn fee_splits(e: Env) -> Vec<(Address, u32)> {
+        read_fee_splits(&e)
+    }
+
+    /// Contract admin overrides the fee for one asset (e.g. a lower rate for
+    /// institutional volume). `None` falls back to the global fee.
+    /// `caller` is the contract admin or a `FEE_MANAGER` role holder.
+    pub fn set_asset_fee(e: Env, caller: Address, asset_code: Symbol, fee_bps: Option<u32>) {
+        require_role(&e, &caller, ROLE_FEE_MANAGER);
+
+        read_asset(&e, asset_code.clone());
+        let key = DataKey::AssetFee(asset_code.clone());
+        match fee_bps {
+            Some(fee_bps) => {
+                require_valid_fee_bps(fee_bps);
+                e.storage().instance().set(&key, &fee_bps);
+            }
+            None => e.storage().instance().remove(&key),
+        }
+        validate_bps_sum(&e, &asset_code, "asset fee_bps");
+    }
+
+    /// Fee rate applied right now to primary or secondary trades on an asset.
+    pub fn effective_fee_bps(e: Env, asset_code: Symbol, is_primary: bool) -> u32 {
+        effective_fee_bps(&e, asset_code, is_primary)
+    }
+
+    /// Contract admin sets a separate fee for primary sales (listings by the
+    /// asset's issuer). `None` treats them like secondary resales.
+    /// `caller` is the contract admin or a `FEE_MANAGER` role holder.
+    pub fn set_primary_fee(e: Env, caller: Address, fee_bps: Option<u32>) {
+        require_role(&e, &caller, ROLE_FEE_MANAGER);
+
+        match fee_bps {
+            Some(fee_bps) => {
+                require_valid_fee_bps(fee_bps);
+                e.storage()
+                    .instance()
+                    .set(&DataKey::PrimaryFeeBps, &fee_bps);
+            }
+            None => e.storage().instance().remove(&DataKey::PrimaryFeeBps),
+        }
+        validate_all_bps_sums(&e, "primary fee_bps");
+    }
+
+    /// Contract admin caps an asset's worst-case fee plus conservation share
+    /// (default 3000 bps). Rejected if an asset is already configured above
+    /// the new cap.
+    /// `caller` is the contract admin or a `FEE_MANAGER` role holder.
+    pub fn set_max_bps_sum(e: Env, caller: Address, max_bps: u32) {
+        require_role(&e, &caller, ROLE_FEE_MANAGER);
+
+        if max_bps > 10_000 {
+            panic!("max_bps above 10000");
+        }
+        e.storage().instance().set(&ExtKey::MaxBpsSum, &max_bps);
+        validate_all_bps_sums(&e, "max_bps_sum");
+    }
+
+    /// Cap on an asset's fee plus conservation bps.
+    pub fn max_bps_sum(e: Env) -> u32 {
+        read_max_bps_sum(&e)
+    }
+
+    /// Asset admin records the asset's primary issuer; that address's
+    /// listings count as primary sales.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_issuer(e: Env, asset_code: Symbol, signers: Vec<Address>, issuer: Address) {
+        let meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        e.storage()
+            .instance()
+            .set(&DataKey::Issuer(asset_code), &issuer);
+    }
+
+    /// Primary issuer of an asset, if known.
+    pub fn issuer(e: Env, asset_code: Symbol) -> Option<Address> {
+        e.storage().instance().get(&DataKey::Issuer(asset_code))
+    }
+
+    /// Contract admin sets how many extra decimal places listing prices carry
+    /// (cost = `amount * price / 10^price_scale`). Prices are reinterpreted
+    /// under the new scale, so it can only change while no listing is open.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_price_scale(e: Env, caller: Address, price_scale: u32) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        if price_scale > MAX_PRICE_SCALE {
+            panic!("price_scale above maximum of {}", MAX_PRICE_SCALE);
+        }
+        if read_global_stats(&e).active_listings > 0 {
+            panic!("price_scale can't change while listings are open");
+        }
+        e.storage()
+            .instance()
+            .set(&DataKey::PriceScale, &price_scale);
+    }
+
+    /// Decimal places of listing prices beyond the XML base unit (default 0).
+    pub fn price_scale(e: Env) -> u32 {
+        read_price_scale(&e)
+    }
+
+    /// Asset admin puts the asset in an asset class (e.g. jurisdiction) whose
+    /// settlement token is looked up in the routing table. Empty symbol
+    /// settles in the global XML token.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_asset_class(e: Env, asset_code: Symbol, signers: Vec<Address>, asset_class: Symbol) {
+        let mut meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        meta.asset_class = asset_class;
+        write_asset(&e, asset_code, &meta);
+    }
+
+    /// Contract admin routes an asset class to a settlement token for listing
+    /// buys and Dutch auctions (English auctions keep the global XML token).
+    /// `None` removes the route.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_settlement_for(
+        e: Env,
+        caller: Address,
+        asset_class: Symbol,
+        token: Option<Address>,
+    ) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        let key = DataKey::SettlementFor(asset_class);
+        match token {
+            Some(token) => {
+                require_sane_decimals(&e, &token);
+                e.storage().instance().set(&key, &token);
+            }
+            None => e.storage().instance().remove(&key),
+        }
+    }
+
+    /// Settlement token routed to an asset class, if any.
+    pub fn settlement_for(e: Env, asset_class: Symbol) -> Option<Address> {
+        e.storage()
+            .instance()
+            .get(&DataKey::SettlementFor(asset_class))
+    }
+
+    /// Token an asset's listings and Dutch auctions settle in right now.
+    pub fn settlement_token(e: Env, asset_code: Symbol) -> Option<Address> {
+        let meta = read_asset(&e, asset_code);
+        settlement_token(&e, &meta)
+    }
+
+    /// Token a buy from `seller`'s listing would be paid in, resolved exactly
+    /// as the trade path does (asset class routing, then the global XML
+    /// token), so the UI can ask for the right approval. Listings have no
+    /// token override of their own.
+    pub fn settlement_token_for(e: Env, asset_code: Symbol, seller: Address) -> Address {
+        read_listing(&e, asset_code.clone(), seller);
+        let meta = read_asset(&e, asset_code);
+        read_settlement_token(&e, &meta)
+    }
+
+    /// Contract admin toggles the credit fallback for seller proceeds (see
+    /// `pay_for_purchase`). Off by default.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_credit_fallback(e: Env, caller: Address, enabled: bool) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        e.storage()
+            .instance()
+            .set(&DataKey::CreditFallback, &enabled);
+    }
+
+    /// Whether undeliverable seller proceeds are booked as credits.
+    pub fn credit_fallback(e: Env) -> bool {
+        is_credit_fallback_enabled(&e)
+    }
+
+    /// Contract admin makes `register_asset` reject tokens whose decimals
+    /// differ from the XML token's, for deployments that price with plain
+    /// `amount * price`. Off by default; assets already registered are not
+    /// re-checked.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_require_matching_decimals(e: Env, caller: Address, enabled: bool) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        e.storage()
+            .instance()
+            .set(&ExtKey::RequireMatchingDecimals, &enabled);
+    }
+
+    /// Whether registration rejects carbon / XML decimals mismatches.
+    pub fn require_matching_decimals(e: Env) -> bool {
+        e.storage()
+            .instance()
+            .get(&ExtKey::RequireMatchingDecimals)
+            .unwrap_or(false)
+    }
+
+    /// Proceeds of `token` held for `who` after a failed payout.
+    pub fn credit_of(e: Env, who: Address, token: Address) -> i128 {
+        read_persistent_counter(&e, &DataKey::Credit(who, token))
+    }
+
+    /// Withdraw all credited proceeds of `token` (e.g. once a trustline
+    /// exists). Returns the amount paid out.
+    pub fn withdraw_credit(e: Env, who: Address, token: Address) -> i128 {
+        who.require_auth();
+        require_holder_not_frozen(&e, &who);
+
+        let key = DataKey::Credit(who.clone(), token.clone());
+        let amount = read_persistent_counter(&e, &key);
+        if amount <= 0 {
+            panic!("no credit to withdraw");
+        }
+        e.storage().persistent().remove(&key);
+
+        TokenClient::new(&e, &token).transfer(&e.current_contract_address(), &who, &amount);
+        amount
+    }
+
+    /// Contract admin caps how many listings may be open at once, overall
+    /// and per seller. 0 means no cap.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_listing_caps(e: Env, caller: Address, max_active: u32, max_per_seller: u32) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        e.storage()
+            .instance()
+            .set(&DataKey::ListingCaps, &(max_active, max_per_seller));
+    }
+
+    /// `(max_active, max_per_seller)`; 0 means no cap.
+    pub fn listing_caps(e: Env) -> (u32, u32) {
+        read_listing_caps(&e)
+    }
+
+    /// Set which token contract is used as "money" (XML).
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn set_xml_token(e: Env, caller: Address, xml_token: Address) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        require_sane_decimals(&e, &xml_token);
+
+        let previous: Option<Address> = e.storage().instance().get(&DataKey::XmlToken);
+        e.storage().instance().set(&DataKey::XmlToken, &xml_token);
+
+        let mut history = Self::xml_token_history(e.clone());
+        if history.len() >= MAX_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back((xml_token.clone(), e.ledger().timestamp()));
+        e.storage()
+            .instance()
+            .set(&DataKey::XmlTokenHistory, &history);
+
+        XmlTokenChangedEvent {
+            caller,
+            previous,
+            xml_token,
+        }
+        .publish(&e);
+    }
+
+    /// Most recent settlement token changes as `(xml_token, timestamp)`,
+    /// oldest first.
+    pub fn xml_token_history(e: Env) -> Vec<(Address, u64)> {
+        e.storage()
+            .instance()
+            .get(&DataKey::XmlTokenHistory)
+            .unwrap_or_else(|| Vec::new(&e))
+    }
+
+    /// Seller creates or updates a listing for an asset.
+    ///
+    /// IMPORTANT: Off-chain, seller must first call:
+    ///   carbon_token.approve(controller, amount)
+    /// so this contract can move `amount` tokens later.
+    ///
+    /// `starts_at` schedules a launch: the listing can't be bought before
+    /// that ledger timestamp (0 = buyable immediately).
+    ///
+    /// Ends by running the asset's order matching (see `match_orders`), so
+    /// resting limit bids at or above `price` fill straight away at the
+    /// listing's price, as far as the approval already covers; the rest of
+    /// the listing stays up.
+    ///
+    /// Returns the listing id (unchanged when updating an existing listing).
+    pub fn list_asset(
+        e: Env,
+        seller: Address,
+        asset_code: Symbol,
+        amount: i128,
+        price: i128,
+        starts_at: u64,
+    ) -> u64 {
+        seller.require_auth();
+
+        let listing_id = put_listing(
+            &e,
+            seller.clone(),
+            seller.clone(),
+            asset_code.clone(),
+            amount,
+            price,
+            false,
+            Some(starts_at),
+        );
+        match_orders(&e, &asset_code);
+        listing_id
+    }
+
+    /// One-step onboarding: approve the controller on the asset token and
+    /// list in the same transaction. The controller calls the token's
+    /// `approve(seller, controller, listed_total, approve_expiry)` itself,
+    /// so the seller's signature must cover that nested call in its auth
+    /// tree (simulation records it). The allowance is set to everything the
+    /// seller has committed for the asset (this listing plus any Dutch
+    /// auction), replacing any allowance granted before.
+    pub fn list_with_approval(
+        e: Env,
+        seller: Address,
+        asset_code: Symbol,
+        amount: i128,
+        price: i128,
+        approve_expiry: u32,
+    ) -> u64 {
+        seller.require_auth();
+
+        let listing_id = put_listing(
+            &e,
+            seller.clone(),
+            seller.clone(),
+            asset_code.clone(),
+            amount,
+            price,
+            false,
+            None,
+        );
+
+        let meta = read_asset(&e, asset_code.clone());
+        TokenClient::new(&e, &meta.token).approve(
+            &seller,
+            &e.current_contract_address(),
+            &read_listed_total(&e, &asset_code, &seller),
+            &approve_expiry,
+        );
+        match_orders(&e, &asset_code);
+        listing_id
+    }
+
+    /// Custodian lists tokens it holds for a client. The custodian approves and
+    /// holds the tokens (`seller = custodian`), but sale proceeds go to `beneficiary`.
+    pub fn list_for(
+        e: Env,
+        custodian: Address,
+        beneficiary: Address,
+        asset_code: Symbol,
+        amount: i128,
+        price: i128,
+    ) -> u64 {
+        custodian.require_auth();
+
+        let listing_id = put_listing(
+            &e,
+            custodian,
+            beneficiary,
+            asset_code.clone(),
+            amount,
+            price,
+            false,
+            None,
+        );
+        match_orders(&e, &asset_code);
+        listing_id
+    }
+
+    /// Custodial listing: moves `amount` carbon tokens from the seller into
+    /// the controller (seller must have approved it) and lists them at
+    /// `price`. Buys deliver from the controller's own balance, so the
+    /// seller needs no live allowance later. Depositing again adds to the
+    /// listing and reprices it. Unsold tokens stay in custody after the
+    /// listing closes until `withdraw_inventory`.
+    pub fn deposit_and_list(
+        e: Env,
+        seller: Address,
+        asset_code: Symbol,
+        amount: i128,
+        price: i128,
+    ) -> u64 {
+        seller.require_auth();
+
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        // Wallet commitments (Dutch auctions) must still be covered afterwards
+        require_uncommitted_balance(&e, &asset_code, &seller, 0, amount);
+
+        let held = e
+            .storage()
+            .persistent()
+            .get::<DataKey, Listing>(&DataKey::Listing(asset_code.clone(), seller.clone()))
+            .filter(|l| l.custodial)
+            .map_or(0, |l| l.amount);
+        let total = held
+            .checked_add(amount)
+            .unwrap_or_else(|| panic!("overflow in custodial listing"));
+        let listing_id = put_listing(
+            &e,
+            seller.clone(),
+            seller.clone(),
+            asset_code.clone(),
+            total,
+            price,
+            true,
+            None,
+        );
+
+        let meta = read_asset(&e, asset_code.clone());
+        let contract = e.current_contract_address();
+        TokenClient::new(&e, &meta.token).transfer_from(&contract, &seller, &contract, &amount);
+        adjust_custody(&e, &asset_code, &seller, amount);
+        match_orders(&e, &asset_code);
+        listing_id
+    }
+
+    /// Seller takes back custodied tokens that no active custodial listing
+    /// still offers. Cancel or shrink the listing first to free more.
+    pub fn withdraw_inventory(e: Env, seller: Address, asset_code: Symbol, amount: i128) {
+        seller.require_auth();
+        require_holder_not_frozen(&e, &seller);
+
+        require_not_halted(&e, &asset_code);
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        let listed = e
+            .storage()
+            .persistent()
+            .get::<DataKey, Listing>(&DataKey::Listing(asset_code.clone(), seller.clone()))
+            .filter(|l| l.custodial)
+            .map_or(0, |l| l.amount);
+        let free = read_custody_balance(&e, &asset_code, &seller) - listed;
+        if amount > free {
+            panic!(
+                "withdraw of {} exceeds uncommitted custody balance {}",
+                amount, free
+            );
+        }
+
+        adjust_custody(&e, &asset_code, &seller, -amount);
+        let meta = read_asset(&e, asset_code.clone());
+        let contract = e.current_contract_address();
+        TokenClient::new(&e, &meta.token).transfer(&contract, &seller, &amount);
+
+        WithdrawInventoryEvent {
+            remaining: read_custody_balance(&e, &asset_code, &seller),
+            asset_code,
+            seller,
+            amount,
+        }
+        .publish(&e);
+    }
+
+    /// Units the controller holds for `seller`, listed or not.
+    pub fn custody_balance(e: Env, asset_code: Symbol, seller: Address) -> i128 {
+        read_custody_balance(&e, &asset_code, &seller)
+    }
+
+    /// `seller`'s custody lots as `(amount, deposit_ledger)`, oldest first;
+    /// the order sales and withdrawals draw them down in.
+    pub fn custody_lots(e: Env, asset_code: Symbol, seller: Address) -> Vec<(i128, u64)> {
+        read_custody_lots(&e, &asset_code, &seller)
+    }
+
+    /// Units offered across every open listing of an asset, kept as a
+    /// running total instead of summing the book. Includes listings that
+    /// haven't started yet or have expired but weren't pruned.
+    pub fn total_listed(e: Env, asset_code: Symbol) -> i128 {
+        read_total_listed(&e, &asset_code)
+    }
+
+    /// Units the controller holds in custody for an asset, across sellers.
+    pub fn custodied(e: Env, asset_code: Symbol) -> i128 {
+        read_counter(&e, &DataKey::Custodied(asset_code))
+    }
+
+    /// Asset admin pauses (`enabled = false`) or resumes secondary trading
+    /// for one asset. Retirement and minting are unaffected.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_asset_trading(e: Env, asset_code: Symbol, signers: Vec<Address>, enabled: bool) {
+        let meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        let key = DataKey::TradingDisabled(asset_code.clone());
+        if enabled {
+            e.storage().instance().remove(&key);
+        } else {
+            e.storage().instance().set(&key, &true);
+        }
+
+        AssetTradingToggledEvent {
+            outstanding_supply: outstanding_supply(&e, &asset_code),
+            asset_code,
+            enabled,
+        }
+        .publish(&e);
+    }
+
+    /// Whether secondary trading is currently enabled for an asset.
+    pub fn asset_trading_enabled(e: Env, asset_code: Symbol) -> bool {
+        is_trading_enabled(&e, asset_code)
+    }
+
+    /// Contract admin puts an asset under a compliance freeze (regulatory
+    /// hold) or lifts it. Unlike `set_asset_trading`, a freeze also blocks
+    /// minting and retirement.
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn set_asset_frozen(
+        e: Env,
+        caller: Address,
+        asset_code: Symbol,
+        frozen: bool,
+        reason: String,
+    ) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        read_asset(&e, asset_code.clone());
+        require_string_len(&reason, MAX_STRING_LEN, "reason");
+        let key = ExtKey::Frozen(asset_code.clone());
+        if frozen {
+            e.storage().instance().set(&key, &true);
+        } else {
+            e.storage().instance().remove(&key);
+        }
+
+        AssetFrozenEvent {
+            asset_code,
+            frozen,
+            reason,
+        }
+        .publish(&e);
+    }
+
+    /// Contract admin freezes one account across every asset: it can no
+    /// longer list, buy, receive a purchase, be bought from, auction, bid,
+    /// withdraw custody or credits, or retire until `unfreeze_holder`.
+    /// Its open listings and bids stay but are skipped by routing and
+    /// matching, and an English auction it sold or won waits to settle.
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn freeze_holder(e: Env, caller: Address, who: Address) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        write_persistent(&e, &ExtKey::FrozenHolder(who.clone()), &true);
+
+        HolderFrozenEvent { who, frozen: true }.publish(&e);
+    }
+
+    /// Contract admin lifts a `freeze_holder`.
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn unfreeze_holder(e: Env, caller: Address, who: Address) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        e.storage()
+            .persistent()
+            .remove(&ExtKey::FrozenHolder(who.clone()));
+
+        HolderFrozenEvent { who, frozen: false }.publish(&e);
+    }
+
+    /// Whether `who` is under a holder-wide compliance freeze.
+    pub fn is_holder_frozen(e: Env, who: Address) -> bool {
+        is_holder_frozen(&e, &who)
+    }
+
+    /// Freeze, trading pause, closure and global pause for an asset in one
+    /// read. `tradable` also accounts for aliases and pending migrations.
+    pub fn asset_status(e: Env, asset_code: Symbol) -> AssetStatus {
+        let meta = read_asset(&e, asset_code.clone());
+        let frozen = is_asset_frozen(&e, &asset_code);
+        let trading_paused = !is_trading_enabled(&e, asset_code.clone());
+        let global_paused = is_paused(&e);
+        let tradable = !frozen
+            && !trading_paused
+            && !meta.closed
+            && !global_paused
+            && read_alias(&e, &asset_code).is_none()
+            && !has_pending_migration(&e, asset_code);
+        AssetStatus {
+            frozen,
+            trading_paused,
+            closed: meta.closed,
+            global_paused,
+            tradable,
+        }
+    }
+
+    /// Asset admin sets the lowest per-unit price a listing may ask.
+    /// `signers` must reach the asset's admin quorum (see `asset_admins`).
+    pub fn set_min_price(e: Env, asset_code: Symbol, signers: Vec<Address>, min_price: i128) {
+        let meta = read_asset(&e, asset_code.clone());
+        require_asset_quorum(&e, asset_code.clone(), &meta, &signers);
+
+        if min_price <= 0 {
+            panic!("min_price must be positive");
+        }
+        e.storage()
+            .instance()
+            .set(&DataKey::MinPrice(asset_code), &min_price);
+    }
+
+    /// Lowest per-unit price accepted for new listings (defaults to 1).
+    pub fn min_price(e: Env, asset_code: Symbol) -> i128 {
+        read_min_price(&e, asset_code)
+    }
+
+    /// Pre-flight check for `list_asset` that never reverts. Returns warning
+    /// codes (empty if the listing would be accepted and fillable):
+    /// `NO_ASSET`, `AMOUNT`, `PRICE`, `MIN_PRICE`, `HALTED`, `NO_XML`, `BALANCE`,
+    /// `ALLOWANCE`.
+    /// Keep in step with the hard checks in `check_listing`.
+    pub fn validate_listing(
+        e: Env,
+        seller: Address,
+        asset_code: Symbol,
+        amount: i128,
+        price: i128,
+    ) -> Vec<Symbol> {
+        let mut warnings = Vec::new(&e);
+
+        let asset_key = DataKey::Asset(asset_code.clone());
+        let meta: CarbonAssetMeta = match e.storage().instance().get(&asset_key) {
+            Some(meta) => meta,
+            None => {
+                warnings.push_back(symbol_short!("NO_ASSET"));
+                return warnings;
+            }
+        };
+
+        if amount <= 0 {
+            warnings.push_back(symbol_short!("AMOUNT"));
+        }
+        if price <= 0 {
+            warnings.push_back(symbol_short!("PRICE"));
+        } else if price < read_min_price(&e, asset_code.clone()) {
+            warnings.push_back(symbol_short!("MIN_PRICE"));
+        }
+        if meta.closed
+            || is_paused(&e)
+            || is_asset_frozen(&e, &asset_code)
+            || !is_trading_enabled(&e, asset_code.clone())
+            || has_pending_migration(&e, asset_code)
+        {
+            warnings.push_back(symbol_short!("HALTED"));
+        }
+        if settlement_token(&e, &meta).is_none() {
+            warnings.push_back(symbol_short!("NO_XML"));
+        }
+
+        let token_client = TokenClient::new(&e, &meta.token);
+        if token_client.balance(&seller) < amount {
+            warnings.push_back(symbol_short!("BALANCE"));
+        }
+        if token_client.allowance(&seller, &e.current_contract_address()) < amount {
+            warnings.push_back(symbol_short!("ALLOWANCE"));
+        }
+
+        warnings
+    }
+
+    /// Contract admin rebuilds `ListingSellers(asset_code)` if it ever drifts.
+    /// Storage can't be scanned, so the admin supplies candidate sellers;
+    /// those and the current entries are kept only if a listing really
+    /// exists, deduplicated. Also restores the asset in each kept seller's
+    /// own index and recomputes `total_listed` from the kept listings.
+    /// Returns the number of sellers in the rebuilt index.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn repair_seller_index(
+        e: Env,
+        caller: Address,
+        asset_code: Symbol,
+        candidates: Vec<Address>,
+    ) -> u32 {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        let mut sellers = Vec::new(&e);
+        let mut listed: i128 = 0;
+        let mut all = read_listing_sellers(&e, &asset_code);
+        all.append(&candidates);
+        for seller in all.iter() {
+            let key = DataKey::Listing(asset_code.clone(), seller.clone());
+            if sellers.contains(&seller) || !e.storage().persistent().has(&key) {
+                continue;
+            }
+
+            let mut codes = read_listings_of(&e, &seller);
+            if !codes.contains(&asset_code) {
+                codes.push_back(asset_code.clone());
+                write_listings_of(&e, &seller, &codes);
+            }
+            listed = listed
+                .checked_add(read_listing(&e, asset_code.clone(), seller.clone()).amount)
+                .unwrap_or_else(|| panic!("overflow in listed total"));
+            sellers.push_back(seller);
+        }
+
+        e.storage()
+            .instance()
+            .set(&ExtKey::TotalListed(asset_code.clone()), &listed);
+        write_listing_sellers(&e, &asset_code, &sellers);
+        sellers.len()
+    }
+
+    /// Seller withdraws their own listing.
+    pub fn cancel_listing(e: Env, seller: Address, asset_code: Symbol) {
+        seller.require_auth();
+
+        // Panic early if there is nothing to cancel
+        read_listing(&e, asset_code.clone(), seller.clone());
+
+        update_seller_stats(&e, &seller, |s| s.cancelled += 1);
+        close_listing(
+            &e,
+            asset_code,
+            seller,
+            String::from_str(&e, "cancelled"),
+            false,
+        );
+    }
+
+    /// Seller lets buyers settle their listing in other tokens too:
+    /// `accepted_tokens` maps each extra token to a per-unit price in it
+    /// (same scale as `price`), replacing the previous set; empty = the
+    /// asset's settlement token only. Kept across listing updates.
+    pub fn set_listing_tokens(
+        e: Env,
+        seller: Address,
+        asset_code: Symbol,
+        accepted_tokens: Map<Address, i128>,
+    ) {
+        seller.require_auth();
+
+        let mut listing = read_listing(&e, asset_code.clone(), seller);
+        if listing.disputed {
+            panic!("listing under review");
+        }
+        if accepted_tokens.len() > MAX_ACCEPTED_TOKENS {
+            panic!("too many accepted tokens");
+        }
+        let meta = read_asset(&e, asset_code);
+        let settlement = read_settlement_token(&e, &meta);
+        for (token, price) in accepted_tokens.iter() {
+            if token == settlement {
+                panic!("settlement token is priced by the listing price");
+            }
+            if price <= 0 {
+                panic!("price must be positive");
+            }
+            if checked_xml_cost(&e, listing.amount, price).is_none() {
+                panic!("listing too large for price {}", price);
+            }
+        }
+
+        listing.accepted_tokens = accepted_tokens;
+        store_listing(&e, &listing);
+    }
+
+    /// Rewrite the seller's existing listing in place: new `amount` and
+    /// `price`, same id, payout address, custody mode and fill history.
+    /// Unlike cancel-then-relist the listing is never absent, and it can't
+    /// half-succeed. Panics if there is no listing to replace. A custodial
+    /// listing can only grow up to the seller's custody balance.
+    pub fn replace_listing(
+        e: Env,
+        seller: Address,
+        asset_code: Symbol,
+        new_amount: i128,
+        new_price: i128,
+    ) -> u64 {
+        seller.require_auth();
+
+        let old = read_listing(&e, asset_code.clone(), seller.clone());
+        if old.custodial && new_amount > read_custody_balance(&e, &asset_code, &seller) {
+            panic!("not enough tokens in custody; use deposit_and_list");
+        }
+        let listing_id = put_listing(
+            &e,
+            seller.clone(),
+            old.proceeds_to,
+            asset_code.clone(),
+            new_amount,
+            new_price,
+            old.custodial,
+            None,
+        );
+
+        ListingReplacedEvent {
+            asset_code: asset_code.clone(),
+            seller,
+            listing_id,
+            old_amount: old.amount,
+            old_price: old.price,
+            new_amount,
+            new_price,
+        }
+        .publish(&e);
+        match_orders(&e, &asset_code);
+        listing_id
+    }
+
+    /// Market-maker reprice: atomically drop the listings in `cancels` and
+    /// create or replace the ones in `news` (`(asset_code, amount, price)`)
+    /// under one seller auth, so there's no gap where the seller is out of
+    /// the book. Everything is validated before anything is written.
+    ///
+    /// With `summarize` the per-listing `ListingClosedEvent`s are replaced by
+    /// one `BatchListingEvent`, for sellers repricing often enough to load
+    /// indexers. Off (per-listing events) is the default granularity.
+    pub fn reprice_batch(
+        e: Env,
+        seller: Address,
+        cancels: Vec<Symbol>,
+        news: Vec<(Symbol, i128, i128)>,
+        summarize: bool,
+    ) {
+        seller.require_auth();
+
+        if cancels.len() + news.len() > MAX_BATCH_SIZE {
+            panic!("batch too large");
+        }
+        for (i, code) in cancels.iter().enumerate() {
+            read_listing(&e, code.clone(), seller.clone());
+            if cancels.first_index_of(&code) != Some(i as u32) {
+                panic!("duplicate cancel");
+            }
+        }
+        for (i, (code, amount, price)) in news.iter().enumerate() {
+            check_listing(&e, &seller, code.clone(), amount, price);
+            if news.iter().position(|(c, _, _)| c == code) != Some(i) {
+                panic!("duplicate listing in batch");
+            }
+        }
+
+        for code in cancels.iter() {
+            if summarize {
+                take_down_listing(&e, &code, &seller, false);
+            } else {
+                close_listing(
+                    &e,
+                    code,
+                    seller.clone(),
+                    String::from_str(&e, "repriced"),
+                    false,
+                );
+            }
+        }
+        for (code, amount, price) in news.iter() {
+            put_listing(
+                &e,
+                seller.clone(),
+                seller.clone(),
+                code.clone(),
+                amount,
+                price,
+                false,
+                None,
+            );
+            match_orders(&e, &code);
+        }
+
+        if summarize && event_enabled(&e, EVENT_BATCH_LISTING) {
+            let mut asset_codes = cancels.clone();
+            for (code, _, _) in news.iter() {
+                if !asset_codes.contains(&code) {
+                    asset_codes.push_back(code);
+                }
+            }
+            BatchListingEvent {
+                seller,
+                count: cancels.len() + news.len(),
+                asset_codes,
+            }
+            .publish(&e);
+        }
+    }
+
+    /// Compliance takedown: the contract admin removes a listing the seller
+    /// won't cancel themselves (e.g. a project found to be fraudulent).
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn admin_cancel_listing(
+        e: Env,
+        caller: Address,
+        asset_code: Symbol,
+        seller: Address,
+        reason: String,
+    ) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        read_listing(&e, asset_code.clone(), seller.clone());
+        require_string_len(&reason, MAX_STRING_LEN, "reason");
+
+        close_listing(&e, asset_code, seller, reason, true);
+    }
+
+    /// Contract admin puts a listing reported as fraudulent under review
+    /// (`disputed = true`) or clears it. While disputed it can't be bought,
+    /// updated or cancelled by the seller, so it stays intact for the
+    /// investigation; `admin_cancel_listing` can still take it down.
+    /// `caller` is the contract admin or a `COMPLIANCE` role holder.
+    pub fn flag_listing(
+        e: Env,
+        caller: Address,
+        asset_code: Symbol,
+        seller: Address,
+        disputed: bool,
+    ) {
+        require_role(&e, &caller, ROLE_COMPLIANCE);
+
+        let mut listing = read_listing(&e, asset_code.clone(), seller.clone());
+        listing.disputed = disputed;
+        store_listing(&e, &listing);
+
+        if event_enabled(&e, EVENT_LISTING_FLAGGED) {
+            ListingFlaggedEvent {
+                asset_code,
+                seller,
+                disputed,
+            }
+            .publish(&e);
+        }
+    }
+
+    /// Keeper hook: anyone may remove a listing the controller can no longer
+    /// fill because the seller's carbon allowance dropped below the listed
+    /// amount, or one past its `expires_at`.
+    pub fn prune_listing(e: Env, asset_code: Symbol, seller: Address) {
+        let listing = read_listing(&e, asset_code.clone(), seller.clone());
+        let meta = read_asset(&e, asset_code.clone());
+        if is_listing_expired(&e, &listing) {
+            close_listing(
+                &e,
+                asset_code,
+                seller,
+                String::from_str(&e, "expired"),
+                false,
+            );
+            return;
+        }
+        if listing.custodial {
+            panic!("custodial listings need no approval");
+        }
+
+        let allowance =
+            TokenClient::new(&e, &meta.token).allowance(&seller, &e.current_contract_address());
+        if allowance >= listing.amount {
+            panic!("listing is sufficiently approved");
+        }
+
+        update_seller_stats(&e, &seller, |s| s.failed_approval += 1);
+        close_listing(
+            &e,
+            asset_code,
+            seller,
+            String::from_str(&e, "insufficient approval"),
+            false,
+        );
+    }
+
+    /// Keeper hook: anyone may reconcile a non-custodial listing against
+    /// the seller's current carbon balance and allowance. If the lesser of
+    /// the two no longer covers the listed amount, the listing is capped to
+    /// it (`ListingCappedEvent`), or closed when nothing is left. A fully
+    /// backed listing is left alone. Returns the amount still listed.
+    pub fn verify_listing(e: Env, asset_code: Symbol, seller: Address) -> i128 {
+        let mut listing = read_listing(&e, asset_code.clone(), seller.clone());
+        if listing.custodial {
+            panic!("custodial listings are always backed");
+        }
+        let meta = read_asset(&e, asset_code.clone());
+        let carbon_client = TokenClient::new(&e, &meta.token);
+        let balance = carbon_client.balance(&seller);
+        let allowance = carbon_client.allowance(&seller, &e.current_contract_address());
+        let backed = balance.min(allowance);
+        if backed >= listing.amount {
+            return listing.amount;
+        }
+
+        if allowance < balance {
+            update_seller_stats(&e, &seller, |s| s.failed_approval += 1);
+        }
+        if backed <= 0 {
+            close_listing(
+                &e,
+                asset_code,
+                seller,
+                String::from_str(&e, "insufficient backing"),
+                false,
+            );
+            return 0;
+        }
+
+        let old_amount = listing.amount;
+        listing.amount = backed;
+        store_listing(&e, &listing);
+
+        ListingCappedEvent {
+            asset_code,
+            seller,
+            old_amount,
+            new_amount: backed,
+        }
+        .publish(&e);
+        backed
+    }
+
+    /// Keeper cleanup: close every candidate seller's listing for
+    /// `asset_code` that is past its `expires_at`. Storage can't be scanned,
+    /// so the caller supplies the sellers (e.g. from `listings_for`); ones
+    /// with no listing or a live one are skipped. Anyone may call this.
+    /// Returns how many listings were closed.
+    pub fn sweep_expired(e: Env, asset_code: Symbol, sellers: Vec<Address>) -> u32 {
+        if sellers.len() > MAX_BATCH_SIZE {
+            panic!("too many sellers");
+        }
+
+        let mut closed = 0;
+        for seller in sellers.iter() {
+            let expired = e
+                .storage()
+                .persistent()
+                .get::<DataKey, Listing>(&DataKey::Listing(asset_code.clone(), seller.clone()))
+                .is_some_and(|l| is_listing_expired(&e, &l));
+            if expired {
+                close_listing(
+                    &e,
+                    asset_code.clone(),
+                    seller,
+                    String::from_str(&e, "expired"),
+                    false,
+                );
+                closed += 1;
+            }
+        }
+        closed
+    }
+
+    /// Buyer purchases `amount` units of `asset_code` from a specific seller,
+    /// paying with XML token in a single atomic call.
+    ///
+    /// Off-chain:
+    ///  - Seller must have approved controller for at least `amount` of carbon token.
+    ///  - Buyer must have approved controller for at least `max_xml` of XML token.
+    ///
+    /// `deadline` is a ledger timestamp after which the call is rejected, so a
+    /// delayed transaction can't fill against a stale book. 0 = no deadline.
+    ///
+    /// `funding_source` records, for AML reporting, the account that really
+    /// funded the payment when a sponsor or relayer is involved (`None` =
+    /// `buyer`). A source other than the buyer must co-sign, so a trade can't
+    /// be attributed to an account that never agreed to it.
+    ///
+    /// Returns the trade's `settlement_id` (also in the `PurchaseEvent`), a
+    /// reconciliation key for classic memos; see `next_settlement`.
+    ///
+    /// `pay_token` settles in one of the listing's `accepted_tokens` at the
+    /// seller's price for it (`None` = the asset's settlement token);
+    /// `max_xml` and the buyer's allowance are then in that token.
+    pub fn buy_with_xml(
+        e: Env,
+        buyer: Address,
+        asset_code: Symbol,
+        seller: Address,
+        amount: i128,
+        max_xml: i128,
+        deadline: u64,
+        funding_source: Option<Address>,
+        pay_token: Option<Address>,
+    ) -> BytesN<32> {
+        buyer.require_auth();
+
+        if deadline != 0 && e.ledger().timestamp() > deadline {
+            panic!("transaction expired");
+        }
+        let funding_source = funding_source.unwrap_or_else(|| buyer.clone());
+        if funding_source == e.current_contract_address() {
+            panic!("funding_source cannot be the controller");
+        }
+        if funding_source != buyer {
+            funding_source.require_auth();
+        }
+
+        let purchase =
+            prepare_purchase_in(&e, &buyer, asset_code, seller, amount, max_xml, pay_token);
+
+        // 1) XML: buyer -> proceeds_to (the seller unless listed via `list_for`)
+        //    and the fee collector, spending the allowance granted to the controller
+        pay_for_purchase(&e, &purchase, &buyer, false);
+
+        // 2) Carbon leg + bookkeeping
+        let auto_retire = read_auto_retire(&e, &buyer);
+        finish_purchase(
+            &e,
+            purchase,
+            &buyer,
+            &buyer,
+            &funding_source,
+            amount,
+            auto_retire,
+        )
+    }
+
+    /// Buyer-side counterpart of `list_with_approval`: approve the controller
+    /// on the settlement token for exactly this purchase's cost, then buy,
+    /// in one transaction. The buyer's signature must cover the nested
+    /// `approve(buyer, controller, cost, current_ledger)` call in its auth
+    /// tree (simulation records it), scoping the approval to this ledger and
+    /// amount. The purchase spends it in full, so no allowance is left
+    /// standing; any allowance granted before is replaced. Returns the
+    /// trade's `settlement_id` like `buy_with_xml`.
+    pub fn approve_and_buy(
+        e: Env,
+        buyer: Address,
+        asset_code: Symbol,
+        seller: Address,
+        amount: i128,
+        max_xml: i128,
+    ) -> BytesN<32> {
+        buyer.require_auth();
+
+        let purchase = prepare_purchase(&e, &buyer, asset_code, seller, amount, max_xml);
+        TokenClient::new(&e, &purchase.xml_token).approve(
+            &buyer,
+            &e.current_contract_address(),
+            &purchase.cost_xml,
+            &e.ledger().sequence(),
+        );
+        pay_for_purchase(&e, &purchase, &buyer, false);
+
+        let auto_retire = read_auto_retire(&e, &buyer);
+        finish_purchase(&e, purchase, &buyer, &buyer, &buyer, amount, auto_retire)
+    }
+
+    /// Buy on someone else's behalf (gifts, treasury flows): `payer` signs for
+    /// the XML spend and the carbon goes straight to `recipient`.
+    pub fn buy_for(
+        e: Env,
+        payer: Address,
+        recipient: Address,
+        asset_code: Symbol,
+        seller: Address,
+        amount: i128,
+        max_xml: i128,
+    ) {
+        payer.require_auth();
+        require_holder_not_frozen(&e, &recipient);
+
+        let purchase = prepare_purchase(&e, &payer, asset_code, seller, amount, max_xml);
+
+        pay_for_purchase(&e, &purchase, &payer, false);
+
+        let auto_retire = read_auto_retire(&e, &recipient);
+        finish_purchase(&e, purchase, &payer, &recipient, &payer, amount, auto_retire);
+    }
+
+    /// Issuer buyback: the asset's issuer (or its admin) buys `amount` from
+    /// `seller` like `buy_with_xml` and burns it in the same call, for
+    /// market support or guarantee programs. Emits the `PurchaseEvent` and a
+    /// `CarbonRetireEvent` with `buyback` set. With `set_waive_buyback_fees`
+    /// the marketplace fee is skipped and the seller gets that share; the
+    /// conservation share is still paid. Returns the certificate id.
+    pub fn buyback_and_retire(
+        e: Env,
+        issuer: Address,
+        asset_code: Symbol,
+        seller: Address,
+        amount: i128,
+        max_xml: i128,
+        note: String,
+    ) -> u64 {
+        issuer.require_auth();
+
+        let meta = read_asset(&e, asset_code.clone());
+        let is_issuer = e
+            .storage()
+            .instance()
+            .get::<DataKey, Address>(&DataKey::Issuer(asset_code.clone()))
+            .is_some_and(|registered| registered == issuer);
+        if !is_issuer && issuer != meta.admin {
+            panic!("only the asset issuer can buy back");
+        }
+
+        let mut purchase =
+            prepare_purchase(&e, &issuer, asset_code.clone(), seller, amount, max_xml);
+        if read_waive_buyback_fees(&e) {
+            purchase.fee_xml = 0;
+        }
+        pay_for_purchase(&e, &purchase, &issuer, false);
+        finish_purchase(&e, purchase, &issuer, &issuer, &issuer, amount, None);
+
+        retire_credits(&e, asset_code, issuer, amount, note, None, None, None, true, false)
+    }
+
+    /// Contract admin lets `buyback_and_retire` skip the marketplace fee.
+    /// `caller` is the contract admin or a `FEE_MANAGER` role holder.
+    pub fn set_waive_buyback_fees(e: Env, caller: Address, waived: bool) {
+        require_role(&e, &caller, ROLE_FEE_MANAGER);
+
+        e.storage()
+            .instance()
+            .set(&ExtKey::WaiveBuybackFees, &waived);
+    }
+
+    /// Whether issuer buybacks trade without the marketplace fee.
+    pub fn waive_buyback_fees(e: Env) -> bool {
+        read_waive_buyback_fees(&e)
+    }
+
+    /// Deposit-style variant of `buy_with_xml`: the buyer pre-funds the
+    /// controller with `max_xml`, the seller is paid the actual cost and the
+    /// difference is refunded to the buyer in the same call.
+    pub fn buy_with_xml_exact(
+        e: Env,
+        buyer: Address,
+        asset_code: Symbol,
+        seller: Address,
+        amount: i128,
+        max_xml: i128,
+    ) {
+        buyer.require_auth();
+
+        let purchase = prepare_purchase(&e, &buyer, asset_code.clone(), seller, amount, max_xml);
+        let contract = e.current_contract_address();
+
+        // 1) XML: buyer deposits max_xml, controller pays the seller and refunds the rest
+        let xml_client = TokenClient::new(&e, &purchase.xml_token);
+        xml_client.transfer(&buyer, &contract, &max_xml);
+        pay_for_purchase(&e, &purchase, &buyer, true);
+
+        let refund = max_xml - purchase.cost_xml;
+        if refund > 0 {
+            xml_client.transfer(&contract, &buyer, &refund);
+        }
+
+        // 2) Carbon leg + bookkeeping
+        finish_purchase(&e, purchase, &buyer, &buyer, &buyer, amount, None);
+
+        if refund > 0 {
+            RefundEvent {
+                asset_code,
+                buyer,
+                deposited: max_xml,
+                refunded: refund,
+            }
+            .publish(&e);
+        }
+    }
+
+    /// Buy `amount` units of `asset_code` from the cheapest listings first,
+    /// across as many sellers as it takes (the route `preview_fill` shows).
+    ///
+    /// `max_xml` caps the total spend. `max_avg_price` caps the realized
+    /// volume-weighted price per unit across all legs (0 = no cap), so a
+    /// book that moved between simulation and execution can't push the
+    /// average up while staying under the total. Both are checked against
+    /// the full route before any tokens move. Returns a `FillReceipt` of the
+    /// legs settled; the route fills in full or reverts, so there is no
+    /// shortfall.
+    ///
+    /// Sellers in `exclude` (at most `MAX_BATCH_SIZE`, empty for none) are
+    /// routed around, so the buyer picks who they won't trade with.
+    ///
+    /// Against fragmentation, listings offering fewer than
+    /// `min_fill_per_seller` units are skipped (0 = none), and a route
+    /// touching more than `max_sellers` listings reverts rather than
+    /// partially filling (0 = no cap).
+    pub fn buy_best_price(
+        e: Env,
+        buyer: Address,
+        asset_code: Symbol,
+        amount: i128,
+        max_xml: i128,
+        max_avg_price: i128,
+        exclude: Vec<Address>,
+        min_fill_per_seller: i128,
+        max_sellers: u32,
+    ) -> FillReceipt {
+        buyer.require_auth();
+
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        if max_avg_price < 0 {
+            panic!("max_avg_price cannot be negative");
+        }
+        if exclude.len() > MAX_BATCH_SIZE {
+            panic!("exclude list too large");
+        }
+        if min_fill_per_seller < 0 {
+            panic!("min_fill_per_seller cannot be negative");
+        }
+
+        let (fills, total_cost) =
+            plan_full_fill(&e, &asset_code, amount, &exclude, min_fill_per_seller);
+        if max_sellers > 0 && fills.len() > max_sellers {
+            panic!("route needs {} sellers, above max_sellers", fills.len());
+        }
+        if total_cost > max_xml {
+            panic!("price exceeds max_xml");
+        }
+        if max_avg_price > 0 && total_cost > xml_cost(&e, amount, max_avg_price) {
+            panic!("average price exceeds max_avg_price");
+        }
+
+        settle_fills(&e, &buyer, &asset_code, &fills);
+        FillReceipt {
+            total_amount: amount,
+            total_cost,
+            fills,
+            shortfall: 0,
+        }
+    }
+
+    /// Buyer rests a bid for up to `amount` units of `asset_code` at or
+    /// below `limit_price`, replacing any bid they already have on the
+    /// asset. Matching runs at the end of this call and of every
+    /// `list_asset` (see `match_orders`): the bid fills at listing prices at
+    /// or below its limit, from the XML allowance the buyer granted the
+    /// controller; fills the allowance can't cover at that moment are
+    /// skipped. `expires_at` is a ledger timestamp (0 = never).
+    pub fn place_limit_bid(
+        e: Env,
+        buyer: Address,
+        asset_code: Symbol,
+        amount: i128,
+        limit_price: i128,
+        expires_at: u64,
+    ) {
+        buyer.require_auth();
+        require_holder_not_frozen(&e, &buyer);
+
+        let meta = read_asset(&e, asset_code.clone());
+        require_trading_enabled(&e, asset_code.clone());
+        read_settlement_token(&e, &meta);
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        if limit_price <= 0 {
+            panic!("limit_price must be positive");
+        }
+        if amount.checked_mul(limit_price).is_none() {
+            panic!(
+                "bid too large: amount {} * limit_price {} overflows",
+                amount, limit_price
+            );
+        }
+        if expires_at != 0 && expires_at <= e.ledger().timestamp() {
+            panic!("expires_at is in the past");
+        }
+
+        store_limit_bid(
+            &e,
+            &LimitBid {
+                asset_code: asset_code.clone(),
+                buyer: buyer.clone(),
+                amount,
+                limit_price,
+                expires_at,
+                placed_at: e.ledger().timestamp(),
+            },
+        );
+
+        LimitBidPlacedEvent {
+            asset_code: asset_code.clone(),
+            buyer,
+            amount,
+            limit_price,
+            expires_at,
+        }
+        .publish(&e);
+
+        match_orders(&e, &asset_code);
+    }
+
+    /// How much of a limit bid for `amount` at `limit_price` would fill as
+    /// soon as it's placed, matched the way `match_orders` would: cheapest
+    /// active listings at or below the limit first, wallet listings only as
+    /// far as their seller's approval covers, at most `MAX_MATCHES_PER_CALL`
+    /// listings. The rest would rest in the book. The buyer's own funding
+    /// and listings aren't known here, so they aren't accounted for.
+    pub fn bid_would_match(e: Env, asset_code: Symbol, limit_price: i128, amount: i128) -> i128 {
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        let meta = read_asset(&e, asset_code.clone());
+
+        let mut asks: Vec<Listing> = Vec::new(&e);
+        for seller in read_listing_sellers(&e, &asset_code).iter() {
+            let listing = read_listing(&e, asset_code.clone(), seller);
+            if !listing.disputed
+                && is_listing_active(&e, &listing)
+                && listing.price <= limit_price
+            {
+                asks.push_back(listing);
+            }
+        }
+
+        let carbon_client = TokenClient::new(&e, &meta.token);
+        let contract = e.current_contract_address();
+        let mut remaining = amount;
+        let mut legs = 0;
+        while remaining > 0 && legs < MAX_MATCHES_PER_CALL && !asks.is_empty() {
+            let mut best = 0;
+            for i in 1..asks.len() {
+                if asks.get_unchecked(i).price < asks.get_unchecked(best).price {
+                    best = i;
+                }
+            }
+            let ask = asks.get_unchecked(best);
+            asks.remove(best);
+
+            let mut fill = remaining.min(ask.amount);
+            if !ask.custodial {
+                fill = fill.min(carbon_client.allowance(&ask.seller, &contract));
+            }
+            if fill > 0 {
+                remaining -= fill;
+                legs += 1;
+            }
+        }
+        amount - remaining
+    }
+
+    /// Keeper hook: anyone may run the asset's order matching, e.g. once a
+    /// scheduled listing opens or to finish crossings a capped run left
+    /// behind. Returns the number of matches settled.
+    pub fn match_orders(e: Env, asset_code: Symbol) -> u32 {
+        require_trading_enabled(&e, asset_code.clone());
+        match_orders(&e, &asset_code)
+    }
+
+    /// Buyer withdraws their resting limit bid on an asset.
+    pub fn cancel_limit_bid(e: Env, buyer: Address, asset_code: Symbol) {
+        buyer.require_auth();
+
+        let bid = read_limit_bid(&e, &asset_code, &buyer);
+        remove_limit_bid(&e, &asset_code, &buyer);
+
+        LimitBidCancelledEvent {
+            asset_code,
+            buyer,
+            amount: bid.amount,
+        }
+        .publish(&e);
+    }
+
+    /// Keeper hook: anyone may remove the given buyers' limit bids on
+    /// `asset_code` that are past their `expires_at`, emitting a
+    /// `BidClosedEvent` for each. Buyers without an expired bid are
+    /// skipped. Returns how many bids were closed.
+    pub fn sweep_expired_bids(e: Env, asset_code: Symbol, buyers: Vec<Address>) -> u32 {
+        if buyers.len() > MAX_BATCH_SIZE {
+            panic!("too many buyers");
+        }
+
+        let mut closed = 0;
+        for buyer in buyers.iter() {
+            let expired = e
+                .storage()
+                .persistent()
+                .get::<ExtKey, LimitBid>(&ExtKey::Bid(asset_code.clone(), buyer.clone()))
+                .is_some_and(|b| is_limit_bid_expired(&e, &b));
+            if expired {
+                close_limit_bid(&e, &asset_code, &buyer, "expired");
+                closed += 1;
+            }
+        }
+        closed
+    }
+
+    /// `buyer`'s resting limit bid on an asset.
+    pub fn limit_bid(e: Env, asset_code: Symbol, buyer: Address) -> LimitBid {
+        read_limit_bid(&e, &asset_code, &buyer)
+    }
+
+    /// All of `buyer`'s resting limit bids, the buyer-side counterpart of
+    /// `my_listings`. May include bids past their `expires_at` that no
+    /// matching run has swept yet.
+    pub fn my_bids(e: Env, buyer: Address) -> Vec<LimitBid> {
+        let mut bids = Vec::new(&e);
+        for code in read_bids_of(&e, &buyer).iter() {
+            bids.push_back(read_limit_bid(&e, &code, &buyer));
+        }
+        bids
+    }
+
+    /// Resting limit bids on an asset, oldest first. May include bids past
+    /// their `expires_at` that no listing has swept yet.
+    pub fn limit_bids(e: Env, asset_code: Symbol) -> Vec<LimitBid> {
+        let mut bids = Vec::new(&e);
+        for buyer in read_bid_buyers(&e, &asset_code).iter() {
+            bids.push_back(read_limit_bid(&e, &asset_code, &buyer));
+        }
+        bids
+    }
+
+    /// Contract admin defines (or redefines) a basket: a product such as
+    /// "1 tonne of mixed credits" made of registered assets weighted in bps
+    /// that sum to 10000. Bought through `buy_basket`.
+    /// `caller` is the contract admin or an `ADMIN` role holder.
+    pub fn define_basket(
+        e: Env,
+        caller: Address,
+        basket_code: Symbol,
+        components: Vec<(Symbol, u32)>,
+    ) {
+        require_role(&e, &caller, ROLE_ADMIN);
+
+        if components.is_empty() {
+            panic!("basket needs components");
+        }
+        if components.len() > MAX_BASKET_COMPONENTS {
+            panic!("too many basket components");
+        }
+        let mut total_bps: u32 = 0;
+        for (i, (code, weight_bps)) in components.iter().enumerate() {
+            if weight_bps == 0 {
+                panic!("component weight must be positive");
+            }
+            if components.iter().position(|(c, _)| c == code) != Some(i) {
+                panic!("duplicate basket component");
+            }
+            read_asset(&e, code);
+            total_bps += weight_bps;
+        }
+        if total_bps != 10_000 {
+            panic!("basket weights must sum to 10000 bps");
+        }
+
+        e.storage()
+            .instance()
+            .set(&ExtKey::Basket(basket_code), &components);
+    }
+
+    /// Basket components as `(asset_code, weight_bps)`.
+    pub fn basket(e: Env, basket_code: Symbol) -> Vec<(Symbol, u32)> {
+        read_basket(&e, &basket_code)
+    }
+
+    /// Buy `amount` units of a basket: each component's share of `amount`
+    /// (rounded down, the last component takes the remainder) is sourced
+    /// from its cheapest listings as in `buy_best_price`. Every leg is
+    /// planned and `max_xml` checked against the combined cost before any
+    /// tokens move, so the basket settles in full or not at all. Returns
+    /// the XML spent.
+    pub fn buy_basket(
+        e: Env,
+        buyer: Address,
+        basket_code: Symbol,
+        amount: i128,
+        max_xml: i128,
+    ) -> i128 {
+        buyer.require_auth();
+
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+
+        let components = read_basket(&e, &basket_code);
+        let mut routes = Vec::new(&e);
+        let mut summary = Vec::new(&e);
+        let mut assigned = 0;
+        let mut total_xml: i128 = 0;
+        for (i, (code, weight_bps)) in components.iter().enumerate() {
+            let share = if i as u32 + 1 == components.len() {
+                amount - assigned
+            } else {
+                mul_div(amount, weight_bps as i128, BPS_DENOMINATOR)
+            };
+            assigned += share;
+            if share == 0 {
+                continue;
+            }
+            let (fills, cost) = plan_full_fill(&e, &code, share, &Vec::new(&e), 0);
+            total_xml = total_xml
+                .checked_add(cost)
+                .unwrap_or_else(|| panic!("overflow in fill cost"));
+            routes.push_back((code.clone(), fills));
+            summary.push_back((code, share, cost));
+        }
+        if total_xml > max_xml {
+            panic!("price exceeds max_xml");
+        }
+
+        for (code, fills) in routes.iter() {
+            settle_fills(&e, &buyer, &code, &fills);
+        }
+
+        BasketPurchaseEvent {
+            basket_code,
+            buyer,
+            amount,
+            total_xml,
+            components: summary,
+        }
+        .publish(&e);
+
+        total_xml
+    }
+
+    /// Lifetime carbon units traded for an asset through `buy_with_xml`.
+    pub fn traded_volume(e: Env, asset_code: Symbol) -> i128 {
+        read_counter(&e, &DataKey::Volume(asset_code))
+    }
+
+    /// Lifetime XML paid for an asset through `buy_with_xml`.
+    pub fn xml_volume(e: Env, asset_code: Symbol) -> i128 {
+        read_counter(&e, &DataKey::XmlVolume(asset_code))
+    }
+
+    /// Completed / cancelled / failed-approval listing counts for a seller.
+    pub fn seller_stats(e: Env, who: Address) -> SellerStats {
+        read_seller_stats(&e, &who)
+    }
+
+    /// Seller opens a Dutch auction for `amount` units of `asset_code`.
+    /// As with `list_asset`, the seller must approve the controller off-chain.
+    pub fn create_dutch_auction(
+        e: Env,
+        seller: Address,
+        asset_code: Symbol,
+        amount: i128,
+        start_price: i128,
+        end_price: i128,
+        start: u64,
+        end: u64,
+    ) {
+        seller.require_auth();
+        require_holder_not_frozen(&e, &seller);
+        require_mint_unlocked(&e, &asset_code, &seller);
+
+        // Ensure the asset exists (panic if not)
+        let meta = read_asset(&e, asset_code.clone());
+        require_trading_enabled(&e, asset_code.clone());
+        read_settlement_token(&e, &meta);
+
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        if end_price <= 0 {
+            panic!("end_price must be positive");
+        }
+        if start_price <= end_price {
+            panic!("start_price must be above end_price");
+        }
+        if start >= end {
+            panic!("auction must end after it starts");
+        }
+        if end <= e.ledger().timestamp() {
+            panic!("auction end is in the past");
+        }
+
+        let key = DataKey::DutchAuction(asset_code.clone(), seller.clone());
+        let released = e
+            .storage()
+            .persistent()
+            .get::<DataKey, DutchAuction>(&key)
+            .map_or(0, |a| a.amount);
+        require_uncommitted_balance(&e, &asset_code, &seller, released, amount);
+        adjust_listed_total(&e, &asset_code, &seller, amount - released);
+
+        let auction = DutchAuction {
+            asset_code: asset_code.clone(),
+            seller: seller.clone(),
+            amount,
+            start_price,
+            end_price,
+            start,
+            end,
+        };
+        write_persistent(&e, &key, &auction);
+    }
+
+    /// Seller withdraws an open Dutch auction.
+    pub fn cancel_dutch_auction(e: Env, seller: Address, asset_code: Symbol) {
+        seller.require_auth();
 
-#[contractimpl]
-impl CarbonController {
-    /// Register an asset once you have deployed its token contract.
-    /// This is typically called by the marketplace admin.
-    pub fn register_asset(
+        let auction = read_dutch_auction(&e, asset_code.clone(), seller.clone());
+        adjust_listed_total(&e, &asset_code, &seller, -auction.amount);
+        e.storage()
+            .persistent()
+            .remove(&DataKey::DutchAuction(asset_code, seller));
+    }
+
+    /// Read a Dutch auction.
+    pub fn dutch_auction(e: Env, asset_code: Symbol, seller: Address) -> DutchAuction {
+        read_dutch_auction(&e, asset_code, seller)
+    }
+
+    /// Per-unit price a Dutch auction would fill at right now.
+    pub fn auction_price(e: Env, asset_code: Symbol, seller: Address) -> i128 {
+        let auction = read_dutch_auction(&e, asset_code, seller);
+        dutch_auction_price(&e, &auction)
+    }
+
+    /// Buy from a Dutch auction at the current interpolated price.
+    /// Buyer must have approved the controller for at least `max_xml` of XML.
+    pub fn buy_auction(
         e: Env,
+        buyer: Address,
         asset_code: Symbol,
-        project_id: i64,
-        vintage_year: i32,
-        token: Address,
-        admin: Address,
+        seller: Address,
+        amount: i128,
+        max_xml: i128,
     ) {
-        // Only the provided admin can (re)register
-        admin.require_auth();
+        buyer.require_auth();
+        require_holder_not_frozen(&e, &buyer);
+        require_holder_not_frozen(&e, &seller);
 
-        let meta = CarbonAssetMeta {
-            project_id,
-            vintage_year,
-            token,
-            admin: admin.clone(),
-        };
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
 
-        let key = DataKey::Asset(asset_code);
-        e.storage().instance().set(&key, &meta);
-    }
+        let key = DataKey::DutchAuction(asset_code.clone(), seller.clone());
+        let mut auction = read_dutch_auction(&e, asset_code.clone(), seller.clone());
 
-    /// Mint tokens to issuer when a tokenization_request is APPROVED.
-    /// Only the configured admin for that asset can call this.
-    pub fn mint_to_issuer(e: Env, asset_code: Symbol, issuer: Address, amount: i128) {
-        let meta = read_asset(&e, asset_code);
-        // Require marketplace admin signature
-        meta.admin.require_auth();
+        let now = e.ledger().timestamp();
+        if now < auction.start {
+            panic!("auction not started");
+        }
+        if now > auction.end {
+            panic!("auction ended");
+        }
+        if amount > auction.amount {
+            panic!("not enough auctioned amount");
+        }
+
+        let meta = read_asset(&e, asset_code.clone());
+        require_trading_enabled(&e, asset_code.clone());
+        let xml_token = read_settlement_token(&e, &meta);
+
+        let price = dutch_auction_price(&e, &auction);
+        let cost_xml = xml_cost(&e, amount, price);
+        if cost_xml > max_xml {
+            panic!("price exceeds max_xml");
+        }
+        check_trade_size(&e, asset_code.clone(), &buyer, cost_xml);
+        check_holder_cap(&e, &meta, &buyer, amount);
 
-        // Admin client: has `mint`
-        let sac_client = StellarAssetClient::new(&e, &meta.token);
-        sac_client.mint(&issuer, &amount);
+        let contract = e.current_contract_address();
+        TokenClient::new(&e, &xml_token).transfer_from(&contract, &buyer, &seller, &cost_xml);
+        TokenClient::new(&e, &meta.token).transfer_from(&contract, &seller, &buyer, &amount);
+
+        record_trade(&e, &asset_code, amount, cost_xml);
+
+        auction.amount = remaining_after(auction.amount, amount);
+        adjust_listed_total(&e, &asset_code, &seller, -amount);
+        if auction.amount > 0 {
+            write_persistent(&e, &key, &auction);
+        } else {
+            e.storage().persistent().remove(&key);
+        }
     }
 
-    /// Retire carbon credits by burning tokens from the holder.
-    /// The holder must sign the transaction.
-    pub fn retire(
+    /// Seller opens an English auction, moving `amount` carbon into the
+    /// controller's escrow. Bids are made in the settlement token configured
+    /// now, even if `set_xml_token` changes it before settlement. Returns
+    /// the auction id.
+    pub fn create_english_auction(
         e: Env,
+        seller: Address,
         asset_code: Symbol,
-        from: Address,
         amount: i128,
-        note: String,
-    ) {
-        // Clone because we also want to use asset_code in the event
+        min_bid: i128,
+        end: u64,
+    ) -> u64 {
+        seller.require_auth();
+        require_holder_not_frozen(&e, &seller);
+        require_mint_unlocked(&e, &asset_code, &seller);
+
         let meta = read_asset(&e, asset_code.clone());
+        require_trading_enabled(&e, asset_code.clone());
+        require_settlement_token(&e);
+        let bid_token = read_xml_token(&e);
 
-        // Holder must authorize the burn
-        from.require_auth();
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        if min_bid <= 0 {
+            panic!("min_bid must be positive");
+        }
+        if end <= e.ledger().timestamp() {
+            panic!("auction end is in the past");
+        }
 
-        // Standard token interface for burn / transfer
-        let token_client = TokenClient::new(&e, &meta.token);
-        token_client.burn(&from, &amount);
+        TokenClient::new(&e, &meta.token).transfer(&seller, e.current_contract_address(), &amount);
+        add_to_counter(&e, &DataKey::EscrowedCarbon(asset_code.clone()), amount);
+
+        let id: u64 = e
+            .storage()
+            .instance()
+            .get(&DataKey::NextAuctionId)
+            .unwrap_or(0);
+        e.storage()
+            .instance()
+            .set(&DataKey::NextAuctionId, &(id + 1));
 
-        // Emit a carbon-specific event your indexer / backend can listen to
-        CarbonRetireEvent {
+        let auction = EnglishAuction {
+            id,
             asset_code,
-            holder: from,
+            seller,
             amount,
-            project_id: meta.project_id,
-            vintage_year: meta.vintage_year,
-            note,
+            min_bid,
+            end,
+            bids: Vec::new(&e),
+            bid_token,
+        };
+        write_persistent(&e, &DataKey::EnglishAuction(id), &auction);
+
+        id
+    }
+
+    /// Place or raise a bid. `amount_xml` is the bidder's new total bid; only
+    /// the difference from any earlier bid is pulled into escrow.
+    pub fn place_bid(e: Env, bidder: Address, auction_id: u64, amount_xml: i128) {
+        bidder.require_auth();
+        require_holder_not_frozen(&e, &bidder);
+
+        let mut auction = read_english_auction(&e, auction_id);
+        require_trading_enabled(&e, auction.asset_code.clone());
+        if e.ledger().timestamp() >= auction.end {
+            panic!("auction ended");
+        }
+        if amount_xml < auction.min_bid {
+            panic!("bid below minimum");
+        }
+
+        let mut highest = 0;
+        let mut existing: Option<u32> = None;
+        for (i, bid) in auction.bids.iter().enumerate() {
+            if bid.amount_xml > highest {
+                highest = bid.amount_xml;
+            }
+            if bid.bidder == bidder {
+                existing = Some(i as u32);
+            }
+        }
+        if amount_xml <= highest {
+            panic!("bid must beat the highest bid");
+        }
+
+        check_trade_size(&e, auction.asset_code.clone(), &bidder, amount_xml);
+
+        let already_escrowed = match existing {
+            Some(i) => auction.bids.get(i).unwrap().amount_xml,
+            None => {
+                if auction.bids.len() >= MAX_BIDDERS {
+                    panic!("too many bidders");
+                }
+                0
+            }
+        };
+
+        TokenClient::new(&e, &auction.bid_token).transfer(
+            &bidder,
+            e.current_contract_address(),
+            &(amount_xml - already_escrowed),
+        );
+        add_to_counter(&e, &DataKey::EscrowedXml, amount_xml - already_escrowed);
+
+        let bid = AuctionBid {
+            bidder: bidder.clone(),
+            amount_xml,
+        };
+        match existing {
+            Some(i) => auction.bids.set(i, bid),
+            None => auction.bids.push_back(bid),
+        }
+        write_persistent(&e, &DataKey::EnglishAuction(auction_id), &auction);
+
+        if event_enabled(&e, EVENT_BID_PLACED) {
+            BidPlacedEvent {
+                auction_id,
+                bidder,
+                amount_xml,
+            }
+            .publish(&e);
+        }
+    }
+
+    /// Close an English auction after `end`. Anyone may call it: the highest
+    /// bidder receives the carbon, the seller the winning bid, and every
+    /// other bidder is refunded. Can't settle while the winner or seller
+    /// is frozen.
+    pub fn settle_auction(e: Env, auction_id: u64) {
+        let auction = read_english_auction(&e, auction_id);
+        if e.ledger().timestamp() < auction.end {
+            panic!("auction still running");
+        }
+
+        let meta = read_asset(&e, auction.asset_code.clone());
+        let contract = e.current_contract_address();
+        let xml_client = TokenClient::new(&e, &auction.bid_token);
+        let carbon_client = TokenClient::new(&e, &meta.token);
+
+        let mut winner: Option<AuctionBid> = None;
+        for bid in auction.bids.iter() {
+            if winner
+                .as_ref()
+                .is_none_or(|w| bid.amount_xml > w.amount_xml)
+            {
+                winner = Some(bid);
+            }
+        }
+
+        let mut price_xml = 0;
+        match &winner {
+            Some(win) => {
+                require_holder_not_frozen(&e, &win.bidder);
+                require_holder_not_frozen(&e, &auction.seller);
+                for bid in auction.bids.iter() {
+                    if bid.bidder != win.bidder {
+                        xml_client.transfer(&contract, &bid.bidder, &bid.amount_xml);
+                    }
+                }
+                xml_client.transfer(&contract, &auction.seller, &win.amount_xml);
+                carbon_client.transfer(&contract, &win.bidder, &auction.amount);
+
+                record_trade(&e, &auction.asset_code, auction.amount, win.amount_xml);
+                price_xml = win.amount_xml;
+            }
+            None => carbon_client.transfer(&contract, &auction.seller, &auction.amount),
+        }
+
+        let mut bids_xml: i128 = 0;
+        for bid in auction.bids.iter() {
+            bids_xml += bid.amount_xml;
+        }
+        add_to_counter(&e, &DataKey::EscrowedXml, -bids_xml);
+        add_to_counter(
+            &e,
+            &DataKey::EscrowedCarbon(auction.asset_code.clone()),
+            -auction.amount,
+        );
+
+        e.storage()
+            .persistent()
+            .remove(&DataKey::EnglishAuction(auction_id));
+
+        AuctionSettledEvent {
+            auction_id,
+            asset_code: auction.asset_code,
+            seller: auction.seller,
+            winner: winner.map(|w| w.bidder),
+            amount: auction.amount,
+            price_xml,
         }
         .publish(&e);
     }
 
-    /// Simple read method to debug / inspect from frontend
-    pub fn asset_info(e: Env, asset_code: Symbol) -> CarbonAssetMeta {
-        read_asset(&e, asset_code)
+    /// Read an English auction and its current bids.
+    pub fn english_auction(e: Env, auction_id: u64) -> EnglishAuction {
+        read_english_auction(&e, auction_id)
     }
 
-    /// Set which token contract is used as "money" (XML).
-    /// You can restrict this to an admin pattern later if you want.
-    pub fn set_xml_token(e: Env, caller: Address, xml_token: Address) {
-        caller.require_auth();
+    /// XML the controller holds in escrow for bids on open English auctions,
+    /// for reconciling against its token balance.
+    pub fn escrowed_xml(e: Env) -> i128 {
+        read_counter(&e, &DataKey::EscrowedXml)
+    }
 
-        e.storage().instance().set(&DataKey::XmlToken, &xml_token);
+    /// Units of `asset_code` the controller holds as English-auction lots.
+    pub fn escrowed_carbon(e: Env, asset_code: Symbol) -> i128 {
+        read_counter(&e, &DataKey::EscrowedCarbon(asset_code))
     }
 
-    /// Seller creates or updates a listing for an asset.
-    ///
-    /// IMPORTANT: Off-chain, seller must first call:
-    ///   carbon_token.approve(controller, amount)
-    /// so this contract can move `amount` tokens later.
-    pub fn list_asset(e: Env, seller: Address, asset_code: Symbol, amount: i128, price: i128) {
-        seller.require_auth();
+    /// The controller's own on-chain balance of `token`, to reconcile against
+    /// what it tracks as held: `escrowed_xml`, `escrowed_carbon`, `custodied`
+    /// and outstanding credits.
+    pub fn contract_balance(e: Env, token: Address) -> i128 {
+        TokenClient::new(&e, &token).balance(&e.current_contract_address())
+    }
 
-        // Ensure the asset exists (panic if not)
-        let _meta = read_asset(&e, asset_code.clone());
+    /// Page through the last `MAX_TRADE_LOG_LEN` purchases across all
+    /// assets, oldest first. Older trades are only in the event stream.
+    pub fn recent_trades(e: Env, start: u32, limit: u32) -> Vec<TradeRecord> {
+        page(&read_trade_log(&e), start, limit)
+    }
 
-        if amount <= 0 {
-            panic!("amount must be positive");
+    /// An asset's traded volume, retirements and trade count on UTC day
+    /// `day` (ledger timestamp / 86400). Zeroes for quiet days.
+    pub fn daily_stats(e: Env, asset_code: Symbol, day: u64) -> DailyStats {
+        read_daily_stats(&e, &asset_code, day)
+    }
+
+    /// Keeper hook: anyone may publish the `DailySummaryEvent` rollup for an
+    /// asset's finished UTC day, once per `(asset_code, day)`, so indexers
+    /// don't have to rebuild daily stats from every trade.
+    pub fn emit_daily_summary(e: Env, asset_code: Symbol, day: u64) {
+        read_asset(&e, asset_code.clone());
+        if day >= e.ledger().timestamp() / SECONDS_PER_DAY {
+            panic!("day not over yet");
         }
-        if price <= 0 {
-            panic!("price must be positive");
+        let emitted_key = ExtKey::DailySummaryEmitted(asset_code.clone(), day);
+        if e.storage().persistent().has(&emitted_key) {
+            panic!("daily summary already emitted");
         }
+        write_persistent(&e, &emitted_key, &true);
 
-        let key = DataKey::Listing(asset_code.clone(), seller.clone());
-        let listing = Listing {
+        let stats = read_daily_stats(&e, &asset_code, day);
+        DailySummaryEvent {
             asset_code,
-            seller,
-            amount,
-            price,
+            day,
+            volume: stats.volume,
+            retired: stats.retired,
+            trade_count: stats.trade_count,
+        }
+        .publish(&e);
+    }
+
+    /// Contract-wide totals: assets, open listings, traded volume and retired units.
+    pub fn global_stats(e: Env) -> GlobalStats {
+        read_global_stats(&e)
+    }
+
+    /// Interface revision of this build (see `SCHEMA_VERSION`).
+    pub fn schema_version(_e: Env) -> u32 {
+        SCHEMA_VERSION
+    }
+
+    /// Which optional behaviours this deployment has configured.
+    pub fn features(e: Env) -> FeatureFlags {
+        let instance = e.storage().instance();
+        FeatureFlags {
+            fees_enabled: read_fee_config(&e).is_some(),
+            primary_fee_override: instance.has(&DataKey::PrimaryFeeBps),
+            credit_fallback: is_credit_fallback_enabled(&e),
+            retire_quorum: read_retire_quorum(&e).is_some(),
+            retirement_nft: instance.has(&ExtKey::RetirementNftContract),
+            retirement_oracle: instance.has(&ExtKey::RetirementOracle),
+            listing_expiry: read_max_listing_duration(&e) > 0,
+            price_cooldown: read_min_update_interval(&e) > 0,
+            matching_decimals: instance
+                .get(&ExtKey::RequireMatchingDecimals)
+                .unwrap_or(false),
+            buyback_fees_waived: read_waive_buyback_fees(&e),
+            paused: is_paused(&e),
+        }
+    }
+
+    /// All of a seller's active listings, across assets.
+    pub fn my_listings(e: Env, seller: Address) -> Vec<Listing> {
+        let mut out = Vec::new(&e);
+        for code in read_listings_of(&e, &seller).iter() {
+            out.push_back(read_listing(&e, code, seller.clone()));
+        }
+        out
+    }
+
+    /// All active listings for an asset.
+    pub fn listings_for(e: Env, asset_code: Symbol) -> Vec<Listing> {
+        let mut out = Vec::new(&e);
+        for seller in read_listing_sellers(&e, &asset_code).iter() {
+            out.push_back(read_listing(&e, asset_code.clone(), seller));
+        }
+        out
+    }
+
+    /// Batch lookup of specific listings by `(asset_code, seller)`, for
+    /// dashboards tracking listings across many sellers. Results line up
+    /// with `keys`; missing listings come back as `None`.
+    pub fn get_listings(e: Env, keys: Vec<(Symbol, Address)>) -> Vec<Option<Listing>> {
+        if keys.len() > MAX_BATCH_SIZE {
+            panic!("too many listing keys");
+        }
+
+        let mut out = Vec::new(&e);
+        for (asset_code, seller) in keys.iter() {
+            out.push_back(
+                e.storage()
+                    .persistent()
+                    .get(&DataKey::Listing(asset_code, seller)),
+            );
+        }
+        out
+    }
+
+    /// Last `MAX_HISTORY_LEN` prices a seller's listing was set to, oldest
+    /// first, as `(price, timestamp)`. Kept after the listing closes.
+    pub fn price_history(e: Env, asset_code: Symbol, seller: Address) -> Vec<(i128, u64)> {
+        read_price_history(&e, &asset_code, &seller)
+    }
+
+    /// Units a seller has committed across their listing and Dutch auction
+    /// for an asset.
+    pub fn listed_total(e: Env, asset_code: Symbol, seller: Address) -> i128 {
+        read_listed_total(&e, &asset_code, &seller)
+    }
+
+    /// Whether the listing is inside its `[starts_at, expires_at)` window
+    /// right now. Disputes are reported separately on the listing.
+    pub fn is_listing_active(e: Env, asset_code: Symbol, seller: Address) -> bool {
+        let listing = read_listing(&e, asset_code, seller);
+        is_listing_active(&e, &listing)
+    }
+
+    /// Seconds since the listing was first created, for "listed 3 days ago"
+    /// badges. Updates and repricing don't reset it.
+    pub fn listing_age(e: Env, asset_code: Symbol, seller: Address) -> u64 {
+        let listing = read_listing(&e, asset_code, seller);
+        e.ledger().timestamp().saturating_sub(listing.created_at)
+    }
+
+    /// XML value still available in a listing (`remaining amount * price`),
+    /// or 0 if there is no such listing.
+    pub fn listing_value(e: Env, asset_code: Symbol, seller: Address) -> i128 {
+        e.storage()
+            .persistent()
+            .get::<DataKey, Listing>(&DataKey::Listing(asset_code, seller))
+            .map_or(0, |l| xml_cost(&e, l.amount, l.price))
+    }
+
+    /// What buying out a listing retires: its remaining amount in whole
+    /// units of the asset (token decimals applied, rounded down) and the
+    /// asset's `unit_label`. 0 if there is no such listing.
+    pub fn listing_impact(e: Env, asset_code: Symbol, seller: Address) -> (i128, Symbol) {
+        let meta = read_asset(&e, asset_code.clone());
+        let label = read_unit_label(&e, &asset_code);
+        let Some(listing) = e
+            .storage()
+            .persistent()
+            .get::<DataKey, Listing>(&DataKey::Listing(asset_code, seller))
+        else {
+            return (0, label);
         };
 
-        e.storage().instance().set(&key, &listing);
+        let decimals = TokenClient::new(&e, &meta.token).decimals();
+        (listing.amount / 10i128.pow(decimals), label)
     }
 
-    /// Buyer purchases `amount` units of `asset_code` from a specific seller,
-    /// paying with XML token in a single atomic call.
-    ///
-    /// Off-chain:
-    ///  - Seller must have approved controller for at least `amount` of carbon token.
-    ///  - Buyer must have approved controller for at least `max_xml` of XML token.
-    pub fn buy_with_xml(
+    /// Quote buying `amount` from a listing, including whether the seller's
+    /// balance and allowance would let the trade settle right now.
+    pub fn quote_detailed(e: Env, asset_code: Symbol, seller: Address, amount: i128) -> Quote {
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        let listing = read_listing(&e, asset_code.clone(), seller.clone());
+        if amount > listing.amount {
+            panic!("not enough listed amount");
+        }
+        let meta = read_asset(&e, asset_code.clone());
+
+        let cost_xml = xml_cost(&e, amount, listing.price);
+        let fee_bps = effective_fee_bps(&e, asset_code, listing.is_primary);
+        let token_client = TokenClient::new(&e, &meta.token);
+        Quote {
+            cost_xml,
+            fee_bps,
+            fee_xml: fee_amount(cost_xml, fee_bps),
+            // Custodial listings settle from the controller's own balance
+            seller_approval_ok: listing.custodial
+                || token_client.allowance(&seller, &e.current_contract_address()) >= amount,
+            seller_balance_ok: listing.custodial || token_client.balance(&seller) >= amount,
+        }
+    }
+
+    /// Buyer-side pre-flight for `buy_with_xml`: `(approval_ok, balance_ok,
+    /// required_xml)`, checking the buyer's allowance to the controller and
+    /// balance in the listing's settlement token against the full cost.
+    pub fn buyer_ready(
         e: Env,
         buyer: Address,
         asset_code: Symbol,
         seller: Address,
         amount: i128,
-        max_xml: i128,
-    ) {
-        buyer.require_auth();
+    ) -> (bool, bool, i128) {
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        let listing = read_listing(&e, asset_code.clone(), seller);
+        if amount > listing.amount {
+            panic!("not enough listed amount");
+        }
+        let meta = read_asset(&e, asset_code);
+
+        let required_xml = xml_cost(&e, amount, listing.price);
+        let xml_client = TokenClient::new(&e, &read_settlement_token(&e, &meta));
+        (
+            xml_client.allowance(&buyer, &e.current_contract_address()) >= required_xml,
+            xml_client.balance(&buyer) >= required_xml,
+            required_xml,
+        )
+    }
 
+    /// Largest amount `buyer` could take from `seller`'s listing right now,
+    /// given their XML balance and the allowance they've granted this
+    /// contract. Fees and conservation come out of the seller's proceeds,
+    /// so the buyer only has to cover the `xml_cost` of the fill; the
+    /// result is the largest amount whose cost fits, capped by what's
+    /// listed. Returns 0 if they can't afford a single unit.
+    pub fn affordable_amount(e: Env, buyer: Address, asset_code: Symbol, seller: Address) -> i128 {
+        let listing = read_listing(&e, asset_code.clone(), seller);
+        let meta = read_asset(&e, asset_code);
+
+        let xml_client = TokenClient::new(&e, &read_settlement_token(&e, &meta));
+        let budget = xml_client
+            .allowance(&buyer, &e.current_contract_address())
+            .min(xml_client.balance(&buyer));
+        if budget <= 0 {
+            return 0;
+        }
+        // xml_cost rounds up, so cost(amount) <= budget exactly when
+        // amount * price <= budget * 10^scale.
+        match checked_mul_div(budget, 10i128.pow(read_price_scale(&e)), listing.price) {
+            Some(amount) => amount.min(listing.amount),
+            None => listing.amount,
+        }
+    }
+
+    /// `buyer`'s XML balance left after buying `amount` from `seller`'s
+    /// listing, for "spend down to X" planning. The buyer pays the full
+    /// `xml_cost`; fees and conservation come out of that, not on top.
+    /// Panics with "xml balance insufficient" if the balance can't cover it.
+    pub fn balance_after_buy(
+        e: Env,
+        buyer: Address,
+        asset_code: Symbol,
+        seller: Address,
+        amount: i128,
+    ) -> i128 {
         if amount <= 0 {
             panic!("amount must be positive");
         }
+        let listing = read_listing(&e, asset_code.clone(), seller);
+        if amount > listing.amount {
+            panic!("not enough listed amount");
+        }
+        let meta = read_asset(&e, asset_code);
 
-        // Read listing
-        let listing_key = DataKey::Listing(asset_code.clone(), seller.clone());
-        let mut listing: Listing = e
-            .storage()
-            .instance()
-            .get(&listing_key)
-            .unwrap_or_else(|| panic!("listing not found"));
+        let cost_xml = xml_cost(&e, amount, listing.price);
+        let balance = TokenClient::new(&e, &read_settlement_token(&e, &meta)).balance(&buyer);
+        if balance < cost_xml {
+            panic!("xml balance insufficient");
+        }
+        balance - cost_xml
+    }
 
+    /// How buying `amount` from a listing would split:
+    /// `(total, seller_proceeds, fee, royalty, conservation)`, resolved
+    /// exactly as `buy_with_xml` does. There are no issuer royalties yet, so
+    /// `royalty` is always 0; the other three parts sum to `total`.
+    pub fn quote_breakdown(
+        e: Env,
+        asset_code: Symbol,
+        seller: Address,
+        amount: i128,
+    ) -> (i128, i128, i128, i128, i128) {
+        if amount <= 0 {
+            panic!("amount must be positive");
+        }
+        let listing = read_listing(&e, asset_code.clone(), seller);
         if amount > listing.amount {
             panic!("not enough listed amount");
         }
+        let meta = read_asset(&e, asset_code);
 
-        // Read asset meta (to get carbon token contract)
-        let meta = read_asset(&e, asset_code.clone());
+        let total = xml_cost(&e, amount, listing.price);
+        let fee = listing_fee(&e, &listing, total);
+        let conservation = conservation_amount(&meta, total);
+        (total, total - fee - conservation, fee, 0, conservation)
+    }
 
-        // Read XML token address
-        let xml_token: Address = e
-            .storage()
-            .instance()
-            .get(&DataKey::XmlToken)
-            .unwrap_or_else(|| panic!("XML token not set"));
+    /// What a seller nets per unit listing `asset_code` at `price` (in the
+    /// same units as `price`), after the marketplace fee and the asset's
+    /// conservation share, split as a trade would (there are no issuer
+    /// royalties). This is the
+    /// secondary-market rate; the issuer's own primary listings pay
+    /// `PrimaryFeeBps` where configured. Trades round on their total, so a
+    /// multi-unit fill can net slightly more than `amount` times this.
+    pub fn seller_net_per_unit(e: Env, asset_code: Symbol, price: i128) -> i128 {
+        if price <= 0 {
+            panic!("price must be positive");
+        }
+        let meta = read_asset(&e, asset_code.clone());
 
-        // Compute cost_xml = amount * price
-        let cost_xml = amount
-            .checked_mul(listing.price)
-            .expect("overflow in price calc");
+        let fee = fee_amount(price, effective_fee_bps(&e, asset_code, false));
+        price - fee - conservation_amount(&meta, price)
+    }
 
-        if cost_xml > max_xml {
-            panic!("price exceeds max_xml");
+    /// Show which sellers buying `amount` across the book would hit, at what
+    /// cost, without executing anything.
+    pub fn preview_fill(e: Env, asset_code: Symbol, amount: i128) -> FillPreview {
+        if amount <= 0 {
+            panic!("amount must be positive");
         }
 
-        // 1) XML: buyer -> seller
-        let xml_client = TokenClient::new(&e, &xml_token);
-        xml_client.transfer_from(&buyer, &buyer, &seller, &cost_xml);
+        let fills = plan_fill(&e, &asset_code, amount, &Vec::new(&e), 0);
+        let mut total_amount = 0;
+        let mut total_cost: i128 = 0;
+        for (_, fill, cost) in fills.iter() {
+            total_amount += fill;
+            total_cost = total_cost
+                .checked_add(cost)
+                .unwrap_or_else(|| panic!("overflow in fill cost"));
+        }
+        FillPreview {
+            fills,
+            total_amount,
+            total_cost,
+        }
+    }
 
-        // 2) Carbon: seller -> buyer
-        let carbon_client = TokenClient::new(&e, &meta.token);
-        carbon_client.transfer_from(&seller, &seller, &buyer, &amount);
+    /// Cheapest active listing for an asset as `(seller, price, available_amount)`,
+    /// or `None` if nobody is selling. Ties go to the earliest listing; disputed,
+    /// not yet started and expired listings are skipped.
+    pub fn best_ask(e: Env, asset_code: Symbol) -> Option<(Address, i128, i128)> {
+        let mut best: Option<Listing> = None;
+        for seller in read_listing_sellers(&e, &asset_code).iter() {
+            let listing = read_listing(&e, asset_code.clone(), seller);
+            if listing.disputed || !is_listing_active(&e, &listing) {
+                continue;
+            }
+            if best.as_ref().is_none_or(|b| listing.price < b.price) {
+                best = Some(listing);
+            }
+        }
+        best.map(|l| (l.seller, l.price, l.amount))
+    }
 
-        // Update or remove listing
-        listing.amount -= amount;
-        if listing.amount > 0 {
-            e.storage().instance().set(&listing_key, &listing);
-        } else {
-            e.storage().instance().remove(&listing_key);
+    /// Depth of book: `(price, total_amount)` per distinct price across the
+    /// asset's active listings, cheapest first, at most `max_levels` levels.
+    /// Skips the same listings as `best_ask`.
+    pub fn order_book(e: Env, asset_code: Symbol, max_levels: u32) -> Vec<(i128, i128)> {
+        let mut levels: Vec<(i128, i128)> = Vec::new(&e);
+        for seller in read_listing_sellers(&e, &asset_code).iter() {
+            let listing = read_listing(&e, asset_code.clone(), seller);
+            if listing.disputed || !is_listing_active(&e, &listing) {
+                continue;
+            }
+            let at = levels
+                .iter()
+                .position(|(price, _)| price >= listing.price)
+                .map_or(levels.len(), |i| i as u32);
+            match levels.get(at) {
+                Some((price, total)) if price == listing.price => {
+                    let total = total
+                        .checked_add(listing.amount)
+                        .unwrap_or_else(|| panic!("overflow in order book level"));
+                    levels.set(at, (price, total));
+                }
+                _ => levels.insert(at, (listing.price, listing.amount)),
+            }
         }
+        levels.slice(0..levels.len().min(max_levels))
     }
 }
+
+#[cfg(test)]
+mod test;
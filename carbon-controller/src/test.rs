@@ -0,0 +1,1839 @@
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::testutils::{
+    storage::Persistent as _, Address as _, Events, IssuerFlags, Ledger, MockAuth, MockAuthInvoke,
+};
+use soroban_sdk::token::StellarAssetClient;
+use soroban_sdk::{contract, contractimpl, vec, Map, TryFromVal};
+
+/// A token whose `decimals` no price math can handle.
+#[contract]
+pub struct BadDecimalsToken;
+
+#[contractimpl]
+impl BadDecimalsToken {
+    pub fn decimals(_e: Env) -> u32 {
+        255
+    }
+}
+
+struct Setup<'a> {
+    e: Env,
+    admin: Address,
+    asset_admin: Address,
+    issuer: Address,
+    code: Symbol,
+    carbon: TokenClient<'a>,
+    xml: TokenClient<'a>,
+    xml_admin: StellarAssetClient<'a>,
+    client: CarbonControllerClient<'a>,
+}
+
+/// Controller with XML configured and one registered asset whose issuer
+/// holds 1000 units. The carbon token's SAC admin is the controller.
+fn setup<'a>() -> Setup<'a> {
+    let e = Env::default();
+    e.mock_all_auths();
+    let admin = Address::generate(&e);
+    let id = e.register(CarbonController, (&admin,));
+    let client = CarbonControllerClient::new(&e, &id);
+
+    let xml_sac = e.register_stellar_asset_contract_v2(Address::generate(&e));
+    xml_sac.issuer().set_flag(IssuerFlags::RevocableFlag);
+    client.set_xml_token(&admin, &xml_sac.address());
+
+    let carbon_sac = e.register_stellar_asset_contract_v2(id.clone());
+    let code = Symbol::new(&e, "ZORLU23");
+    let asset_admin = Address::generate(&e);
+    client.register_asset(
+        &code,
+        &1,
+        &2023,
+        &carbon_sac.address(),
+        &asset_admin,
+        &verra(&e),
+    );
+
+    let issuer = Address::generate(&e);
+    client.mint_to_issuer(&code, &issuer, &1_000);
+
+    Setup {
+        carbon: TokenClient::new(&e, &carbon_sac.address()),
+        xml: TokenClient::new(&e, &xml_sac.address()),
+        xml_admin: StellarAssetClient::new(&e, &xml_sac.address()),
+        e,
+        admin,
+        asset_admin,
+        issuer,
+        code,
+        client,
+    }
+}
+
+impl Setup<'_> {
+    /// The asset's lone admin, as the signer set for asset-admin actions.
+    fn admins(&self) -> Vec<Address> {
+        vec![&self.e, self.asset_admin.clone()]
+    }
+
+    fn expiration(&self) -> u32 {
+        self.e.ledger().sequence() + 1_000
+    }
+
+    /// Lets the controller move `owner`'s carbon, as a seller does once.
+    fn approve(&self, owner: &Address) {
+        self.carbon
+            .approve(owner, &self.client.address, &1_000_000, &self.expiration());
+    }
+
+    fn list(&self, seller: &Address, amount: i128, price: i128) -> u64 {
+        self.approve(seller);
+        self.client
+            .list_asset(seller, &self.code, &amount, &price, &0)
+    }
+
+    /// A new buyer holding `xml` XML, all of it approved to the controller.
+    fn buyer(&self, xml: i128) -> Address {
+        let buyer = Address::generate(&self.e);
+        self.xml_admin.mint(&buyer, &xml);
+        self.xml
+            .approve(&buyer, &self.client.address, &xml, &self.expiration());
+        buyer
+    }
+
+    fn buy(&self, buyer: &Address, seller: &Address, amount: i128) {
+        self.client.buy_with_xml(
+            buyer,
+            &self.code,
+            seller,
+            &amount,
+            &i128::MAX,
+            &0,
+            &None,
+            &None,
+        );
+    }
+
+    fn listing(&self, seller: &Address) -> Option<Listing> {
+        self.client
+            .get_listings(&vec![&self.e, (self.code.clone(), seller.clone())])
+            .get(0)
+            .unwrap()
+    }
+
+    fn retire(&self, from: &Address, amount: i128) -> u64 {
+        self.client.retire(
+            &self.code,
+            from,
+            &amount,
+            &String::from_str(&self.e, "offset"),
+            &None,
+            &None,
+            &None,
+        )
+    }
+
+    /// Registers a second asset of the same project and mints the issuer
+    /// 1000 units of it.
+    fn add_asset(&self, name: &str, vintage_year: i32) -> (Symbol, TokenClient<'_>) {
+        let code = Symbol::new(&self.e, name);
+        let sac = self
+            .e
+            .register_stellar_asset_contract_v2(self.client.address.clone());
+        self.client.register_asset(
+            &code,
+            &1,
+            &vintage_year,
+            &sac.address(),
+            &self.asset_admin,
+            &verra(&self.e),
+        );
+        self.client.mint_to_issuer(&code, &self.issuer, &1_000);
+        (code, TokenClient::new(&self.e, &sac.address()))
+    }
+}
+
+/// Data of the last event published under `name` by the last invocation.
+fn find_event(e: &Env, name: &str) -> Option<Map<Symbol, Val>> {
+    let name = Symbol::new(e, name);
+    let mut found = None;
+    for (_, topics, data) in e.events().all().iter() {
+        let topic = topics.get(0).and_then(|t| Symbol::try_from_val(e, &t).ok());
+        if topic == Some(name.clone()) {
+            found = Some(Map::try_from_val(e, &data).ok().unwrap());
+        }
+    }
+    found
+}
+
+fn field<T: TryFromVal<Env, Val>>(e: &Env, data: &Map<Symbol, Val>, key: &str) -> T {
+    T::try_from_val(e, &data.get(Symbol::new(e, key)).unwrap())
+        .ok()
+        .unwrap()
+}
+
+/// VERRA serials 1..=1000 with no retirement lock, the provenance every
+/// test asset registers with.
+fn verra(e: &Env) -> Provenance {
+    Provenance {
+        standard: Symbol::new(e, "VERRA"),
+        serial_start: 1,
+        serial_end: 1_000,
+        retire_not_before: 0,
+    }
+}
+
+fn text(e: &Env, len: usize) -> String {
+    String::from_str(e, &"x".repeat(len))
+}
+
+/// Deterministic operands for the property tests (xorshift), spread over
+/// small values and values whose product overflows i128.
+fn operands(count: usize) -> std::vec::Vec<(i128, i128, i128)> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let mut pick = |bits: u32| {
+        let wide = ((next() as u128) << 64 | next() as u128) >> (128 - bits);
+        wide as i128
+    };
+    (0..count)
+        .map(|i| {
+            let bits = [16, 64, 100, 127][i % 4];
+            (pick(bits), pick(bits), pick(64).max(1))
+        })
+        .collect()
+}
+
+#[test]
+#[should_panic(expected = "token decimals out of range")]
+fn register_asset_rejects_out_of_range_decimals() {
+    let s = setup();
+    let token = s.e.register(BadDecimalsToken, ());
+    s.client.register_asset(
+        &Symbol::new(&s.e, "BAD"),
+        &2,
+        &2023,
+        &token,
+        &s.asset_admin,
+        &verra(&s.e),
+    );
+}
+
+#[test]
+#[should_panic(expected = "token decimals out of range")]
+fn set_xml_token_rejects_out_of_range_decimals() {
+    let s = setup();
+    let token = s.e.register(BadDecimalsToken, ());
+    s.client.set_xml_token(&s.admin, &token);
+}
+
+#[test]
+fn in_range_decimals_trade_normally() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let buyer = s.buyer(1_000);
+    s.buy(&buyer, &s.issuer, 10);
+    assert_eq!(s.carbon.balance(&buyer), 10);
+    assert_eq!(s.xml.balance(&s.issuer), 100);
+}
+
+#[test]
+fn constructor_sets_admin() {
+    let s = setup();
+    assert_eq!(s.client.admin(), s.admin);
+}
+
+#[test]
+fn admin_can_take_down_a_listing() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let reason = String::from_str(&s.e, "reported");
+
+    let outsider = Address::generate(&s.e);
+    assert!(s
+        .client
+        .try_admin_cancel_listing(&outsider, &s.code, &s.issuer, &reason)
+        .is_err());
+    assert!(s.listing(&s.issuer).is_some());
+
+    s.client
+        .admin_cancel_listing(&s.admin, &s.code, &s.issuer, &reason);
+    assert!(s.listing(&s.issuer).is_none());
+    assert_eq!(s.client.listed_total(&s.code, &s.issuer), 0);
+}
+
+#[test]
+fn cosigned_mint_needs_both_signatures() {
+    let s = setup();
+    let issuer = Address::generate(&s.e);
+    let invoke = MockAuthInvoke {
+        contract: &s.client.address,
+        fn_name: "mint_to_issuer_cosigned",
+        args: (s.code.clone(), issuer.clone(), 100_i128).into_val(&s.e),
+        sub_invokes: &[],
+    };
+    let admin_auth = MockAuth {
+        address: &s.asset_admin,
+        invoke: &invoke,
+    };
+    let issuer_auth = MockAuth {
+        address: &issuer,
+        invoke: &invoke,
+    };
+
+    s.e.mock_auths(core::slice::from_ref(&admin_auth));
+    assert!(s
+        .client
+        .try_mint_to_issuer_cosigned(&s.code, &issuer, &100)
+        .is_err());
+
+    s.e.mock_auths(core::slice::from_ref(&issuer_auth));
+    assert!(s
+        .client
+        .try_mint_to_issuer_cosigned(&s.code, &issuer, &100)
+        .is_err());
+
+    s.e.mock_auths(&[admin_auth, issuer_auth]);
+    s.client.mint_to_issuer_cosigned(&s.code, &issuer, &100);
+    assert_eq!(s.carbon.balance(&issuer), 100);
+}
+
+#[test]
+#[should_panic(expected = "overflow in mul_div: 170141183460469231731687303715884105727 * 2 / 1")]
+fn mul_div_overflow_names_operands() {
+    mul_div(i128::MAX, 2, 1);
+}
+
+#[test]
+#[should_panic(expected = "overflow in mul_div")]
+fn xml_cost_overflow_panics() {
+    let s = setup();
+    s.e.as_contract(&s.client.address, || xml_cost(&s.e, i128::MAX, 2));
+}
+
+#[test]
+fn dutch_auction_price_decays_linearly() {
+    let s = setup();
+    s.approve(&s.issuer);
+    s.client
+        .create_dutch_auction(&s.issuer, &s.code, &100, &100, &50, &100, &200);
+    assert_eq!(s.client.auction_price(&s.code, &s.issuer), 100);
+
+    s.e.ledger().set_timestamp(150);
+    assert_eq!(s.client.auction_price(&s.code, &s.issuer), 75);
+    let buyer = s.buyer(1_000);
+    s.client.buy_auction(&buyer, &s.code, &s.issuer, &10, &750);
+    assert_eq!(s.carbon.balance(&buyer), 10);
+    assert_eq!(s.xml.balance(&s.issuer), 750);
+    assert_eq!(s.client.dutch_auction(&s.code, &s.issuer).amount, 90);
+
+    s.e.ledger().set_timestamp(250);
+    assert_eq!(s.client.auction_price(&s.code, &s.issuer), 50);
+    assert!(s
+        .client
+        .try_buy_auction(&buyer, &s.code, &s.issuer, &1, &50)
+        .is_err());
+}
+
+#[test]
+#[should_panic(expected = "price exceeds max_xml")]
+fn dutch_auction_respects_max_xml() {
+    let s = setup();
+    s.approve(&s.issuer);
+    s.client
+        .create_dutch_auction(&s.issuer, &s.code, &100, &100, &50, &100, &200);
+    s.e.ledger().set_timestamp(150);
+    let buyer = s.buyer(1_000);
+    s.client.buy_auction(&buyer, &s.code, &s.issuer, &10, &749);
+}
+
+#[test]
+fn dutch_auction_commits_balance_until_cancelled() {
+    let s = setup();
+    s.approve(&s.issuer);
+    s.client
+        .create_dutch_auction(&s.issuer, &s.code, &600, &100, &50, &100, &200);
+    assert!(s
+        .client
+        .try_list_asset(&s.issuer, &s.code, &500, &10, &0)
+        .is_err());
+
+    s.client.cancel_dutch_auction(&s.issuer, &s.code);
+    s.list(&s.issuer, 1_000, 10);
+    assert_eq!(s.client.listed_total(&s.code, &s.issuer), 1_000);
+}
+
+#[test]
+fn english_auction_pays_seller_and_refunds_losers() {
+    let s = setup();
+    s.e.ledger().set_timestamp(100);
+    let id = s
+        .client
+        .create_english_auction(&s.issuer, &s.code, &100, &50, &200);
+    assert_eq!(s.client.escrowed_carbon(&s.code), 100);
+
+    let alice = s.buyer(1_000);
+    let bob = s.buyer(1_000);
+    s.client.place_bid(&alice, &id, &60);
+    s.client.place_bid(&bob, &id, &80);
+    assert!(s.client.try_place_bid(&alice, &id, &70).is_err());
+    s.client.place_bid(&alice, &id, &90);
+    assert_eq!(s.xml.balance(&alice), 910);
+
+    assert!(s.client.try_settle_auction(&id).is_err());
+    s.e.ledger().set_timestamp(200);
+    s.client.settle_auction(&id);
+
+    assert_eq!(s.carbon.balance(&alice), 100);
+    assert_eq!(s.xml.balance(&bob), 1_000);
+    assert_eq!(s.xml.balance(&s.issuer), 90);
+    assert_eq!(s.client.escrowed_carbon(&s.code), 0);
+    assert_eq!(s.client.escrowed_xml(), 0);
+}
+
+#[test]
+fn english_auction_settles_in_its_own_token() {
+    let s = setup();
+    s.e.ledger().set_timestamp(100);
+    let id = s
+        .client
+        .create_english_auction(&s.issuer, &s.code, &100, &50, &200);
+    let bidder = s.buyer(1_000);
+    s.client.place_bid(&bidder, &id, &60);
+
+    let next =
+        s.e.register_stellar_asset_contract_v2(Address::generate(&s.e));
+    s.client.set_xml_token(&s.admin, &next.address());
+    s.e.ledger().set_timestamp(200);
+    s.client.settle_auction(&id);
+
+    assert_eq!(s.xml.balance(&s.issuer), 60);
+    assert_eq!(s.carbon.balance(&bidder), 100);
+}
+
+#[test]
+fn reprice_batch_replaces_and_cancels() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let none = Vec::<Symbol>::new(&s.e);
+
+    s.client.reprice_batch(
+        &s.issuer,
+        &none,
+        &vec![&s.e, (s.code.clone(), 200_i128, 12_i128)],
+        &false,
+    );
+    let listing = s.listing(&s.issuer).unwrap();
+    assert_eq!((listing.amount, listing.price), (200, 12));
+
+    s.client.reprice_batch(
+        &s.issuer,
+        &vec![&s.e, s.code.clone()],
+        &Vec::new(&s.e),
+        &true,
+    );
+    assert!(s.listing(&s.issuer).is_none());
+    assert_eq!(s.client.listed_total(&s.code, &s.issuer), 0);
+}
+
+#[test]
+fn reprice_batch_is_all_or_nothing() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    assert!(s
+        .client
+        .try_reprice_batch(
+            &s.issuer,
+            &vec![&s.e, s.code.clone()],
+            &vec![&s.e, (s.code.clone(), 100_i128, 0_i128)],
+            &false,
+        )
+        .is_err());
+    assert_eq!(s.listing(&s.issuer).unwrap().price, 10);
+}
+
+#[test]
+#[should_panic(expected = "asset token can only change via migrate_asset_token")]
+fn reregistering_cannot_swap_token() {
+    let s = setup();
+    let other =
+        s.e.register_stellar_asset_contract_v2(s.client.address.clone());
+    s.client.register_asset(
+        &s.code,
+        &1,
+        &2023,
+        &other.address(),
+        &s.asset_admin,
+        &verra(&s.e),
+    );
+}
+
+#[test]
+#[should_panic(expected = "open English auctions must settle before migrating")]
+fn migration_waits_for_english_auctions() {
+    let s = setup();
+    s.e.ledger().set_timestamp(100);
+    s.client
+        .create_english_auction(&s.issuer, &s.code, &100, &50, &200);
+    let next =
+        s.e.register_stellar_asset_contract_v2(s.client.address.clone());
+    s.client
+        .migrate_asset_token(&s.admin, &s.code, &s.admins(), &next.address(), &0);
+}
+
+#[test]
+fn listing_ids_are_sequential_and_stable_on_update() {
+    let s = setup();
+    let first = s.list(&s.issuer, 100, 10);
+    assert_eq!(s.list(&s.issuer, 200, 11), first);
+    assert_eq!(s.listing(&s.issuer).unwrap().listing_id, first);
+}
+
+#[test]
+fn cancelled_listing_id_is_not_reused() {
+    let s = setup();
+    let mut seen = Vec::new(&s.e);
+    for _ in 0..3 {
+        let id = s.list(&s.issuer, 100, 10);
+        assert!(!seen.contains(id));
+        seen.push_back(id);
+        s.client.cancel_listing(&s.issuer, &s.code);
+    }
+    assert_eq!(seen, vec![&s.e, 0_u64, 1, 2]);
+}
+
+#[test]
+fn buying_everything_removes_listing() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let buyer = s.buyer(1_000);
+    s.buy(&buyer, &s.issuer, 60);
+    assert_eq!(s.listing(&s.issuer).unwrap().amount, 40);
+    s.buy(&buyer, &s.issuer, 40);
+
+    assert!(s.listing(&s.issuer).is_none());
+    assert_eq!(s.client.listed_total(&s.code, &s.issuer), 0);
+    assert_eq!(s.client.total_listed(&s.code), 0);
+    assert_eq!(s.carbon.balance(&buyer), 100);
+}
+
+#[test]
+#[should_panic(expected = "not enough listed amount")]
+fn buying_more_than_listed_panics() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let buyer = s.buyer(10_000);
+    s.buy(&buyer, &s.issuer, 101);
+}
+
+#[test]
+fn remaining_after_stays_non_negative() {
+    assert_eq!(remaining_after(10, 10), 0);
+    assert_eq!(remaining_after(10, 3), 7);
+}
+
+#[test]
+#[should_panic(expected = "invariant violated: 11 taken from 10 available")]
+fn remaining_after_rejects_overdraw() {
+    remaining_after(10, 11);
+}
+
+#[test]
+fn registry_event_carries_the_registered_provenance() {
+    let s = setup();
+    s.retire(&s.issuer, 10);
+    let data = find_event(&s.e, "registry_retire_event").unwrap();
+    assert_eq!(field::<i128>(&s.e, &data, "amount"), 10);
+    assert_eq!(field::<i64>(&s.e, &data, "serial_start"), 1);
+    assert_eq!(field::<i64>(&s.e, &data, "project_id"), 1);
+    assert_eq!(
+        field::<Symbol>(&s.e, &data, "standard"),
+        Symbol::new(&s.e, "VERRA")
+    );
+}
+
+#[test]
+fn registry_event_after_metadata_uri() {
+    let s = setup();
+    let uri = String::from_str(&s.e, "https://registry.example/ZORLU23");
+    s.client.set_metadata_uri(&s.code, &s.admins(), &uri);
+    s.retire(&s.issuer, 5);
+    let data = find_event(&s.e, "registry_retire_event").unwrap();
+    assert_eq!(field::<String>(&s.e, &data, "metadata_uri"), uri);
+}
+
+#[test]
+fn certificate_records_beneficiary() {
+    let s = setup();
+    let name = String::from_str(&s.e, "Acme Logistics");
+    let cert = s.client.retire(
+        &s.code,
+        &s.issuer,
+        &25,
+        &String::from_str(&s.e, "2025 fleet"),
+        &Some(name.clone()),
+        &None,
+        &None,
+    );
+    let record = s.client.get_certificate(&cert);
+    assert_eq!(record.beneficiary_name, Some(name));
+    assert_eq!(record.holder, s.issuer);
+    assert_eq!(record.owner, s.issuer);
+    assert_eq!(record.amount, 25);
+
+    let plain = s.retire(&s.issuer, 5);
+    assert_eq!(s.client.get_certificate(&plain).beneficiary_name, None);
+    assert_eq!(s.carbon.balance(&s.issuer), 970);
+}
+
+#[test]
+fn undeliverable_proceeds_become_credit() {
+    let s = setup();
+    s.client.set_credit_fallback(&s.admin, &true);
+    s.list(&s.issuer, 100, 10);
+    s.xml_admin.set_authorized(&s.issuer, &false);
+
+    let buyer = s.buyer(1_000);
+    s.buy(&buyer, &s.issuer, 10);
+    assert_eq!(s.carbon.balance(&buyer), 10);
+    assert_eq!(s.client.credit_of(&s.issuer, &s.xml.address), 100);
+
+    s.xml_admin.set_authorized(&s.issuer, &true);
+    assert_eq!(s.client.withdraw_credit(&s.issuer, &s.xml.address), 100);
+    assert_eq!(s.xml.balance(&s.issuer), 100);
+    assert_eq!(s.client.credit_of(&s.issuer, &s.xml.address), 0);
+    assert!(s
+        .client
+        .try_withdraw_credit(&s.issuer, &s.xml.address)
+        .is_err());
+}
+
+#[test]
+fn undeliverable_proceeds_revert_without_fallback() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    s.xml_admin.set_authorized(&s.issuer, &false);
+    let buyer = s.buyer(1_000);
+    assert!(s
+        .client
+        .try_buy_with_xml(&buyer, &s.code, &s.issuer, &10, &100, &0, &None, &None)
+        .is_err());
+    assert_eq!(s.xml.balance(&buyer), 1_000);
+}
+
+#[test]
+fn xml_cost_rounds_up_at_every_scale() {
+    let s = setup();
+    s.e.as_contract(&s.client.address, || {
+        let cost_at = |scale: u32, amount, price| {
+            s.e.storage().instance().set(&DataKey::PriceScale, &scale);
+            xml_cost(&s.e, amount, price)
+        };
+        assert_eq!(cost_at(0, 3, 150), 450);
+        assert_eq!(cost_at(2, 3, 150), 5);
+        assert_eq!(cost_at(2, 2, 150), 3);
+        assert_eq!(cost_at(7, 1, 1), 1);
+        assert_eq!(cost_at(7, 20_000_000, 15_000_000), 30_000_000);
+    });
+}
+
+#[test]
+fn price_scale_applies_to_trades() {
+    let s = setup();
+    s.client.set_price_scale(&s.admin, &2);
+    s.list(&s.issuer, 100, 150);
+    let buyer = s.buyer(1_000);
+    s.buy(&buyer, &s.issuer, 3);
+    assert_eq!(s.xml.balance(&buyer), 995);
+}
+
+#[test]
+#[should_panic(expected = "price_scale can't change while listings are open")]
+fn price_scale_locked_while_listed() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    s.client.set_price_scale(&s.admin, &2);
+}
+
+#[test]
+#[should_panic(expected = "insufficient balance to retire: have 1000, need 1001")]
+fn underfunded_retire_reports_balance() {
+    let s = setup();
+    s.retire(&s.issuer, 1_001);
+}
+
+#[test]
+fn preview_fill_walks_cheapest_first() {
+    let s = setup();
+    let other = Address::generate(&s.e);
+    s.client.mint_to_issuer(&s.code, &other, &100);
+    s.list(&s.issuer, 100, 20);
+    s.list(&other, 50, 10);
+
+    let preview = s.client.preview_fill(&s.code, &120);
+    assert_eq!(
+        preview.fills,
+        vec![
+            &s.e,
+            (other.clone(), 50_i128, 500_i128),
+            (s.issuer.clone(), 70_i128, 1_400_i128),
+        ]
+    );
+    assert_eq!(preview.total_amount, 120);
+    assert_eq!(preview.total_cost, 1_900);
+    assert_eq!(s.listing(&other).unwrap().amount, 50);
+    assert_eq!(s.carbon.balance(&other), 100);
+}
+
+#[test]
+fn now_reads_ledger_timestamp() {
+    let s = setup();
+    assert_eq!(s.client.now(), 0);
+    s.e.ledger().set_timestamp(1_234);
+    assert_eq!(s.client.now(), 1_234);
+}
+
+#[test]
+#[should_panic(expected = "cannot mint to the controller contract")]
+fn mint_to_controller_rejected() {
+    let s = setup();
+    s.client.mint_to_issuer(&s.code, &s.client.address, &10);
+}
+
+#[test]
+fn pause_lifts_at_resume_time() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let buyer = s.buyer(1_000);
+    s.e.ledger().set_timestamp(100);
+
+    s.client.pause(&s.admin, &200);
+    assert_eq!(s.client.pause_status(), (true, 200));
+    assert!(s
+        .client
+        .try_buy_with_xml(&buyer, &s.code, &s.issuer, &10, &100, &0, &None, &None)
+        .is_err());
+
+    s.e.ledger().set_timestamp(200);
+    s.buy(&buyer, &s.issuer, 10);
+    assert_eq!(s.carbon.balance(&buyer), 10);
+}
+
+#[test]
+fn open_ended_pause_needs_unpause() {
+    let s = setup();
+    s.client.pause(&s.admin, &0);
+    s.e.ledger().set_timestamp(1_000_000);
+    assert!(s.client.try_mint_to_issuer(&s.code, &s.issuer, &1).is_err());
+
+    s.client.unpause(&s.admin);
+    s.client.mint_to_issuer(&s.code, &s.issuer, &1);
+    assert_eq!(s.carbon.balance(&s.issuer), 1_001);
+}
+
+#[test]
+#[should_panic(expected = "resume_at is in the past")]
+fn pause_rejects_past_resume_time() {
+    let s = setup();
+    s.e.ledger().set_timestamp(100);
+    s.client.pause(&s.admin, &50);
+}
+
+#[test]
+fn custodial_listing_delivers_from_custody() {
+    let s = setup();
+    s.approve(&s.issuer);
+    s.client.deposit_and_list(&s.issuer, &s.code, &100, &10);
+    assert_eq!(s.carbon.balance(&s.issuer), 900);
+    assert_eq!(s.client.custody_balance(&s.code, &s.issuer), 100);
+    assert_eq!(s.client.custodied(&s.code), 100);
+
+    let buyer = s.buyer(1_000);
+    s.buy(&buyer, &s.issuer, 40);
+    assert_eq!(s.carbon.balance(&buyer), 40);
+    assert_eq!(s.client.custody_balance(&s.code, &s.issuer), 60);
+    assert!(s
+        .client
+        .try_withdraw_inventory(&s.issuer, &s.code, &1)
+        .is_err());
+
+    s.client.cancel_listing(&s.issuer, &s.code);
+    s.client.withdraw_inventory(&s.issuer, &s.code, &60);
+    assert_eq!(s.carbon.balance(&s.issuer), 960);
+    assert_eq!(s.client.custody_balance(&s.code, &s.issuer), 0);
+    assert_eq!(s.client.custodied(&s.code), 0);
+}
+
+#[test]
+fn purchase_event_reports_remaining() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let buyer = s.buyer(1_000);
+    s.buy(&buyer, &s.issuer, 30);
+
+    let data = find_event(&s.e, "purchase_event").unwrap();
+    assert_eq!(field::<i128>(&s.e, &data, "remaining"), 70);
+    assert_eq!(field::<i128>(&s.e, &data, "cost_xml"), 300);
+    assert_eq!(s.listing(&s.issuer).unwrap().amount, 70);
+
+    s.buy(&buyer, &s.issuer, 70);
+    let data = find_event(&s.e, "purchase_event").unwrap();
+    assert_eq!(field::<i128>(&s.e, &data, "remaining"), 0);
+    assert!(s.listing(&s.issuer).is_none());
+}
+
+#[test]
+fn user_strings_are_length_capped() {
+    let s = setup();
+    let note = |len| text(&s.e, len);
+    let retire = |note: String, name: Option<String>, reference: Option<String>| {
+        s.client
+            .try_retire(&s.code, &s.issuer, &1, &note, &name, &reference, &None)
+    };
+
+    assert!(retire(note(257), None, None).is_err());
+    assert!(retire(note(1), Some(note(129)), None).is_err());
+    assert!(retire(note(1), None, Some(note(65))).is_err());
+    assert!(s
+        .client
+        .try_set_metadata_uri(&s.code, &s.admins(), &note(257))
+        .is_err());
+    assert!(s
+        .client
+        .try_mint_with_attestation(&s.code, &s.issuer, &1, &note(257))
+        .is_err());
+    assert!(s
+        .client
+        .try_close_asset(&s.code, &s.admins(), &note(257))
+        .is_err());
+
+    // At the limit is fine
+    assert!(retire(note(256), Some(note(128)), Some(note(64))).is_ok());
+    s.client.set_metadata_uri(&s.code, &s.admins(), &note(256));
+}
+
+#[test]
+#[should_panic(expected = "reason too long: 257 bytes, max 256")]
+fn admin_cancel_reason_is_length_capped() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    s.client
+        .admin_cancel_listing(&s.admin, &s.code, &s.issuer, &text(&s.e, 257));
+}
+
+#[test]
+fn mint_auth_is_bound_to_amount() {
+    let s = setup();
+    let issuer = Address::generate(&s.e);
+    let invoke = MockAuthInvoke {
+        contract: &s.client.address,
+        fn_name: "mint_to_issuer",
+        args: (s.code.clone(), issuer.clone(), 100_i128).into_val(&s.e),
+        sub_invokes: &[],
+    };
+    let auth = MockAuth {
+        address: &s.asset_admin,
+        invoke: &invoke,
+    };
+
+    s.e.mock_auths(core::slice::from_ref(&auth));
+    assert!(s.client.try_mint_to_issuer(&s.code, &issuer, &200).is_err());
+    assert_eq!(s.carbon.balance(&issuer), 0);
+
+    s.e.mock_auths(&[auth]);
+    s.client.mint_to_issuer(&s.code, &issuer, &100);
+    assert_eq!(s.carbon.balance(&issuer), 100);
+}
+
+#[test]
+fn basket_buys_each_component_by_weight() {
+    let s = setup();
+    let (code2, carbon2) = s.add_asset("ZORLU24", 2024);
+    let mix = Symbol::new(&s.e, "MIX");
+    s.client.define_basket(
+        &s.admin,
+        &mix,
+        &vec![
+            &s.e,
+            (s.code.clone(), 6_000_u32),
+            (code2.clone(), 4_000_u32),
+        ],
+    );
+    s.list(&s.issuer, 100, 10);
+    carbon2.approve(&s.issuer, &s.client.address, &1_000, &s.expiration());
+    s.client.list_asset(&s.issuer, &code2, &100, &20, &0);
+
+    let buyer = s.buyer(1_000);
+    assert!(s.client.try_buy_basket(&buyer, &mix, &10, &139).is_err());
+    assert_eq!(s.carbon.balance(&buyer), 0);
+    assert_eq!(carbon2.balance(&buyer), 0);
+
+    assert_eq!(s.client.buy_basket(&buyer, &mix, &10, &140), 140);
+    assert_eq!(s.carbon.balance(&buyer), 6);
+    assert_eq!(carbon2.balance(&buyer), 4);
+    assert_eq!(s.xml.balance(&buyer), 860);
+}
+
+#[test]
+#[should_panic(expected = "basket weights must sum to 10000 bps")]
+fn basket_weights_must_sum_to_whole() {
+    let s = setup();
+    s.client.define_basket(
+        &s.admin,
+        &Symbol::new(&s.e, "MIX"),
+        &vec![&s.e, (s.code.clone(), 9_000_u32)],
+    );
+}
+
+#[test]
+#[should_panic(expected = "xml allowance too low")]
+fn purchase_without_allowance_panics() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let buyer = Address::generate(&s.e);
+    s.xml_admin.mint(&buyer, &1_000);
+    s.buy(&buyer, &s.issuer, 10);
+}
+
+#[test]
+#[should_panic(expected = "xml balance insufficient")]
+fn purchase_without_balance_panics() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let buyer = s.buyer(50);
+    s.xml
+        .approve(&buyer, &s.client.address, &1_000, &s.expiration());
+    s.buy(&buyer, &s.issuer, 10);
+}
+
+#[test]
+#[should_panic(expected = "xml token frozen for buyer")]
+fn purchase_by_deauthorized_buyer_panics() {
+    let s = setup();
+    s.list(&s.issuer, 100, 10);
+    let buyer = s.buyer(1_000);
+    s.xml_admin.set_authorized(&buyer, &false);
+    s.buy(&buyer, &s.issuer, 10);
+}
+
+#[test]
+fn listing_activates_at_start_time() {
+    let s = setup();
+    s.approve(&s.issuer);
+    s.client.list_asset(&s.issuer, &s.code, &100, &10, &2_000);
+    assert!(!s.client.is_listing_active(&s.code, &s.issuer));
+    let buyer = s.buyer(1_000);
+    assert!(s
+        .client
+        .try_buy_with_xml(&buyer, &s.code, &s.issuer, &10, &100, &0, &None, &None)
+        .is_err());
+
+    s.e.ledger().set_timestamp(2_000);
+    assert!(s.client.is_listing_active(&s.code, &s.issuer));
+    s.buy(&buyer, &s.issuer, 10);
+    assert_eq!(s.carbon.balance(&buyer), 10);
+}
+
+#[test]
+fn attestation_is_recorded_and_published() {
+    let s = setup();
+    let doc = String::from_str(&s.e, "ipfs://issuance-0001");
+    let mint_id = s
+        .client
+        .mint_with_attestation(&s.code, &s.issuer, &50, &doc);
+
+    let mint_event = find_event(&s.e, "mint_event").unwrap();
+    assert_eq!(
+        field::<Option<String>>(&s.e, &mint_event, "attestation_ref"),
+        Some(doc.clone())
+    );
+    assert_eq!(s.client.mint_attestation(&mint_id).attestation_ref, doc);
+    assert_eq!(s.client.attestation_mint(&doc), Some(mint_id));
+    assert_eq!(s.carbon.balance(&s.issuer), 1_050);
+}
+
+#[test]
+#[should_panic(expected = "attestation already used")]
+fn attestation_cannot_be_reused() {
+    let s = setup();
+    let doc = String::from_str(&s.e, "ipfs://issuance-0001");
+    s.client
+        .mint_with_attestation(&s.code, &s.issuer, &50, &doc);
+    s.client
+        .mint_with_attestation(&s.code, &s.issuer, &50, &doc);
+}
+
+#[test]
+fn bps_sum_cap_covers_every_combination() {
+    // (global fee, asset fee, primary fee, conservation, accepted) under a 1500 cap
+    let cases = [
+        (1_000, None, None, 500, true),
+        (1_000, None, None, 501, false),
+        (1_000, Some(500), None, 1_000, true),
+        (300, Some(1_000), None, 500, true),
+        (300, Some(1_000), None, 501, false),
+        (0, None, Some(1_000), 500, true),
+        (0, None, Some(1_000), 501, false),
+        (900, Some(300), Some(800), 700, true),
+        (900, Some(300), Some(800), 701, false),
+    ];
+    for (fee, asset_fee, primary, conservation, accepted) in cases {
+        let s = setup();
+        s.client.set_max_bps_sum(&s.admin, &1_500);
+        let fund = Some(Address::generate(&s.e));
+        let applied = s
+            .client
+            .try_set_fee(&s.admin, &fee, &Address::generate(&s.e))
+            .is_ok()
+            && s.client
+                .try_set_asset_fee(&s.admin, &s.code, &asset_fee)
+                .is_ok()
+            && s.client.try_set_primary_fee(&s.admin, &primary).is_ok()
+            && s.client
+                .try_set_conservation(&s.code, &s.admins(), &conservation, &fund)
+                .is_ok();
+        assert_eq!(
+            applied, accepted,
+            "{fee} {asset_fee:?} {primary:?} {conservation}"
+        );
+    }
+}
+
+#[test]
+fn lowering_the_cap_below_configured_bps_is_rejected() {
+    let s = setup();
+    s.client.set_fee(&s.admin, &1_000, &Address::generate(&s.e));
+    s.client
+        .set_conservation(&s.code, &s.admins(), &500, &Some(Address::generate(&s.e)));
+    assert!(s.client.try_set_max_bps_sum(&s.admin, &1_499).is_err());
+    s.client.set_max_bps_sum(&s.admin, &1_500);
+    assert_eq!(s.client.max_bps_sum(), 1_500);
+}
+
+#[test]
+#[should_panic(expected = "conservation_bps takes fee + conservation")]
+fn bps_sum_panic_names_the_setting() {
+    let s = setup();
+    s.client.set_max_bps_sum(&s.admin, &1_500);
+    s.client.set_fee(&s.admin, &1_000, &Address::generate(&s.e));
+    s.client
+        .set_conservation(&s.code, &s.admins(), &1_000, &Some(Address::generate(&s.e)));
+}
+
+#[test]
+fn fee_split_adds_up_to_cost() {
+    let s = setup();
+    let collector = Address::generate(&s.e);
+    let fund = Address::generate(&s.e);
+    s.client.set_fee(&s.admin, &250, &collector);
+    s.client.set_primary_fee(&s.admin, &Some(250));
+    s.client
+        .set_conservation(&s.code, &s.admins(), &100, &Some(fund.clone()));
+    s.list(&s.issuer, 100, 100);
+    let buyer = s.buyer(10_000);
+    s.buy(&buyer, &s.issuer, 100);
+
+    assert_eq!(s.xml.balance(&collector), 250);
+    assert_eq!(s.xml.balance(&fund), 100);
+    assert_eq!(s.xml.balance(&s.issuer), 9_650);
+}
+
+#[test]
+fn limit_bid_below_ask_rests_until_cancelled() {
+    let s = setup();
+    s.list(&s.issuer, 100, 15);
+    let buyer = s.buyer(1_000);
+    s.client.place_limit_bid(&buyer, &s.code, &30, &10, &0);
+    assert_eq!(s.carbon.balance(&buyer), 0);
+    assert_eq!(s.client.limit_bids(&s.code).len(), 1);
+    assert_eq!(s.client.my_bids(&buyer).len(), 1);
+    assert_eq!(s.client.limit_bid(&s.code, &buyer).limit_price, 10);
+
+    s.client.cancel_limit_bid(&buyer, &s.code);
+    assert_eq!(s.client.limit_bids(&s.code).len(), 0);
+    assert_eq!(s.client.my_bids(&buyer).len(), 0);
+}
+
+#[test]
+fn limit_bid_fills_when_crossing_ask_is_listed() {
+    let s = setup();
+    let buyer = s.buyer(1_000);
+    s.client.place_limit_bid(&buyer, &s.code, &50, &20, &0);
+    assert_eq!(s.client.limit_bids(&s.code).len(), 1);
+
+    s.list(&s.issuer, 100, 15);
+    assert_eq!(s.carbon.balance(&buyer), 50);
+    assert_eq!(s.xml.balance(&buyer), 250);
+    assert_eq!(s.client.limit_bids(&s.code).len(), 0);
+    assert_eq!(s.listing(&s.issuer).unwrap().amount, 50);
+}
+
+#[test]
+fn limit_bid_fills_against_resting_ask() {
+    let s = setup();
+    s.list(&s.issuer, 100, 15);
+    let buyer = s.buyer(1_000);
+    s.client.place_limit_bid(&buyer, &s.code, &30, &20, &0);
+    assert_eq!(s.carbon.balance(&buyer), 30);
+    assert_eq!(s.listing(&s.issuer).unwrap().amount, 70);
+}
+
+#[test]
+fn underbacked_ask_does_not_block_matching() {
+    let s = setup();
+    s.list(&s.issuer, 100, 15);
+    s.carbon
+        .transfer(&s.issuer, Address::generate(&s.e), &1_000);
+
+    let buyer = s.buyer(1_000);
+    s.client.place_limit_bid(&buyer, &s.code, &30, &20, &0);
+    assert_eq!(s.carbon.balance(&buyer), 0);
+    assert_eq!(s.client.limit_bids(&s.code).len(), 1);
+}
+
+#[test]
+fn mul_div_properties() {
+    for (a, b, d) in operands(4_000) {
+        let floor = checked_mul_div(a, b, d);
+        assert_eq!(floor, checked_mul_div(b, a, d), "{a} * {b} / {d}");
+        let Some(floor) = floor else {
+            continue;
+        };
+        if let Some(product) = a.checked_mul(b) {
+            assert_eq!(floor, product / d);
+        }
+        let (_, inexact) = mul_div_parts(a, b, d).unwrap();
+        if let Some(ceil) = floor.checked_add(inexact as i128) {
+            assert_eq!(mul_div_ceil(a, b, d), ceil);
+        }
+        assert_eq!(mul_div(a, d, d), a);
+        if let Some(larger) = checked_mul_div(a.saturating_add(1), b, d) {
+            assert!(larger >= floor);
+        }
+    }
+}
+
+#[test]
+fn mul_div_is_exact_past_i128_products() {
+    assert_eq!(mul_div(7, 3, 2), 10);
+    assert_eq!(mul_div_ceil(7, 3, 2), 11);
+    assert_eq!(mul_div_ceil(8, 3, 2), 12);
+    assert_eq!(mul_div(i128::MAX, 2, 2), i128::MAX);
+    assert_eq!(mul_div(2, i128::MAX, 2), i128::MAX);
+    assert_eq!(mul_div(i128::MAX, 10_000, 10_000), i128::MAX);
+    assert_eq!(mul_div_ceil(i128::MAX, 1, 1), i128::MAX);
+    assert_eq!(checked_mul_div(i128::MAX, 3, 2), None);
+}
+
+#[test]
+#[should_panic(expected = "overflow in mul_div")]
+fn mul_div_ceil_overflow_panics() {
+    mul_div_ceil(i128::MAX, 3, 2);
+}
+
+#[test]
+#[should_panic(expected = "mul_div denominator must be positive")]
+fn mul_div_rejects_zero_denominator() {
+    mul_div(1, 1, 0);
+}
+
+#[test]
+fn register_vintages_registers_each_vintage() {
+    let s = setup();
+    let token = |_| {
+        s.e.register_stellar_asset_contract_v2(s.client.address.clone())
+            .address()
+    };
+    let (z21, z22) = (Symbol::new(&s.e, "ZORLU21"), Symbol::new(&s.e, "ZORLU22"));
+    s.client.register_vintages(
+        &s.asset_admin,
+        &7,
+        &Symbol::new(&s.e, "ZORLU"),
+        &vec![
+            &s.e,
+            (2021, z21.clone(), token(0), verra(&s.e)),
+            (2022, z22.clone(), token(1), verra(&s.e)),
+        ],
+    );
+    assert_eq!(
+        s.client.assets_for_project(&7, &0, &10),
+        vec![&s.e, z21, z22.clone()]
+    );
+    let info = s.client.asset_info(&z22);
+    assert_eq!((info.project_id, info.vintage_year), (7, 2022));
+}
+
+#[test]
+fn register_vintages_validates_the_whole_batch() {
+    let s = setup();
+    let token =
+        s.e.register_stellar_asset_contract_v2(s.client.address.clone())
+            .address();
+    let code = Symbol::new(&s.e, "ZORLU21");
+    let base = Symbol::new(&s.e, "ZORLU");
+    let duplicate = vec![
+        &s.e,
+        (2021, code.clone(), token.clone(), verra(&s.e)),
+        (2022, code.clone(), token.clone(), verra(&s.e)),
+    ];
+    assert!(s
+        .client
+        .try_register_vintages(&s.asset_admin, &7, &base, &duplicate)
+        .is_err());
+    let too_old = vec![&s.e, (1800, code.clone(), token, verra(&s.e))];
+    assert!(s
+        .client
+        .try_register_vintages(&s.asset_admin, &7, &base, &too_old)
+        .is_err());
+    assert!(s.client.assets_for_project(&7, &0, &10).is_empty());
+}
+
+#[test]
+fn frozen_holder_is_shut_out_until_unfrozen() {
+    let s = setup();
+    let buyer = s.buyer(1_000);
+    s.list(&s.issuer, 100, 10);
+    s.client.freeze_holder(&s.admin, &buyer);
+    assert!(s.client.is_holder_frozen(&buyer));
+    assert!(s
+        .client
+        .try_buy_with_xml(&buyer, &s.code, &s.issuer, &10, &100, &0, &None, &None)
+        .is_err());
+
+    s.client.unfreeze_holder(&s.admin, &buyer);
+    s.buy(&buyer, &s.issuer, 10);
+    assert_eq!(s.carbon.balance(&buyer), 10);
+}
+
+#[test]
+#[should_panic(expected = "holder frozen")]
+fn frozen_holder_cannot_list() {
+    let s = setup();
+    s.client.freeze_holder(&s.admin, &s.issuer);
+    s.list(&s.issuer, 100, 10);
+}
+
+#[test]
+#[should_panic(expected = "holder frozen")]
+fn frozen_holder_cannot_retire() {
+    let s = setup();
+    s.client.freeze_holder(&s.admin, &s.issuer);
+    s.retire(&s.issuer, 10);
+}
+
+#[test]
+fn price_feed_tracks_vwap_and_volume() {
+    let s = setup();
+    let buyer = s.buyer(10_000);
+    s.list(&s.issuer, 10, 10);
+    s.buy(&buyer, &s.issuer, 10);
+    let data = find_event(&s.e, "price_feed_event").unwrap();
+    assert_eq!(field::<i128>(&s.e, &data, "last_price"), 10);
+    assert_eq!(field::<i128>(&s.e, &data, "vwap"), 10);
+    assert_eq!(field::<i128>(&s.e, &data, "volume"), 10);
+
+    s.list(&s.issuer, 10, 20);
+    s.buy(&buyer, &s.issuer, 10);
+    let data = find_event(&s.e, "price_feed_event").unwrap();
+    assert_eq!(field::<i128>(&s.e, &data, "last_price"), 20);
+    assert_eq!(field::<i128>(&s.e, &data, "vwap"), 15);
+    assert_eq!(field::<i128>(&s.e, &data, "volume"), 20);
+}
+
+#[test]
+fn expiring_role_lapses_on_its_own() {
+    let s = setup();
+    let pauser = Address::generate(&s.e);
+    let role = Symbol::new(&s.e, "PAUSER");
+    s.e.ledger().set_timestamp(100);
+    s.client.grant_role(&s.admin, &role, &pauser, &Some(500));
+    assert!(s.client.has_role(&role, &pauser));
+    assert_eq!(s.client.role_expiry(&role, &pauser), 500);
+    s.client.pause(&pauser, &0);
+    s.client.unpause(&pauser);
+
+    s.e.ledger().set_timestamp(500);
+    assert!(s.client.has_role(&role, &pauser));
+    s.e.ledger().set_timestamp(501);
+    assert!(!s.client.has_role(&role, &pauser));
+    assert!(s.client.try_pause(&pauser, &0).is_err());
+}
+
+#[test]
+fn permanent_role_lasts_until_revoked() {
+    let s = setup();
+    let officer = Address::generate(&s.e);
+    let role = Symbol::new(&s.e, "COMPLIANCE");
+    s.client.grant_role(&s.admin, &role, &officer, &None);
+    s.e.ledger().set_timestamp(u64::MAX / 2);
+    assert!(s.client.has_role(&role, &officer));
+    s.client.freeze_holder(&officer, &s.issuer);
+
+    s.client.revoke_role(&s.admin, &role, &officer);
+    assert!(!s.client.has_role(&role, &officer));
+    assert!(s.client.try_unfreeze_holder(&officer, &s.issuer).is_err());
+}
+
+#[test]
+fn only_admins_grant_roles() {
+    let s = setup();
+    let outsider = Address::generate(&s.e);
+    let role = Symbol::new(&s.e, "ADMIN");
+    assert!(s
+        .client
+        .try_grant_role(&outsider, &role, &outsider, &None)
+        .is_err());
+    assert!(s
+        .client
+        .try_grant_role(&s.admin, &Symbol::new(&s.e, "OWNER"), &outsider, &None)
+        .is_err());
+}
+
+#[test]
+fn minted_credits_unlock_after_lockup() {
+    let s = setup();
+    s.client.set_mint_lockup(&s.code, &s.admins(), &1_000);
+    let fresh = Address::generate(&s.e);
+    s.e.ledger().set_timestamp(100);
+    s.client.mint_to_issuer(&s.code, &fresh, &100);
+    s.approve(&fresh);
+    assert!(s
+        .client
+        .try_list_asset(&fresh, &s.code, &100, &10, &0)
+        .is_err());
+
+    s.e.ledger().set_timestamp(1_100);
+    s.list(&fresh, 100, 10);
+    assert_eq!(s.listing(&fresh).unwrap().amount, 100);
+}
+
+#[test]
+#[should_panic(expected = "minted credits locked until")]
+fn auction_respects_mint_lockup() {
+    let s = setup();
+    s.client.set_mint_lockup(&s.code, &s.admins(), &1_000);
+    let fresh = Address::generate(&s.e);
+    s.client.mint_to_issuer(&s.code, &fresh, &100);
+    s.client
+        .create_english_auction(&fresh, &s.code, &100, &50, &200);
+}
+
+#[test]
+fn index_pages_read_back_in_order() {
+    let s = setup();
+    let index = Index::CertsByVintage(9, 2030);
+    s.e.as_contract(&s.client.address, || {
+        for id in 0..250_u64 {
+            assert_eq!(index_push(&s.e, &index, id), id as u32);
+        }
+        assert_eq!(index_len(&s.e, &index), 250);
+        assert_eq!(read_index_page::<u64>(&s.e, &index, 2).len(), 50);
+
+        let across: Vec<u64> = index_range(&s.e, &index, 90, 20);
+        assert_eq!(across.first(), Some(90));
+        assert_eq!(across.last(), Some(109));
+        assert_eq!(across.len(), 20);
+        let capped: Vec<u64> = index_range(&s.e, &index, 0, 500);
+        assert_eq!(capped.len(), MAX_BATCH_SIZE);
+        let tail: Vec<u64> = index_range(&s.e, &index, 240, 50);
+        assert_eq!(tail.len(), 10);
+        assert!(index_range::<u64>(&s.e, &index, 250, 10).is_empty());
+    });
+}
+
+#[test]
+fn certificates_for_vintage_pages_by_vintage() {
+    let s = setup();
+    let (code2, _) = s.add_asset("ZORLU24", 2024);
+    let mut ids = Vec::new(&s.e);
+    for _ in 0..3 {
+        ids.push_back(s.retire(&s.issuer, 1));
+    }
+    s.client.retire(
+        &code2,
+        &s.issuer,
+        &1,
+        &String::from_str(&s.e, "offset"),
+        &None,
+        &None,
+        &None,
+    );
+
+    assert_eq!(s.client.certificates_for_vintage(&1, &2023, &0, &10), ids);
+    assert_eq!(
+        s.client.certificates_for_vintage(&1, &2023, &1, &1),
+        vec![&s.e, ids.get(1).unwrap()]
+    );
+    assert_eq!(
+        s.client.certificates_for_vintage(&1, &2024, &0, &10).len(),
+        1
+    );
+    assert!(s
+        .client
+        .certificates_for_vintage(&1, &2022, &0, &10)
+        .is_empty());
+}
+
+#[test]
+fn certificates_in_day_buckets_by_utc_day() {
+    let s = setup();
+    s.e.ledger().set_timestamp(86_399);
+    let late = s.retire(&s.issuer, 1);
+    s.e.ledger().set_timestamp(86_400);
+    let mut next_day = Vec::new(&s.e);
+    for _ in 0..3 {
+        next_day.push_back(s.retire(&s.issuer, 1));
+    }
+
+    assert_eq!(s.client.certificates_in_day(&0, &0, &10), vec![&s.e, late]);
+    assert_eq!(s.client.certificates_in_day(&1, &0, &10), next_day);
+    assert_eq!(
+        s.client.certificates_in_day(&1, &2, &10),
+        vec![&s.e, next_day.get(2).unwrap()]
+    );
+    assert!(s.client.certificates_in_day(&2, &0, &10).is_empty());
+}
+
+#[test]
+fn index_swap_remove_fills_the_gap_from_the_tail() {
+    let s = setup();
+    let index = Index::CertsByDay(7);
+    s.e.as_contract(&s.client.address, || {
+        for id in 0..150_u64 {
+            index_push(&s.e, &index, id);
+        }
+        assert_eq!(index_swap_remove::<u64>(&s.e, &index, 3), Some(149));
+        assert_eq!(index_len(&s.e, &index), 149);
+        assert_eq!(index_range::<u64>(&s.e, &index, 3, 1).first(), Some(149));
+        assert_eq!(index_swap_remove::<u64>(&s.e, &index, 148), None);
+
+        for _ in 0..48 {
+            index_swap_remove::<u64>(&s.e, &index, 0);
+        }
+        assert_eq!(index_len(&s.e, &index), 100);
+        assert!(read_index_page::<u64>(&s.e, &index, 1).is_empty());
+        for _ in 0..100 {
+            index_swap_remove::<u64>(&s.e, &index, 0);
+        }
+        assert_eq!(index_len(&s.e, &index), 0);
+        assert!(index_range::<u64>(&s.e, &index, 0, 10).is_empty());
+    });
+}
+
+#[test]
+fn transfer_certificate_moves_it_between_owner_indexes() {
+    let s = setup();
+    let other = Address::generate(&s.e);
+    let ids: Vec<u64> = vec![
+        &s.e,
+        s.retire(&s.issuer, 1),
+        s.retire(&s.issuer, 1),
+        s.retire(&s.issuer, 1),
+    ];
+    assert_eq!(s.client.certificates_of(&s.issuer, &0, &10), ids);
+
+    s.client
+        .transfer_certificate(&s.issuer, &other, &ids.get(0).unwrap());
+    assert_eq!(
+        s.client.certificates_of(&s.issuer, &0, &10),
+        vec![&s.e, ids.get(2).unwrap(), ids.get(1).unwrap()]
+    );
+    assert_eq!(
+        s.client.certificates_of(&other, &0, &10),
+        vec![&s.e, ids.get(0).unwrap()]
+    );
+
+    // The moved id's slot was updated, so it can still be transferred out.
+    s.client
+        .transfer_certificate(&s.issuer, &other, &ids.get(2).unwrap());
+    assert_eq!(
+        s.client.certificates_of(&s.issuer, &0, &10),
+        vec![&s.e, ids.get(1).unwrap()]
+    );
+    assert_eq!(s.client.certificates_of(&other, &1, &10).len(), 1);
+}
+
+#[test]
+fn policy_setters_follow_their_roles() {
+    let s = setup();
+    let fee_manager = Address::generate(&s.e);
+    let officer = Address::generate(&s.e);
+    s.client.grant_role(
+        &s.admin,
+        &Symbol::new(&s.e, "FEE_MANAGER"),
+        &fee_manager,
+        &None,
+    );
+    s.client
+        .grant_role(&s.admin, &Symbol::new(&s.e, "COMPLIANCE"), &officer, &None);
+
+    s.client.set_max_bps_sum(&fee_manager, &1_500);
+    assert_eq!(s.client.max_bps_sum(), 1_500);
+    assert!(s.client.try_set_max_bps_sum(&officer, &1_600).is_err());
+
+    s.client.set_retire_quorum(&officer, &500, &officer);
+    assert_eq!(s.client.retire_quorum(), Some((500, officer.clone())));
+    assert!(s
+        .client
+        .try_set_retire_quorum(&fee_manager, &0, &officer)
+        .is_err());
+
+    assert!(s.client.try_set_credit_fallback(&officer, &true).is_err());
+    s.client.set_credit_fallback(&s.admin, &true);
+    assert!(s.client.credit_fallback());
+}
+
+#[test]
+fn listing_sellers_live_in_persistent_storage() {
+    let s = setup();
+    let key = DataKey::ListingSellers(s.code.clone());
+    s.list(&s.issuer, 100, 10);
+    s.e.as_contract(&s.client.address, || {
+        assert!(s.e.storage().persistent().has(&key));
+        assert!(!s.e.storage().instance().has(&key));
+    });
+
+    s.client.cancel_listing(&s.issuer, &s.code);
+    s.e.as_contract(&s.client.address, || {
+        assert!(!s.e.storage().persistent().has(&key));
+    });
+}
+
+#[test]
+fn moving_an_asset_updates_both_project_indexes() {
+    let s = setup();
+    let (code2, token2) = s.add_asset("ZORLU24", 2024);
+    let (code3, _) = s.add_asset("ZORLU25", 2025);
+    assert_eq!(
+        s.client.assets_for_project(&1, &0, &10),
+        vec![&s.e, s.code.clone(), code2.clone(), code3.clone()]
+    );
+
+    s.client.register_asset(
+        &code2,
+        &2,
+        &2024,
+        &token2.address,
+        &s.asset_admin,
+        &verra(&s.e),
+    );
+    assert_eq!(
+        s.client.assets_for_project(&1, &0, &10),
+        vec![&s.e, s.code.clone(), code3.clone()]
+    );
+    assert_eq!(s.client.assets_for_project(&1, &1, &1), vec![&s.e, code3]);
+    assert_eq!(s.client.assets_for_project(&2, &0, &10), vec![&s.e, code2]);
+}
+
+#[test]
+fn retirement_leaderboard_ranks_a_registry_window() {
+    let s = setup();
+    let (code2, _) = s.add_asset("ZORLU24", 2024);
+    let (code3, _) = s.add_asset("ZORLU25", 2025);
+    s.retire(&s.issuer, 10);
+    let offset = String::from_str(&s.e, "offset");
+    s.client
+        .retire(&code2, &s.issuer, &30, &offset, &None, &None, &None);
+    s.client
+        .retire(&code3, &s.issuer, &20, &offset, &None, &None, &None);
+
+    assert_eq!(
+        s.client.top_assets_by_retirement(&0, &10),
+        vec![
+            &s.e,
+            (code2.clone(), 30),
+            (code3.clone(), 20),
+            (s.code.clone(), 10)
+        ]
+    );
+    assert_eq!(
+        s.client.top_assets_by_retirement(&0, &2),
+        vec![&s.e, (code2, 30), (s.code.clone(), 10)]
+    );
+    assert_eq!(
+        s.client.top_assets_by_retirement(&2, &10),
+        vec![&s.e, (code3, 20)]
+    );
+}
+
+#[test]
+fn bid_the_carbon_token_cannot_credit_does_not_block_listing() {
+    let s = setup();
+    let sac =
+        s.e.register_stellar_asset_contract_v2(s.client.address.clone());
+    sac.issuer().set_flag(IssuerFlags::RevocableFlag);
+    let code = Symbol::new(&s.e, "ZORLU24");
+    s.client.register_asset(
+        &code,
+        &1,
+        &2024,
+        &sac.address(),
+        &s.asset_admin,
+        &verra(&s.e),
+    );
+    s.client.mint_to_issuer(&code, &s.issuer, &1_000);
+
+    let buyer = s.buyer(1_000);
+    s.client.place_limit_bid(&buyer, &code, &30, &20, &0);
+    StellarAssetClient::new(&s.e, &sac.address()).set_authorized(&buyer, &false);
+
+    TokenClient::new(&s.e, &sac.address()).approve(
+        &s.issuer,
+        &s.client.address,
+        &1_000,
+        &s.expiration(),
+    );
+    s.client.list_asset(&s.issuer, &code, &100, &15, &0);
+    assert_eq!(s.client.limit_bids(&code).len(), 1);
+    assert_eq!(s.xml.balance(&buyer), 1_000);
+}
+
+#[test]
+fn preview_retire_flags_the_quorum_co_sign() {
+    let s = setup();
+    let officer = Address::generate(&s.e);
+    assert!(s
+        .client
+        .preview_retire(&s.code, &s.issuer, &600)
+        .warnings
+        .is_empty());
+
+    s.client.set_retire_quorum(&s.admin, &500, &officer);
+    assert!(s
+        .client
+        .preview_retire(&s.code, &s.issuer, &500)
+        .warnings
+        .is_empty());
+    assert_eq!(
+        s.client.preview_retire(&s.code, &s.issuer, &600).warnings,
+        vec![&s.e, Symbol::new(&s.e, "QUORUM")]
+    );
+}
+
+#[test]
+fn best_effort_batch_skips_what_would_revert() {
+    let s = setup();
+    s.client
+        .set_retire_quorum(&s.admin, &500, &Address::generate(&s.e));
+    s.client.set_min_retire_amount(&s.code, &s.admins(), &10);
+    let unknown = Symbol::new(&s.e, "NOPE");
+    let items = vec![
+        &s.e,
+        (s.code.clone(), 600),
+        (unknown.clone(), 100),
+        (s.code.clone(), 5),
+        (s.code.clone(), 100),
+    ];
+    let results =
+        s.client
+            .retire_batch_best_effort(&s.issuer, &items, &String::from_str(&s.e, "offset"));
+    assert_eq!(
+        results,
+        vec![
+            &s.e,
+            (s.code.clone(), false),
+            (unknown, false),
+            (s.code.clone(), false),
+            (s.code.clone(), true)
+        ]
+    );
+    assert_eq!(s.carbon.balance(&s.issuer), 900);
+    assert_eq!(s.client.certificates_of(&s.issuer, &0, &10).len(), 1);
+}
+
+#[test]
+fn retire_project_takes_oldest_vintages_first() {
+    let s = setup();
+    let (old, old_token) = s.add_asset("ZORLU22", 2022);
+    let ids = s
+        .client
+        .retire_project(&s.issuer, &1, &1_200, &String::from_str(&s.e, "offset"));
+    assert_eq!(ids.len(), 2);
+    assert_eq!(
+        s.client.get_certificate(&ids.get(0).unwrap()).asset_code,
+        old
+    );
+    assert_eq!(old_token.balance(&s.issuer), 0);
+    assert_eq!(s.carbon.balance(&s.issuer), 800);
+    assert!(s
+        .client
+        .try_retire_project(&s.issuer, &1, &801, &String::from_str(&s.e, "offset"))
+        .is_err());
+}
+
+#[test]
+fn retire_project_skips_vintages_that_would_revert() {
+    let s = setup();
+    let (frozen, frozen_token) = s.add_asset("ZORLU21", 2021);
+    let (small, small_token) = s.add_asset("ZORLU22", 2022);
+    s.client
+        .set_asset_frozen(&s.admin, &frozen, &true, &String::from_str(&s.e, "hold"));
+    s.client.set_min_retire_amount(&small, &s.admins(), &100);
+    small_token.transfer(&s.issuer, Address::generate(&s.e), &950);
+
+    s.client
+        .retire_project(&s.issuer, &1, &300, &String::from_str(&s.e, "offset"));
+    assert_eq!(frozen_token.balance(&s.issuer), 1_000);
+    assert_eq!(small_token.balance(&s.issuer), 50);
+    assert_eq!(s.carbon.balance(&s.issuer), 700);
+}
+
+#[test]
+fn listing_guard_uses_the_scaled_cost() {
+    let s = setup();
+    let huge = 10_i128.pow(36);
+    s.client.mint_to_issuer(&s.code, &s.issuer, &huge);
+    s.carbon
+        .approve(&s.issuer, &s.client.address, &i128::MAX, &s.expiration());
+    assert!(s
+        .client
+        .try_list_asset(&s.issuer, &s.code, &huge, &10_000, &0)
+        .is_err());
+
+    // amount * price overflows i128, the cost after scaling doesn't
+    s.client.set_price_scale(&s.admin, &2);
+    s.client.list_asset(&s.issuer, &s.code, &huge, &10_000, &0);
+    assert!(s
+        .client
+        .try_list_asset(&s.issuer, &s.code, &huge, &10_i128.pow(6), &0)
+        .is_err());
+}
+
+#[test]
+fn retirement_records_get_the_persistent_ttl() {
+    let s = setup();
+    let id = s.retire(&s.issuer, 10);
+    s.e.as_contract(&s.client.address, || {
+        let storage = s.e.storage().persistent();
+        assert_eq!(storage.get_ttl(&DataKey::Certificate(id)), PERSISTENT_TTL);
+        assert_eq!(
+            storage.get_ttl(&ExtKey::TotalRetiredBy(s.issuer.clone())),
+            PERSISTENT_TTL
+        );
+    });
+}
+
+#[test]
+fn asset_settings_need_the_admin_quorum() {
+    let s = setup();
+    let second = Address::generate(&s.e);
+    let both = vec![&s.e, s.asset_admin.clone(), second.clone()];
+    s.client.set_asset_admins(&s.code, &s.admins(), &both, &2);
+
+    let region = Symbol::new(&s.e, "TR");
+    assert!(s
+        .client
+        .try_set_region(&s.code, &s.admins(), &region)
+        .is_err());
+    assert!(s
+        .client
+        .try_set_min_retire_amount(&s.code, &vec![&s.e, second], &10)
+        .is_err());
+    s.client.set_region(&s.code, &both, &region);
+    assert_eq!(s.client.asset_info(&s.code).region, region);
+
+    // Re-registering under another admin would sidestep the set
+    let token = s.client.asset_info(&s.code).token;
+    assert!(s
+        .client
+        .try_register_asset(
+            &s.code,
+            &1,
+            &2023,
+            &token,
+            &Address::generate(&s.e),
+            &verra(&s.e)
+        )
+        .is_err());
+}
+
+#[test]
+fn provenance_is_validated_and_fixed_at_registration() {
+    let s = setup();
+    let sac =
+        s.e.register_stellar_asset_contract_v2(s.client.address.clone());
+    let code = Symbol::new(&s.e, "ZORLU24");
+    let register = |provenance: &Provenance| {
+        s.client
+            .try_register_asset(&code, &1, &2024, &sac.address(), &s.asset_admin, provenance)
+            .is_ok()
+    };
+    let backwards = Provenance {
+        serial_start: 10,
+        serial_end: 9,
+        ..verra(&s.e)
+    };
+    let unnamed = Provenance {
+        standard: Symbol::new(&s.e, ""),
+        ..verra(&s.e)
+    };
+    assert!(!register(&backwards));
+    assert!(!register(&unnamed));
+
+    let single = Provenance {
+        serial_start: 5,
+        serial_end: 5,
+        ..verra(&s.e)
+    };
+    assert!(register(&single));
+    assert_eq!(s.client.asset_info(&code).serial_start, 5);
+    assert!(!register(&verra(&s.e)));
+    assert!(register(&single));
+}
+
+#[test]
+fn retirement_lock_is_set_at_registration_and_only_extends() {
+    let s = setup();
+    let sac =
+        s.e.register_stellar_asset_contract_v2(s.client.address.clone());
+    let code = Symbol::new(&s.e, "ZORLU24");
+    let locked = |until: u64| Provenance {
+        retire_not_before: until,
+        ..verra(&s.e)
+    };
+    s.client.register_asset(
+        &code,
+        &1,
+        &2024,
+        &sac.address(),
+        &s.asset_admin,
+        &locked(1_000),
+    );
+    s.client.mint_to_issuer(&code, &s.issuer, &100);
+    assert_eq!(s.client.asset_info(&code).retire_not_before, 1_000);
+
+    let offset = String::from_str(&s.e, "offset");
+    s.e.ledger().set_timestamp(999);
+    assert!(s
+        .client
+        .try_retire(&code, &s.issuer, &10, &offset, &None, &None, &None)
+        .is_err());
+
+    assert!(s
+        .client
+        .try_set_retire_not_before(&code, &s.admins(), &0)
+        .is_err());
+    assert!(s
+        .client
+        .try_register_asset(
+            &code,
+            &1,
+            &2024,
+            &sac.address(),
+            &s.asset_admin,
+            &locked(500)
+        )
+        .is_err());
+    s.client.set_retire_not_before(&code, &s.admins(), &2_000);
+
+    s.e.ledger().set_timestamp(2_000);
+    s.client
+        .retire(&code, &s.issuer, &10, &offset, &None, &None, &None);
+}